@@ -0,0 +1,92 @@
+// Records metadata of the build (reported by cbuildrt --version and cbuildrt version).
+
+use std::process::Command;
+
+// Shared with the library such that it can be unit tested there.
+#[path = "src/civil.rs"]
+mod civil;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    let commit = match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(commit) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit
+            }
+        }
+        // E.g., when building from a source tarball.
+        None => "unknown".to_string(),
+    };
+
+    // Honor SOURCE_DATE_EPOCH such that the binary can be built reproducibly.
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        });
+    let (year, month, day) = civil::civil_from_days(epoch.div_euclid(86400));
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=CBUILDRT_GIT_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=CBUILDRT_BUILD_DATE={:04}-{:02}-{:02}",
+        year, month, day
+    );
+    println!("cargo:rustc-env=CBUILDRT_FEATURES={}", features.join(","));
+    // For humans, who would otherwise see an empty list.
+    println!(
+        "cargo:rustc-env=CBUILDRT_FEATURES_DISPLAY={}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+    println!(
+        "cargo:rustc-env=CBUILDRT_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+    println!(
+        "cargo:rustc-env=CBUILDRT_PROFILE={}",
+        std::env::var("PROFILE").unwrap()
+    );
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // The commit changes with HEAD (when switching branches) or with the ref that HEAD
+    // points to (when committing); refs may also be moved into packed-refs. The index
+    // changes when the tree becomes dirty. Without a repository, there is nothing to watch.
+    // Cargo reruns the script on every build if a watched file is missing, hence skip those.
+    let mut watched = vec![
+        "HEAD".to_string(),
+        "index".to_string(),
+        "packed-refs".to_string(),
+    ];
+    watched.extend(git(&["symbolic-ref", "-q", "HEAD"]));
+    for name in watched {
+        let path = git(&["rev-parse", "--git-path", &name]);
+        if let Some(path) = path.filter(|p| std::path::Path::new(p).exists()) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+#ifndef CBUILDRT_H
+#define CBUILDRT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+struct cbuildrt_result {
+	// Exit code of the process (if cbuildrt_run() returns 0).
+	int exit_code;
+	// Error message (if cbuildrt_run() returns -1).
+	char *error;
+};
+
+// Runs a sandbox that is described by a cbuild.json document.
+// Returns 0 if the process ran (even if it failed) and -1 if the sandbox could not be run.
+// The result must be released using cbuildrt_result_free().
+int cbuildrt_run(const char *config_json, struct cbuildrt_result *result_out);
+
+void cbuildrt_result_free(struct cbuildrt_result *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // CBUILDRT_H
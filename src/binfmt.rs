@@ -0,0 +1,258 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const BINFMT_MISC: &str = "/proc/sys/fs/binfmt_misc";
+
+// Length of the ELF header prefix that identifies the architecture (up to and including e_machine).
+const IDENT_LEN: usize = 20;
+
+// A handler registered with binfmt_misc.
+struct Handler {
+    name: String,
+    interpreter: PathBuf,
+    // The F flag makes the kernel open the interpreter at registration time,
+    // such that it does not need to exist inside the rootfs.
+    fix_binary: bool,
+    magic: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+impl Handler {
+    fn matches(&self, ident: &[u8]) -> bool {
+        self.magic.len() <= ident.len()
+            && self
+                .magic
+                .iter()
+                .enumerate()
+                .all(|(i, m)| ident[i] & self.mask.get(i).copied().unwrap_or(0xff) == *m)
+    }
+}
+
+fn parse_hex(s: &str) -> Vec<u8> {
+    (0..s.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok())
+        .collect()
+}
+
+// Parses the enabled magic-based handlers (with offset zero).
+fn handlers() -> Vec<Handler> {
+    let entries = match std::fs::read_dir(BINFMT_MISC) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut result = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == "register" || name == "status" {
+            continue;
+        }
+        let content = match std::fs::read_to_string(entry.path()) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let mut enabled = false;
+        let mut handler = Handler {
+            name,
+            interpreter: PathBuf::new(),
+            fix_binary: false,
+            magic: Vec::new(),
+            mask: Vec::new(),
+        };
+        let mut offset = 0;
+        for line in content.lines() {
+            let (key, value) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let value = value.trim();
+            match key {
+                "enabled" => enabled = true,
+                "interpreter" => handler.interpreter = PathBuf::from(value),
+                "flags:" => handler.fix_binary = value.contains('F'),
+                "offset" => offset = value.parse().unwrap_or(-1),
+                "magic" => handler.magic = parse_hex(value),
+                "mask" => handler.mask = parse_hex(value),
+                _ => (),
+            }
+        }
+        if enabled && offset == 0 && !handler.magic.is_empty() {
+            result.push(handler);
+        }
+    }
+    result
+}
+
+// Reads the ELF identification of a binary.
+fn read_ident(path: &Path) -> Option<[u8; IDENT_LEN]> {
+    let mut ident = [0; IDENT_LEN];
+    std::fs::File::open(path)
+        .ok()?
+        .read_exact(&mut ident)
+        .ok()?;
+    if &ident[..4] != b"\x7fELF" {
+        return None;
+    }
+    Some(ident)
+}
+
+// Returns the (class, byte order, machine) triple of an ELF identification.
+fn architecture(ident: &[u8; IDENT_LEN]) -> (u8, u8, u16) {
+    let machine = if ident[5] == 2 {
+        u16::from_be_bytes([ident[18], ident[19]])
+    } else {
+        u16::from_le_bytes([ident[18], ident[19]])
+    };
+    (ident[4], ident[5], machine)
+}
+
+fn architecture_name(ident: &[u8; IDENT_LEN]) -> String {
+    match architecture(ident) {
+        (_, _, 3) => "i386".to_string(),
+        (_, _, 62) => "x86_64".to_string(),
+        (_, _, 40) => "arm".to_string(),
+        (_, _, 183) => "aarch64".to_string(),
+        (_, _, 243) => "riscv64".to_string(),
+        (_, 1, 21) => "ppc64le".to_string(),
+        (_, _, 21) => "ppc64".to_string(),
+        (_, _, 22) => "s390x".to_string(),
+        (_, _, machine) => format!("elf-machine-{}", machine),
+    }
+}
+
+// Identification of the rootfs (determined from its /bin/sh) if it differs from ours.
+fn foreign_ident(rootfs: &Path) -> Option<[u8; IDENT_LEN]> {
+    let rootfs_ident = read_ident(&crate::concat_absolute(rootfs, "/bin/sh"))?;
+    let own_ident = read_ident(Path::new("/proc/self/exe"))?;
+    if architecture(&rootfs_ident) == architecture(&own_ident) {
+        return None;
+    }
+    Some(rootfs_ident)
+}
+
+// Registers a handler for the architecture of ident that uses the given (static) qemu-user.
+fn register(ident: &[u8; IDENT_LEN], qemu: &Path) -> std::io::Result<()> {
+    // Match the ELF identification (except for the OS ABI) of executables and shared objects.
+    let mut magic = ident.to_vec();
+    let mut mask = vec![0xff; IDENT_LEN];
+    magic[7..16].iter_mut().for_each(|b| *b = 0);
+    mask[7] = 0;
+    // e_type is ET_EXEC; the mask also accepts ET_DYN.
+    if ident[5] == 2 {
+        magic[16..18].copy_from_slice(&[0x00, 0x02]);
+        mask[16..18].copy_from_slice(&[0xff, 0xfe]);
+    } else {
+        magic[16..18].copy_from_slice(&[0x02, 0x00]);
+        mask[16..18].copy_from_slice(&[0xfe, 0xff]);
+    }
+
+    let escape =
+        |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("\\x{:02x}", b)).collect() };
+    let rule = format!(
+        ":cbuildrt-{}:M::{}:{}:{}:F",
+        architecture_name(ident),
+        escape(&magic),
+        escape(&mask),
+        qemu.display()
+    );
+    std::fs::write(Path::new(BINFMT_MISC).join("register"), rule)
+}
+
+// Whether mount_interpreter() can create a mount point for qemu-user at the (missing)
+// interpreter path, i.e., if the rootfs is writable or the path is on the tmpfs of /run or /tmp.
+fn can_create_interpreter(interpreter: &Path, rootfs_writable: bool) -> bool {
+    rootfs_writable || interpreter.starts_with("/run") || interpreter.starts_with("/tmp")
+}
+
+// Ensures that binaries of a foreign-architecture rootfs can be executed.
+// Registers a handler if none exists and we are privileged. Must run on the host
+// (i.e., before entering the user namespace). On failure, returns a diagnostic message.
+pub fn prepare(rootfs: &Path, qemu: Option<&Path>, rootfs_writable: bool) -> Result<(), String> {
+    let ident = match foreign_ident(rootfs) {
+        Some(ident) => ident,
+        None => return Ok(()),
+    };
+    let arch = architecture_name(&ident);
+    match handlers().into_iter().find(|h| h.matches(&ident)) {
+        Some(h) if !h.fix_binary && crate::concat_absolute(rootfs, &h.interpreter).is_file() => {
+            Ok(())
+        }
+        Some(h) if !h.fix_binary && qemu.is_none() => Err(format!(
+            "binfmt_misc handler {} for {} uses interpreter {}, which is missing from the \
+            rootfs; install it into the rootfs or set qemuUser to a static qemu-{} binary",
+            h.name,
+            arch,
+            h.interpreter.display(),
+            arch
+        )),
+        Some(h) if !h.fix_binary && !can_create_interpreter(&h.interpreter, rootfs_writable) => {
+            Err(format!(
+                "binfmt_misc handler {} for {} uses interpreter {}, which is missing from the \
+                rootfs; qemuUser can only be mounted there if rootfsWritable is set (or the \
+                handler is registered with the F flag)",
+                h.name,
+                arch,
+                h.interpreter.display()
+            ))
+        }
+        Some(_) => Ok(()),
+        None => match qemu {
+            Some(qemu) if nix::unistd::geteuid().is_root() => register(&ident, qemu)
+                .map_err(|e| format!("failed to register binfmt_misc handler for {}: {}", arch, e)),
+            _ => Err(format!(
+                "rootfs is built for {} but no binfmt_misc handler is registered; install \
+                qemu-user-static with binfmt support or run as root with qemuUser set",
+                arch
+            )),
+        },
+    }
+}
+
+// Bind mounts the static qemu-user to the interpreter path of the handler,
+// if the handler needs the interpreter to exist inside the rootfs.
+// Must be called after /run has been mounted.
+pub fn mount_interpreter(rootfs: &Path, qemu: &Path) -> Result<(), crate::Error> {
+    let ident = match foreign_ident(rootfs) {
+        Some(ident) => ident,
+        None => return Ok(()),
+    };
+    match handlers().into_iter().find(|h| h.matches(&ident)) {
+        Some(h) if !h.fix_binary => crate::bind_into_sandbox(rootfs, qemu, &h.interpreter, true),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler(magic: &str, mask: &str) -> Handler {
+        Handler {
+            name: "test".to_string(),
+            interpreter: PathBuf::new(),
+            fix_binary: false,
+            magic: parse_hex(magic),
+            mask: parse_hex(mask),
+        }
+    }
+
+    #[test]
+    fn parse_hex_bytes() {
+        assert_eq!(parse_hex("7f454c46"), vec![0x7f, 0x45, 0x4c, 0x46]);
+        assert_eq!(parse_hex("FFfe"), vec![0xff, 0xfe]);
+        assert_eq!(parse_hex(""), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn matches_masked_magic() {
+        let h = handler("7f454c460202", "fffffffffffe");
+        assert!(h.matches(&[0x7f, 0x45, 0x4c, 0x46, 0x02, 0x02, 0x00]));
+        assert!(h.matches(&[0x7f, 0x45, 0x4c, 0x46, 0x02, 0x03]));
+        assert!(!h.matches(&[0x7f, 0x45, 0x4c, 0x46, 0x02, 0x01]));
+        // The identification is shorter than the magic.
+        assert!(!h.matches(&[0x7f, 0x45, 0x4c]));
+    }
+
+    #[test]
+    fn matches_without_mask() {
+        let h = handler("7f454c46", "");
+        assert!(h.matches(&[0x7f, 0x45, 0x4c, 0x46]));
+        assert!(!h.matches(&[0x7f, 0x45, 0x4c, 0x47]));
+    }
+}
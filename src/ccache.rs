@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// Location of the ccache directory inside the sandbox.
+// This is on the /run tmpfs such that the mount point can always be created.
+pub const SANDBOX_DIR: &str = "/run/ccache";
+
+// Bind mounts the host cache directory to SANDBOX_DIR.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, host_dir: &Path) -> Result<(), crate::Error> {
+    crate::bind_into_sandbox(rootfs, host_dir, SANDBOX_DIR, false)
+}
+
+// Snapshot of the counters reported by ccache --print-stats.
+pub struct Stats {
+    counters: HashMap<String, u64>,
+}
+
+impl Stats {
+    // Runs ccache inside the sandbox to obtain its counters.
+    // Returns None if ccache is not available or too old to support --print-stats.
+    pub fn collect(rootfs: &Path) -> Option<Stats> {
+        let output = crate::sandbox_command(rootfs, "ccache")
+            .arg("--print-stats")
+            .env("CCACHE_DIR", SANDBOX_DIR)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(Stats::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    // Parses the tab-separated "key value" lines of ccache --print-stats.
+    fn parse(text: &str) -> Stats {
+        let counters = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let key = fields.next()?;
+                let value = fields.next()?.parse().ok()?;
+                Some((key.to_string(), value))
+            })
+            .collect();
+        Stats { counters }
+    }
+
+    fn get(&self, key: &str) -> u64 {
+        self.counters.get(key).copied().unwrap_or(0)
+    }
+
+    // Increase of a counter since the before snapshot was taken.
+    fn delta(&self, before: &Stats, key: &str) -> u64 {
+        self.get(key).saturating_sub(before.get(key))
+    }
+
+    // Prints the hits and misses that happened since the before snapshot was taken.
+    // Note that concurrent users of the same cache are included in these numbers.
+    pub fn report_since(&self, before: &Stats) {
+        let delta = |key: &str| self.delta(before, key);
+        log!(
+            "ccache: {} direct hits, {} preprocessed hits, {} misses",
+            delta("direct_cache_hit"),
+            delta("preprocessed_cache_hit"),
+            delta("cache_miss")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_print_stats() {
+        let stats = Stats::parse(
+            "stats_updated_timestamp\t1700000000\n\
+             direct_cache_hit\t12\n\
+             cache_miss\t3\n\
+             malformed line\n\
+             preprocessed_cache_hit\tnot-a-number\n",
+        );
+        assert_eq!(stats.get("direct_cache_hit"), 12);
+        assert_eq!(stats.get("cache_miss"), 3);
+        assert_eq!(stats.get("preprocessed_cache_hit"), 0);
+        assert_eq!(stats.get("missing"), 0);
+    }
+
+    #[test]
+    fn delta_since_snapshot() {
+        let before = Stats::parse("direct_cache_hit\t5\ncache_miss\t7\n");
+        let after = Stats::parse("direct_cache_hit\t9\ncache_miss\t7\npreprocessed_cache_hit\t2\n");
+        assert_eq!(after.delta(&before, "direct_cache_hit"), 4);
+        assert_eq!(after.delta(&before, "cache_miss"), 0);
+        assert_eq!(after.delta(&before, "preprocessed_cache_hit"), 2);
+        // The cache was cleared in the meantime.
+        assert_eq!(before.delta(&after, "direct_cache_hit"), 0);
+    }
+}
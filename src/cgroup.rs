@@ -0,0 +1,657 @@
+// cgroups of sandboxes.
+// Controllers are used in the unified (v2) hierarchy if it provides them. On hosts with a
+// legacy or hybrid layout, controllers that are bound to a v1 hierarchy are used there instead;
+// the sandbox then has one cgroup per hierarchy.
+
+use crate::{Error, Resources};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+fn unsupported<T>(msg: String) -> Result<T, Error> {
+    Err(Error::Unsupported(msg))
+}
+
+// Returns the mount point of the unified (v2) cgroup hierarchy.
+fn find_mount() -> Option<PathBuf> {
+    find_mount_by(|fstype, _| fstype == "cgroup2")
+}
+
+// Returns the mount point of the v1 hierarchy that the controller is bound to.
+fn find_legacy_mount(controller: &str) -> Option<PathBuf> {
+    find_mount_by(|fstype, options| {
+        fstype == "cgroup" && options.split(',').any(|o| o == controller)
+    })
+}
+
+fn find_mount_by(matches: impl Fn(&str, &str) -> bool) -> Option<PathBuf> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mountinfo.lines().find_map(|line| {
+        // The file system type, the source and the super block options follow
+        // the " - " separator.
+        let (fields, rest) = line.split_once(" - ")?;
+        let mut rest = rest.split_whitespace();
+        let fstype = rest.next()?;
+        let options = rest.nth(1).unwrap_or_default();
+        if !matches(fstype, options) {
+            return None;
+        }
+        fields.split_whitespace().nth(4).map(PathBuf::from)
+    })
+}
+
+// Returns the caller's cgroup (relative to the mount point of the hierarchy).
+// Without a controller, the cgroup in the unified hierarchy is returned.
+fn own_cgroup(controller: Option<&str>) -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    cgroups
+        .lines()
+        .find_map(|line| {
+            // Lines have the format ID:CONTROLLERS:PATH.
+            let mut fields = line.splitn(3, ':');
+            let id = fields.next()?;
+            let controllers = fields.next()?;
+            let path = fields.next()?;
+            let found = match controller {
+                Some(controller) => controllers.split(',').any(|c| c == controller),
+                None => id == "0" && controllers.is_empty(),
+            };
+            Some(path).filter(|_| found)
+        })
+        .map(|path| PathBuf::from(path.trim_start_matches('/')))
+}
+
+// Returns the controllers that are needed to apply the resource settings.
+fn controllers(resources: &Resources) -> Vec<&'static str> {
+    let mut controllers = Vec::new();
+    if resources.numa_node.is_some() {
+        controllers.push("cpuset");
+    }
+    if resources.memory.is_some() {
+        controllers.push("memory");
+    }
+    if resources.pids_max.is_some() {
+        controllers.push("pids");
+    }
+    if resources.cpu.is_some() {
+        controllers.push("cpu");
+    }
+    if resources.io.is_some() {
+        controllers.push("io");
+    }
+    if !resources.hugetlb.is_empty() {
+        controllers.push("hugetlb");
+    }
+    controllers
+}
+
+// Name of the v1 controller that corresponds to a v2 controller.
+fn legacy_name(controller: &str) -> &str {
+    match controller {
+        "io" => "blkio",
+        controller => controller,
+    }
+}
+
+// Returns the parent of the sandbox's cgroup in the hierarchy that is mounted at mount.
+fn parent_in(
+    mount: &Path,
+    resources: &Resources,
+    controller: Option<&str>,
+) -> Result<PathBuf, Error> {
+    match resources
+        .cgroup_parent
+        .clone()
+        .or_else(|| own_cgroup(controller))
+    {
+        Some(parent) => Ok(mount.join(parent.strip_prefix("/").unwrap_or(&parent))),
+        None => unsupported("unable to determine the caller's cgroup".to_string()),
+    }
+}
+
+// Enables the controller for the children of parent (in the unified hierarchy).
+// Returns false if the controller is not available in parent.
+fn enable(parent: &Path, controller: &str) -> Result<bool, Error> {
+    let read = |file: &str| std::fs::read_to_string(parent.join(file)).unwrap_or_default();
+    if !read("cgroup.controllers")
+        .split_whitespace()
+        .any(|c| c == controller)
+    {
+        return Ok(false);
+    }
+    if read("cgroup.subtree_control")
+        .split_whitespace()
+        .any(|c| c == controller)
+    {
+        return Ok(true);
+    }
+    // Fails (with EBUSY) if the parent contains processes.
+    let subtree_control = parent.join("cgroup.subtree_control");
+    match std::fs::write(&subtree_control, format!("+{}", controller)) {
+        Ok(()) => Ok(true),
+        Err(e) => unsupported(format!(
+            "cannot enable cgroup controller {} in {}: {} \
+            (use resources.cgroupParent to select a delegated cgroup without processes)",
+            controller,
+            subtree_control.display(),
+            e
+        )),
+    }
+}
+
+// Converts a weight in the range of cpu.weight and io.weight (1 to 10000) into the range
+// of the corresponding v1 setting.
+fn legacy_weight(weight: u64, min: u64, max: u64) -> u64 {
+    min + (weight - 1) * (max - min) / 9999
+}
+
+// The cgroup of a sandbox. The cgroup is created below the caller's cgroup (or below
+// resources.cgroupParent), which needs to be delegated to the caller.
+#[derive(Clone)]
+pub struct Cgroup {
+    // The sandbox's cgroup in the unified hierarchy (if any controller is used there).
+    unified: Option<PathBuf>,
+    // The sandbox's cgroups in v1 hierarchies, by controller. Controllers that are
+    // mounted together (e.g., cpu,cpuacct) share a cgroup.
+    legacy: Vec<(&'static str, PathBuf)>,
+}
+
+impl Cgroup {
+    // Creates the cgroup and applies the resource settings.
+    // Returns None if no setting requires a cgroup.
+    pub fn create(resources: &Resources, run_id: &str) -> Result<Option<Cgroup>, Error> {
+        let controllers = controllers(resources);
+        if controllers.is_empty() {
+            return Ok(None);
+        }
+
+        let name = format!("cbuildrt-{}", run_id);
+        let unified_parent = match find_mount() {
+            Some(mount) => Some(parent_in(&mount, resources, None)?),
+            None => None,
+        };
+        let mut cgroup = Cgroup {
+            unified: None,
+            legacy: Vec::new(),
+        };
+        for controller in controllers {
+            if let Some(parent) = &unified_parent {
+                if enable(parent, controller)? {
+                    cgroup.unified = Some(parent.join(&name));
+                    continue;
+                }
+            }
+            let mount = match find_legacy_mount(legacy_name(controller)) {
+                Some(mount) => mount,
+                None => {
+                    return unsupported(format!(
+                        "cgroup controller {} is not available{}",
+                        controller,
+                        match &unified_parent {
+                            Some(parent) => format!(" in {}", parent.display()),
+                            None => String::new(),
+                        }
+                    ))
+                }
+            };
+            let parent = parent_in(&mount, resources, Some(legacy_name(controller)))?;
+            cgroup.legacy.push((controller, parent.join(&name)));
+        }
+
+        let paths = cgroup.paths();
+        for (i, path) in paths.iter().enumerate() {
+            if let Err(e) = std::fs::create_dir(path) {
+                for created in &paths[..i] {
+                    let _ = std::fs::remove_dir(created);
+                }
+                return Err(Error::Setup(format!(
+                    "failed to create cgroup {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        }
+        if let Err(e) = cgroup.configure(resources) {
+            let _ = cgroup.remove();
+            return Err(e);
+        }
+        Ok(Some(cgroup))
+    }
+
+    // Returns the cgroup that holds the settings of the controller.
+    fn dir(&self, controller: &str) -> Option<&Path> {
+        self.legacy
+            .iter()
+            .find(|(c, _)| *c == controller)
+            .map(|(_, path)| path)
+            .or(self.unified.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    fn is_legacy(&self, controller: &str) -> bool {
+        self.legacy.iter().any(|(c, _)| *c == controller)
+    }
+
+    fn has(&self, controller: &str, file: &str) -> bool {
+        self.dir(controller)
+            .is_some_and(|dir| dir.join(file).exists())
+    }
+
+    fn write(&self, controller: &str, file: &str, value: &str) -> Result<(), Error> {
+        let path = self
+            .dir(controller)
+            .expect("controller is not used by the cgroup");
+        std::fs::write(path.join(file), value).map_err(|e| {
+            Error::Setup(format!(
+                "failed to set {} of cgroup {} to {}: {}",
+                file,
+                path.display(),
+                value,
+                e
+            ))
+        })
+    }
+
+    fn configure(&self, resources: &Resources) -> Result<(), Error> {
+        if let Some(node) = resources.numa_node {
+            let cpus = crate::numa::cpu_list(node).map_err(Error::Unsupported)?;
+            self.write("cpuset", "cpuset.cpus", &cpus)?;
+            self.write("cpuset", "cpuset.mems", &node.to_string())?;
+        }
+        if let Some(memory) = &resources.memory {
+            self.configure_memory(memory.max, memory.swap_max)?;
+        }
+        if let Some(max) = resources.pids_max {
+            self.write("pids", "pids.max", &max.to_string())?;
+        }
+        if let Some(cpu) = &resources.cpu {
+            let period = cpu.period.unwrap_or(100000);
+            let bandwidth = cpu.quota.is_some() || cpu.period.is_some();
+            if self.is_legacy("cpu") {
+                if let Some(weight) = cpu.weight {
+                    let shares = legacy_weight(weight, 2, 262144);
+                    self.write("cpu", "cpu.shares", &shares.to_string())?;
+                }
+                if bandwidth {
+                    self.write("cpu", "cpu.cfs_period_us", &period.to_string())?;
+                }
+                if let Some(quota) = cpu.quota {
+                    self.write("cpu", "cpu.cfs_quota_us", &quota.to_string())?;
+                }
+            } else {
+                if let Some(weight) = cpu.weight {
+                    self.write("cpu", "cpu.weight", &weight.to_string())?;
+                }
+                if bandwidth {
+                    let quota = cpu.quota.map_or("max".to_string(), |q| q.to_string());
+                    self.write("cpu", "cpu.max", &format!("{} {}", quota, period))?;
+                }
+            }
+        }
+        if let Some(weight) = resources.io.as_ref().and_then(|io| io.weight) {
+            // Weights are only supported by some I/O schedulers (CFQ or BFQ).
+            let (file, value) = if !self.is_legacy("io") && self.has("io", "io.weight") {
+                ("io.weight", format!("default {}", weight))
+            } else if !self.is_legacy("io") && self.has("io", "io.bfq.weight") {
+                ("io.bfq.weight", format!("default {}", weight))
+            } else if self.has("io", "blkio.weight") {
+                ("blkio.weight", legacy_weight(weight, 10, 1000).to_string())
+            } else if self.has("io", "blkio.bfq.weight") {
+                (
+                    "blkio.bfq.weight",
+                    legacy_weight(weight, 1, 1000).to_string(),
+                )
+            } else {
+                return unsupported(
+                    "resources.io.weight requires an I/O scheduler that supports weights \
+                    (e.g., BFQ)"
+                        .to_string(),
+                );
+            };
+            self.write("io", file, &value)?;
+        }
+        for (size, max) in &resources.hugetlb {
+            let file = if self.is_legacy("hugetlb") {
+                format!("hugetlb.{}.limit_in_bytes", size)
+            } else {
+                format!("hugetlb.{}.max", size)
+            };
+            if !self.has("hugetlb", &file) {
+                return unsupported(format!("huge pages of size {} are not available", size));
+            }
+            self.write("hugetlb", &file, &max.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn configure_memory(&self, max: Option<u64>, swap_max: Option<u64>) -> Result<(), Error> {
+        let no_swap_accounting = || {
+            Err(Error::Unsupported(
+                "memory.swapMax requires swap accounting, which is disabled on this host"
+                    .to_string(),
+            ))
+        };
+        if !self.is_legacy("memory") {
+            if let Some(max) = max {
+                self.write("memory", "memory.max", &max.to_string())?;
+            }
+            if let Some(swap_max) = swap_max {
+                // The file only exists if the kernel accounts swap usage.
+                if !self.has("memory", "memory.swap.max") {
+                    return no_swap_accounting();
+                }
+                self.write("memory", "memory.swap.max", &swap_max.to_string())?;
+            }
+            return Ok(());
+        }
+
+        if let Some(max) = max {
+            self.write("memory", "memory.limit_in_bytes", &max.to_string())?;
+        }
+        if let Some(swap_max) = swap_max {
+            // cgroup v1 limits the sum of the memory and swap usage.
+            let max = match max {
+                Some(max) => max,
+                None => {
+                    return unsupported(
+                        "memory.swapMax requires memory.max on hosts with cgroup v1".to_string(),
+                    )
+                }
+            };
+            if !self.has("memory", "memory.memsw.limit_in_bytes") {
+                return no_swap_accounting();
+            }
+            let limit = max.saturating_add(swap_max);
+            self.write("memory", "memory.memsw.limit_in_bytes", &limit.to_string())?;
+        }
+        Ok(())
+    }
+
+    // Returns the cgroups of the sandbox (one per hierarchy).
+    pub fn paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = self.unified.iter().map(PathBuf::as_path).collect();
+        for (_, path) in &self.legacy {
+            if !paths.contains(&path.as_path()) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    // Moves the calling process into the cgroup.
+    pub fn join(&self) -> Result<(), Error> {
+        for path in self.paths() {
+            std::fs::write(path.join("cgroup.procs"), "0").map_err(crate::setup_error(format!(
+                "join cgroup {}",
+                path.display()
+            )))?;
+        }
+        Ok(())
+    }
+
+    // Returns the number of processes in the cgroup that were killed by the OOM killer.
+    pub fn oom_kills(&self) -> Option<u64> {
+        let file = if !self.is_legacy("memory") {
+            "memory.events"
+        } else {
+            "memory.oom_control"
+        };
+        let events = std::fs::read_to_string(self.dir("memory")?.join(file)).ok()?;
+        events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|n| n.trim().parse().ok())
+    }
+
+    // Makes the cgroup available read-only at /sys/fs/cgroup (see resources.mountCgroup).
+    // On hosts with v1 hierarchies, each hierarchy is mounted to a subdirectory that is named
+    // like on the host (e.g., /sys/fs/cgroup/memory). Must be called after entering the
+    // cgroup namespace, such that the cgroup is its root.
+    pub fn mount(&self, rootfs: &Path) -> Result<(), Error> {
+        let target = crate::concat_absolute(rootfs, "/sys/fs/cgroup");
+        if !target.is_dir() {
+            // The rootfs usually has an empty /sys; provide the mount point on a tmpfs.
+            mount_tmpfs(&crate::concat_absolute(rootfs, "/sys"), &["fs/cgroup"])?;
+        }
+
+        let mut binds = Vec::new();
+        if self.legacy.is_empty() {
+            binds.push((target, self.unified.clone().unwrap()));
+        } else {
+            for (controller, path) in &self.legacy {
+                let name = find_legacy_mount(legacy_name(controller))
+                    .and_then(|mount| mount.file_name().map(PathBuf::from))
+                    .unwrap_or_else(|| PathBuf::from(legacy_name(controller)));
+                if !binds.iter().any(|(_, p)| p == path) {
+                    binds.push((target.join(name), path.clone()));
+                }
+            }
+            if let Some(path) = &self.unified {
+                binds.push((target.join("unified"), path.clone()));
+            }
+            let names: Vec<&Path> = binds
+                .iter()
+                .map(|(t, _)| t.strip_prefix(&target).unwrap())
+                .collect();
+            mount_tmpfs(&target, &names)?;
+        }
+
+        for (target, source) in binds {
+            nix::mount::mount(
+                Some(&source),
+                &target,
+                None::<&str>,
+                nix::mount::MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .map_err(crate::setup_error(format!(
+                "mount cgroup {}",
+                source.display()
+            )))?;
+            nix::mount::mount(
+                Some(&source),
+                &target,
+                None::<&str>,
+                nix::mount::MsFlags::MS_REMOUNT
+                    | nix::mount::MsFlags::MS_BIND
+                    | nix::mount::MsFlags::MS_RDONLY
+                    | crate::locked_mount_flags(&source)?,
+                None::<&str>,
+            )
+            .map_err(crate::setup_error("make cgroup read-only"))?;
+        }
+        Ok(())
+    }
+
+    // Removes the cgroup. It must not contain processes anymore.
+    pub fn remove(&self) -> std::io::Result<()> {
+        let mut result = Ok(());
+        for path in self.paths() {
+            if let Err(e) = std::fs::remove_dir(path) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+// Current resource usage of a sandbox's cgroup. Values are None if the sandbox does not
+// use the corresponding controller.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CgroupStats {
+    // Total CPU time of all processes (that ever ran in the cgroup).
+    pub cpu_usec: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub memory_max: Option<u64>,
+    pub pids: Option<u64>,
+    pub pids_max: Option<u64>,
+    pub io_read_bytes: Option<u64>,
+    pub io_write_bytes: Option<u64>,
+}
+
+// Returns the usage of the cgroup of a sandbox, given the (host) PID of its init.
+pub fn cgroup_stats(pid: i32) -> Result<CgroupStats, Error> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        .map_err(|e| Error::Setup(format!("failed to read the cgroups of {}: {}", pid, e)))?;
+    // Only cgroups that cbuildrt created are considered; in other hierarchies,
+    // the sandbox shares the cgroup of its caller.
+    let mut cgroup = Cgroup {
+        unified: None,
+        legacy: Vec::new(),
+    };
+    for line in cgroups.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(id), Some(controllers), Some(path)) => (id, controllers, path),
+            _ => continue,
+        };
+        let path = Path::new(path.trim_start_matches('/'));
+        let is_sandbox = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("cbuildrt-"));
+        if !is_sandbox {
+            continue;
+        }
+        if id == "0" && controllers.is_empty() {
+            cgroup.unified = find_mount().map(|mount| mount.join(path));
+            continue;
+        }
+        for controller in ["cpuacct", "memory", "pids", "io"] {
+            if controllers.split(',').any(|c| c == legacy_name(controller)) {
+                if let Some(mount) = find_legacy_mount(legacy_name(controller)) {
+                    cgroup.legacy.push((controller, mount.join(path)));
+                }
+            }
+        }
+    }
+    if cgroup.paths().is_empty() {
+        return Err(Error::Unsupported(
+            "the sandbox does not have a cgroup (it does not set any resources)".to_string(),
+        ));
+    }
+    Ok(cgroup.stats())
+}
+
+impl Cgroup {
+    fn read(&self, controller: &str, file: &str) -> Option<String> {
+        std::fs::read_to_string(self.dir(controller)?.join(file)).ok()
+    }
+
+    // Reads a file that contains a single number. v2 uses "max" for unlimited settings,
+    // v1 uses values close to i64::MAX.
+    fn read_number(&self, controller: &str, file: &str) -> Option<u64> {
+        self.read(controller, file)?
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&n: &u64| n < 1 << 62)
+    }
+
+    fn stats(&self) -> CgroupStats {
+        let mut stats = CgroupStats {
+            cpu_usec: None,
+            memory_bytes: None,
+            memory_max: None,
+            pids: None,
+            pids_max: None,
+            io_read_bytes: None,
+            io_write_bytes: None,
+        };
+        stats.pids = self.read_number("pids", "pids.current");
+        stats.pids_max = self.read_number("pids", "pids.max");
+
+        if self.is_legacy("cpuacct") {
+            stats.cpu_usec = self
+                .read_number("cpuacct", "cpuacct.usage")
+                .map(|ns| ns / 1000);
+        } else {
+            stats.cpu_usec = self.read("cpu", "cpu.stat").and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|n| n.trim().parse().ok())
+            });
+        }
+
+        if self.is_legacy("memory") {
+            stats.memory_bytes = self.read_number("memory", "memory.usage_in_bytes");
+            stats.memory_max = self.read_number("memory", "memory.limit_in_bytes");
+        } else {
+            stats.memory_bytes = self.read_number("memory", "memory.current");
+            stats.memory_max = self.read_number("memory", "memory.max");
+        }
+
+        let (file, parse) = if self.is_legacy("io") {
+            (
+                "blkio.throttle.io_service_bytes",
+                parse_legacy_io as fn(&str) -> _,
+            )
+        } else {
+            ("io.stat", parse_io as fn(&str) -> _)
+        };
+        if let Some(io) = self.read("io", file) {
+            let (read, write) = io
+                .lines()
+                .filter_map(parse)
+                .fold((0, 0), |(r, w), (dr, dw)| (r + dr, w + dw));
+            stats.io_read_bytes = Some(read);
+            stats.io_write_bytes = Some(write);
+        }
+        stats
+    }
+}
+
+// Parses a line of io.stat, e.g., "8:0 rbytes=1 wbytes=2 ...", into bytes read and written.
+fn parse_io(line: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        line.split_whitespace()
+            .find_map(|f| f.strip_prefix(name))
+            .and_then(|n| n.parse().ok())
+    };
+    Some((field("rbytes=")?, field("wbytes=")?))
+}
+
+// Parses a line of blkio.throttle.io_service_bytes, e.g., "8:0 Read 1".
+fn parse_legacy_io(line: &str) -> Option<(u64, u64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let n = fields.get(2)?.parse().ok()?;
+    match fields[1] {
+        "Read" => Some((n, 0)),
+        "Write" => Some((0, n)),
+        _ => None,
+    }
+}
+
+// Mounts a read-only tmpfs that contains the given (empty) directories.
+fn mount_tmpfs<P: AsRef<Path>>(target: &Path, dirs: &[P]) -> Result<(), Error> {
+    nix::mount::mount(
+        None::<&str>,
+        target,
+        Some("tmpfs"),
+        nix::mount::MsFlags::MS_NOSUID | nix::mount::MsFlags::MS_NODEV,
+        Some("mode=0755"),
+    )
+    .map_err(crate::setup_error(format!(
+        "mount tmpfs at {}",
+        target.display()
+    )))?;
+    for dir in dirs {
+        std::fs::create_dir_all(target.join(dir)).map_err(crate::setup_error(format!(
+            "create {}",
+            dir.as_ref().display()
+        )))?;
+    }
+    nix::mount::mount(
+        None::<&str>,
+        target,
+        None::<&str>,
+        nix::mount::MsFlags::MS_REMOUNT
+            | nix::mount::MsFlags::MS_RDONLY
+            | nix::mount::MsFlags::MS_NOSUID
+            | nix::mount::MsFlags::MS_NODEV,
+        None::<&str>,
+    )
+    .map_err(crate::setup_error(format!(
+        "make {} read-only",
+        target.display()
+    )))
+}
@@ -0,0 +1,19 @@
+use crate::platform::{Native, Platform};
+use serde::Serialize;
+
+// Result of checking whether the host supports a feature of cbuildrt.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    // Whether cbuildrt works at all without this feature.
+    pub required: bool,
+    pub detail: String,
+}
+
+// Checks which features of cbuildrt the host supports.
+// This only inspects the host; it does not try to set up a sandbox.
+pub fn check_host() -> Vec<HostCheck> {
+    <Native as Platform>::check_host()
+}
@@ -0,0 +1,357 @@
+// Detached sessions (see --detach). cbuildrt continues in the background, in a new session,
+// such that the sandbox survives the terminal. The sandbox's console (see Config::console) is
+// relayed to a client that connects to console.sock in the sandbox's state directory
+// (see cbuildrt attach). Clients detach with Ctrl-P Ctrl-Q.
+
+use crate::cli::output::OutputFormat;
+use crate::cli::state::{StateDir, Status};
+use nix::fcntl::{FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{self, SetArg};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+pub const SOCKET_NAME: &str = "console.sock";
+const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+
+// Printed by the foreground process.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Detached<'a> {
+    run_id: &'a str,
+}
+
+type Client = Arc<Mutex<Option<UnixStream>>>;
+
+pub struct Session {
+    // The ends of the pipes that become the sandbox's stdin and stdout/stderr.
+    stdin: Option<File>,
+    stdout: Option<File>,
+    socket: PathBuf,
+    // Reports the run ID to the foreground process.
+    notify: Option<File>,
+    output: Option<JoinHandle<()>>,
+}
+
+// Copies the sandbox's output to the current client (if any).
+fn relay_output(mut output: File, client: Client) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = match output.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let mut client = client.lock().unwrap();
+        if let Some(stream) = client.as_mut() {
+            if stream.write_all(&buffer[..n]).is_err() {
+                *client = None;
+            }
+        }
+    }
+    // Clients exit once the sandbox has exited.
+    if let Some(stream) = client.lock().unwrap().take() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+// Copies the input of a client to the sandbox's stdin. This does not use std::io::copy(),
+// whose splice() fast path ended connections after the first read.
+fn copy_input(mut reader: UnixStream, mut input: &File) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => {
+                if input.write_all(&buffer[..n]).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Accepts clients. A new client replaces the current one (e.g., after a lost connection).
+fn accept(listener: UnixListener, input: File, client: Client) {
+    let input = Arc::new(input);
+    for stream in listener.incoming().flatten() {
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(_) => continue,
+        };
+        if let Some(old) = client.lock().unwrap().replace(stream) {
+            let _ = old.shutdown(Shutdown::Both);
+        }
+        let input = input.clone();
+        std::thread::spawn(move || copy_input(reader, &input));
+    }
+}
+
+fn redirect(fd: i32, target: i32) -> i32 {
+    let saved =
+        nix::fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(3)).expect("failed to duplicate stdio");
+    nix::unistd::dup2(target, fd).expect("failed to redirect stdio");
+    saved
+}
+
+impl Session {
+    // Forks. The parent waits until the sandbox has started, prints its run ID and exits
+    // (with the exit code of the child if the sandbox fails to start). The child returns.
+    // Must be called while the process is single-threaded.
+    pub fn detach(format: OutputFormat, state_dir: &StateDir) -> Result<Session, String> {
+        let (notify_read, notify_write) =
+            nix::unistd::pipe2(OFlag::O_CLOEXEC).expect("failed to create pipe");
+        let child = match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Child) => nix::unistd::getpid(),
+            Ok(nix::unistd::ForkResult::Parent { child }) => {
+                nix::unistd::close(notify_write).expect("failed to close pipe");
+                // The sandbox inherits the pipe; hence, only a line is read (instead of EOF).
+                let mut id = String::new();
+                let _ =
+                    BufReader::new(unsafe { File::from_raw_fd(notify_read) }).read_line(&mut id);
+                if id.trim().is_empty() {
+                    std::process::exit(match nix::sys::wait::waitpid(child, None) {
+                        Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => code,
+                        _ => 1,
+                    });
+                }
+                format.emit(&Detached { run_id: id.trim() }, |d| {
+                    println!("{}", d.run_id)
+                });
+                std::process::exit(0);
+            }
+            Err(e) => return Err(format!("failed to fork: {}", e)),
+        };
+        nix::unistd::close(notify_read).expect("failed to close pipe");
+        // In a new session, the process does not receive SIGHUP when the terminal hangs up.
+        nix::unistd::setsid().expect("failed to create session");
+
+        let socket = state_dir.temp_file(&format!("console-{}.sock", child))?;
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket)
+            .map_err(|e| format!("failed to bind {}: {}", socket.display(), e))?;
+        let (stdin_read, stdin_write) =
+            nix::unistd::pipe2(OFlag::O_CLOEXEC).expect("failed to create pipe");
+        let (stdout_read, stdout_write) =
+            nix::unistd::pipe2(OFlag::O_CLOEXEC).expect("failed to create pipe");
+        let client = Client::default();
+        let output = {
+            let output = unsafe { File::from_raw_fd(stdout_read) };
+            let client = client.clone();
+            std::thread::spawn(move || relay_output(output, client))
+        };
+        {
+            let input = unsafe { File::from_raw_fd(stdin_write) };
+            let client = client.clone();
+            std::thread::spawn(move || accept(listener, input, client));
+        }
+        Ok(Session {
+            stdin: Some(unsafe { File::from_raw_fd(stdin_read) }),
+            stdout: Some(unsafe { File::from_raw_fd(stdout_write) }),
+            socket,
+            notify: Some(unsafe { File::from_raw_fd(notify_write) }),
+            output: Some(output),
+        })
+    }
+
+    // Makes the pipes the stdio of the calling process while spawn() runs, such that the
+    // sandbox inherits them.
+    pub fn around<T, F: FnOnce() -> T>(&mut self, spawn: F) -> T {
+        let stdin = self.stdin.take().unwrap();
+        let stdout = self.stdout.take().unwrap();
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        let saved = [
+            (
+                libc::STDIN_FILENO,
+                redirect(libc::STDIN_FILENO, stdin.as_raw_fd()),
+            ),
+            (
+                libc::STDOUT_FILENO,
+                redirect(libc::STDOUT_FILENO, stdout.as_raw_fd()),
+            ),
+            (
+                libc::STDERR_FILENO,
+                redirect(libc::STDERR_FILENO, stdout.as_raw_fd()),
+            ),
+        ];
+        let result = spawn();
+        for (fd, original) in saved {
+            nix::unistd::dup2(original, fd).expect("failed to restore stdio");
+            nix::unistd::close(original).expect("failed to close stdio");
+        }
+        result
+    }
+
+    // Moves the socket into the state directory of the sandbox (if it was recorded there),
+    // lets the foreground process exit and detaches from the terminal.
+    pub fn started(&mut self, dir: Option<PathBuf>, id: &str) {
+        match dir {
+            Some(dir) => {
+                let path = dir.join(SOCKET_NAME);
+                match std::fs::rename(&self.socket, &path) {
+                    Ok(()) => self.socket = path,
+                    Err(e) => eprintln!("warning: failed to move {}: {}", path.display(), e),
+                }
+            }
+            None => eprintln!("warning: the console of {} cannot be attached", id),
+        }
+        if let Some(mut notify) = self.notify.take() {
+            let _ = writeln!(notify, "{}", id);
+        }
+        let null = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .expect("failed to open /dev/null");
+        for fd in 0..3 {
+            nix::unistd::dup2(null.as_raw_fd(), fd).expect("failed to redirect stdio");
+        }
+    }
+
+    // Waits until the remaining output has been relayed. Must be called once all processes
+    // that write to the sandbox's stdout (including those of Capture) have exited.
+    pub fn finish(mut self) {
+        if let Some(output) = self.output.take() {
+            let _ = output.join();
+        }
+    }
+}
+
+// The socket is also removed if the sandbox fails to start.
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+// Sets the window size of the sandbox's console to the size of the terminal.
+fn resize(console: &File, size: &mut libc::winsize) {
+    let mut current: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut current) } < 0 {
+        return;
+    }
+    if (current.ws_row, current.ws_col) != (size.ws_row, size.ws_col) {
+        *size = current;
+        unsafe { libc::ioctl(console.as_raw_fd(), libc::TIOCSWINSZ, &current) };
+    }
+}
+
+// Relays between the terminal and the session until the sandbox exits (returns true) or the
+// detach keys are pressed (returns false).
+fn relay(stream: &mut UnixStream, console: Option<&File>) -> std::io::Result<bool> {
+    let mut stdin = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+    let mut stdout = std::io::stdout();
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let mut buffer = [0u8; 4096];
+    // Whether the first detach key ended the previous input.
+    let mut pending = false;
+    let result = loop {
+        // Changes of the window size are polled (instead of handling SIGWINCH).
+        if let Some(console) = console {
+            resize(console, &mut size);
+        }
+        let mut fds = [
+            PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN),
+            PollFd::new(stream.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, 250) {
+            Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Ok(_) => (),
+            Err(e) => break Err(std::io::Error::other(e)),
+        }
+        let ready = |fd: &PollFd| fd.revents().is_some_and(|r| !r.is_empty());
+        if ready(&fds[1]) {
+            match stream.read(&mut buffer) {
+                Ok(0) => break Ok(true),
+                Ok(n) => {
+                    stdout.write_all(&buffer[..n])?;
+                    stdout.flush()?;
+                }
+                Err(e) => break Err(e),
+            }
+        }
+        if ready(&fds[0]) {
+            let n = match stdin.read(&mut buffer) {
+                Ok(0) => break Ok(false),
+                Ok(n) => n,
+                Err(e) => break Err(e),
+            };
+            let mut input = Vec::with_capacity(n + 1);
+            for &byte in &buffer[..n] {
+                if pending && byte == DETACH_KEYS[1] {
+                    std::mem::forget(stdin);
+                    return Ok(false);
+                }
+                if pending {
+                    input.push(DETACH_KEYS[0]);
+                }
+                pending = byte == DETACH_KEYS[0];
+                if !pending {
+                    input.push(byte);
+                }
+            }
+            stream.write_all(&input)?;
+        }
+    };
+    std::mem::forget(stdin);
+    result
+}
+
+// Attaches the terminal to the console of a detached sandbox.
+pub fn run(state_dir: &StateDir, id: &str) -> i32 {
+    let result = state_dir.load(id).and_then(|state| {
+        if state.status == Status::Stopped {
+            return Err(format!("sandbox {} is not running", id));
+        }
+        let path = state_dir.dir(id)?.join(SOCKET_NAME);
+        let mut stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                format!("sandbox {} was not started with --detach", id)
+            }
+            _ => format!("failed to connect to {}: {}", path.display(), e),
+        })?;
+        // The window size is set on the terminal's side (as it is not forwarded through the
+        // session).
+        let console = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(Path::new(&format!("/proc/{}/root", state.pid)).join("dev/console"))
+            .ok();
+
+        // Control characters (e.g., ^C) are passed to the sandbox.
+        let saved = termios::tcgetattr(libc::STDIN_FILENO).ok();
+        if let Some(saved) = &saved {
+            let mut raw = saved.clone();
+            termios::cfmakeraw(&mut raw);
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &raw);
+        }
+        let result = relay(&mut stream, console.as_ref());
+        if let Some(saved) = &saved {
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, saved);
+        }
+        match result {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                eprintln!("\ndetached from {}", id);
+                Ok(())
+            }
+            Err(e) => Err(format!("failed to relay the console: {}", e)),
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// Runs many cbuild.json files concurrently.
+// Jobs of a manifest can depend on other jobs; they only start once their dependencies succeeded.
+
+use crate::cli::output::OutputFormat;
+use cbuildrt::{DiskUsage, Error, Handle, Sandbox};
+use nix::poll::{poll, PollFd, PollFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Manifest that lists the jobs of a batch. Paths are relative to the manifest's directory.
+#[derive(Deserialize)]
+struct Manifest {
+    jobs: Vec<JobSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JobSpec {
+    // Defaults to the path of the configuration.
+    name: Option<String>,
+    config: PathBuf,
+    // Names of jobs that need to succeed before this job starts.
+    #[serde(default)]
+    depends_on: Vec<String>,
+}
+
+struct Job {
+    name: String,
+    config: PathBuf,
+    // Indices of the jobs that this job depends on.
+    dependencies: Vec<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobResult {
+    name: String,
+    run_id: Option<String>,
+    exit_code: i32,
+    duration_ms: u64,
+    error: Option<String>,
+    error_code: Option<&'static str>,
+    // The job was not started because of a failure (see --keep-going).
+    skipped: bool,
+    disk_usage: Vec<DiskUsage>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchSummary {
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    jobs: Vec<JobResult>,
+}
+
+struct Running {
+    job: usize,
+    handle: Handle,
+    start: Instant,
+}
+
+fn load_manifest(path: &Path) -> Result<Vec<JobSpec>, String> {
+    let f = std::fs::File::open(path)
+        .map_err(|e| format!("unable to open {}: {}", path.display(), e))?;
+    let manifest: Manifest = serde_json::from_reader(f)
+        .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(manifest
+        .jobs
+        .into_iter()
+        .map(|spec| JobSpec {
+            config: dir.join(&spec.config),
+            name: Some(match spec.name {
+                Some(name) => name,
+                None => spec.config.to_string_lossy().into_owned(),
+            }),
+            depends_on: spec.depends_on,
+        })
+        .collect())
+}
+
+// Resolves the dependencies of the jobs and checks that they do not form a cycle.
+fn resolve(specs: Vec<JobSpec>) -> Result<Vec<Job>, String> {
+    let mut indices = HashMap::new();
+    for (i, spec) in specs.iter().enumerate() {
+        if indices.insert(spec.name.clone().unwrap(), i).is_some() {
+            return Err(format!(
+                "job {} is listed twice",
+                spec.name.as_ref().unwrap()
+            ));
+        }
+    }
+    let mut jobs = Vec::new();
+    for spec in specs {
+        let name = spec.name.unwrap();
+        let dependencies = spec
+            .depends_on
+            .iter()
+            .map(|dep| {
+                indices
+                    .get(dep)
+                    .copied()
+                    .ok_or_else(|| format!("job {} depends on unknown job {}", name, dep))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        jobs.push(Job {
+            name,
+            config: spec.config,
+            dependencies,
+        });
+    }
+
+    // Depth-first search; a job that is reached again while it is on the stack closes a cycle.
+    fn visit(
+        jobs: &[Job],
+        job: usize,
+        marks: &mut [u8],
+        stack: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        match marks[job] {
+            2 => return Ok(()),
+            1 => {
+                let start = stack.iter().position(|j| *j == job).unwrap();
+                let cycle: Vec<&str> = stack[start..]
+                    .iter()
+                    .chain(Some(&job))
+                    .map(|j| jobs[*j].name.as_str())
+                    .collect();
+                return Err(format!("dependency cycle: {}", cycle.join(" -> ")));
+            }
+            _ => (),
+        }
+        marks[job] = 1;
+        stack.push(job);
+        for dep in &jobs[job].dependencies {
+            visit(jobs, *dep, marks, stack)?;
+        }
+        stack.pop();
+        marks[job] = 2;
+        Ok(())
+    }
+    let mut marks = vec![0; jobs.len()];
+    for job in 0..jobs.len() {
+        visit(&jobs, job, &mut marks, &mut Vec::new())?;
+    }
+    Ok(jobs)
+}
+
+fn spawn(job: &Job) -> Result<Handle, Error> {
+    let cfg = crate::cli::load_config(&job.config)?;
+    // Conflicting jobs fail with Error::RootfsLocked and are retried by the scheduler.
+    Sandbox::from_config(cfg)?.spawn()
+}
+
+fn finish(
+    job: &Job,
+    handle: Option<&Handle>,
+    start: Instant,
+    result: Result<i32, Error>,
+) -> JobResult {
+    JobResult {
+        name: job.name.clone(),
+        run_id: handle.map(|h| h.run_id().to_string()),
+        exit_code: *result.as_ref().unwrap_or(&1),
+        duration_ms: start.elapsed().as_millis() as u64,
+        error: result.as_ref().err().map(|e| e.to_string()),
+        error_code: result.as_ref().err().map(Error::code),
+        skipped: false,
+        disk_usage: handle.map_or_else(Vec::new, |h| h.disk_usage().to_vec()),
+    }
+}
+
+fn skip(job: &Job, reason: String) -> JobResult {
+    JobResult {
+        name: job.name.clone(),
+        run_id: None,
+        exit_code: 1,
+        duration_ms: 0,
+        error: Some(reason),
+        error_code: None,
+        skipped: true,
+        disk_usage: Vec::new(),
+    }
+}
+
+// Runs the jobs with at most max_jobs sandboxes at a time. Unless keep_going is set,
+// no further jobs are started after a job failed.
+fn run_jobs(
+    format: OutputFormat,
+    jobs: &[Job],
+    max_jobs: usize,
+    keep_going: bool,
+) -> Vec<Option<JobResult>> {
+    let mut results: Vec<Option<JobResult>> = jobs.iter().map(|_| None).collect();
+    let mut states = vec![State::Pending; jobs.len()];
+    let mut running: Vec<Running> = Vec::new();
+    let mut done = 0;
+    let progress = |done: usize, msg: String| {
+        if format == OutputFormat::Human {
+            eprintln!("[{}/{}] {}", done, jobs.len(), msg);
+        }
+    };
+
+    loop {
+        let stop = !keep_going && states.contains(&State::Failed);
+        for job in 0..jobs.len() {
+            if states[job] != State::Pending {
+                continue;
+            }
+            // Jobs are skipped if a dependency failed (or was skipped itself).
+            let failed = jobs[job]
+                .dependencies
+                .iter()
+                .find(|dep| matches!(states[**dep], State::Failed | State::Skipped));
+            let reason = match failed {
+                Some(dep) => format!("skipped since {} failed", jobs[*dep].name),
+                None if stop => "skipped after an earlier failure".to_string(),
+                None => continue,
+            };
+            done += 1;
+            progress(done, format!("{}: {}", jobs[job].name, reason));
+            states[job] = State::Skipped;
+            results[job] = Some(skip(&jobs[job], reason));
+        }
+
+        for job in 0..jobs.len() {
+            if running.len() >= max_jobs || (!keep_going && states.contains(&State::Failed)) {
+                break;
+            }
+            let ready = states[job] == State::Pending
+                && jobs[job]
+                    .dependencies
+                    .iter()
+                    .all(|dep| states[*dep] == State::Succeeded);
+            if !ready {
+                continue;
+            }
+            let start = Instant::now();
+            match spawn(&jobs[job]) {
+                Ok(handle) => {
+                    progress(
+                        done,
+                        format!("{}: started run {}", jobs[job].name, handle.run_id()),
+                    );
+                    states[job] = State::Running;
+                    running.push(Running { job, handle, start });
+                }
+                // Jobs that conflict with a running job on the rootfs lock are retried
+                // once a job finishes.
+                Err(Error::RootfsLocked { .. }) if !running.is_empty() => (),
+                Err(e) => {
+                    done += 1;
+                    progress(done, format!("{}: {}", jobs[job].name, e));
+                    states[job] = State::Failed;
+                    results[job] = Some(finish(&jobs[job], None, start, Err(e)));
+                }
+            }
+        }
+        if running.is_empty() {
+            // Jobs that are still pending depend on jobs that just failed; skip them.
+            if states.contains(&State::Pending) {
+                continue;
+            }
+            break;
+        }
+
+        let mut fds: Vec<PollFd> = running
+            .iter()
+            .map(|r| PollFd::new(r.handle.as_raw_fd(), PollFlags::POLLIN))
+            .collect();
+        match poll(&mut fds, -1) {
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            result => result.expect("failed to poll sandboxes"),
+        };
+        let mut i = 0;
+        while i < running.len() {
+            let result = match running[i].handle.try_wait() {
+                Ok(None) => {
+                    i += 1;
+                    continue;
+                }
+                result => result.map(Option::unwrap),
+            };
+            let r = running.swap_remove(i);
+            done += 1;
+            progress(
+                done,
+                match &result {
+                    Ok(code) => format!(
+                        "{}: exited with code {} ({} ms)",
+                        jobs[r.job].name,
+                        code,
+                        r.start.elapsed().as_millis()
+                    ),
+                    Err(e) => format!("{}: {}", jobs[r.job].name, e),
+                },
+            );
+            states[r.job] = match result {
+                Ok(0) => State::Succeeded,
+                _ => State::Failed,
+            };
+            results[r.job] = Some(finish(&jobs[r.job], Some(&r.handle), r.start, result));
+        }
+    }
+    results
+}
+
+pub fn run(format: OutputFormat, matches: &clap::ArgMatches) -> i32 {
+    let mut specs = Vec::new();
+    if let Some(path) = matches.value_of("manifest") {
+        match load_manifest(Path::new(path)) {
+            Ok(manifest_specs) => specs.extend(manifest_specs),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    }
+    for config in matches.values_of("cbuild-json").into_iter().flatten() {
+        specs.push(JobSpec {
+            name: Some(config.to_string()),
+            config: PathBuf::from(config),
+            depends_on: Vec::new(),
+        });
+    }
+    let jobs = match resolve(specs) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let max_jobs = match matches.value_of("jobs").map(str::parse::<usize>) {
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            eprintln!("--jobs must be a positive number");
+            return 1;
+        }
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    let job_results: Vec<JobResult> =
+        run_jobs(format, &jobs, max_jobs, matches.is_present("keep-going"))
+            .into_iter()
+            .flatten()
+            .collect();
+    let skipped = job_results.iter().filter(|r| r.skipped).count();
+    let failed = job_results.iter().filter(|r| r.exit_code != 0).count() - skipped;
+    let summary = BatchSummary {
+        succeeded: job_results.len() - failed - skipped,
+        failed,
+        skipped,
+        jobs: job_results,
+    };
+    format.emit(&summary, |s| {
+        eprintln!(
+            "{} jobs succeeded, {} failed, {} skipped",
+            s.succeeded, s.failed, s.skipped
+        );
+        for r in s.jobs.iter().filter(|r| r.exit_code != 0 && !r.skipped) {
+            eprintln!(
+                "  {}: {}",
+                r.name,
+                r.error.as_deref().unwrap_or("non-zero exit code")
+            );
+        }
+    });
+    if summary.failed > 0 || summary.skipped > 0 {
+        1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,22 @@
+use crate::cli::output::OutputFormat;
+
+// Reports which features of cbuildrt the host supports.
+// Returns a non-zero exit code if a required feature is missing.
+pub fn run(format: OutputFormat) -> i32 {
+    let checks = cbuildrt::check_host();
+    format.emit(&checks, |checks| {
+        for check in checks.iter() {
+            let status = match (check.ok, check.required) {
+                (true, _) => "ok",
+                (false, true) => "FAIL",
+                (false, false) => "unavailable",
+            };
+            println!("{:<16} {:<12} {}", check.name, status, check.detail);
+        }
+    });
+    if checks.iter().any(|c| c.required && !c.ok) {
+        1
+    } else {
+        0
+    }
+}
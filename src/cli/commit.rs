@@ -0,0 +1,245 @@
+// Folds the changes of an overlayfs upper directory (see overlay.rs) into a new rootfs
+// directory or into a layer archive (in the format of OCI image layers).
+
+use crate::cli::overlay::{self, Change, Kind};
+use crate::cli::state::StateDir;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn with_path<T, E: std::fmt::Display>(path: &Path, result: Result<T, E>) -> Result<T, String> {
+    result.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Copies a single file system object (without the contents of directories), preserving
+// its type, mode, modification time and (when running as root) its owner.
+fn clone_entry(source: &Path, destination: &Path) -> Result<(), String> {
+    let metadata = with_path(source, std::fs::symlink_metadata(source))?;
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        if !destination.is_dir() {
+            with_path(destination, std::fs::create_dir(destination))?;
+        }
+    } else if file_type.is_symlink() {
+        let target = with_path(source, std::fs::read_link(source))?;
+        with_path(destination, std::os::unix::fs::symlink(target, destination))?;
+    } else if file_type.is_file() {
+        with_path(destination, std::fs::copy(source, destination))?;
+    } else {
+        // Devices, FIFOs and sockets.
+        let mode = nix::sys::stat::SFlag::from_bits_truncate(metadata.mode());
+        with_path(
+            destination,
+            nix::sys::stat::mknod(
+                destination,
+                mode,
+                nix::sys::stat::Mode::from_bits_truncate(metadata.mode()),
+                metadata.rdev(),
+            ),
+        )?;
+    }
+
+    if nix::unistd::geteuid().is_root() {
+        with_path(
+            destination,
+            nix::unistd::fchownat(
+                None,
+                destination,
+                Some(nix::unistd::Uid::from_raw(metadata.uid())),
+                Some(nix::unistd::Gid::from_raw(metadata.gid())),
+                nix::unistd::FchownatFlags::NoFollowSymlink,
+            ),
+        )?;
+    }
+    if !file_type.is_symlink() {
+        with_path(
+            destination,
+            std::fs::set_permissions(
+                destination,
+                std::fs::Permissions::from_mode(metadata.mode() & 0o7777),
+            ),
+        )?;
+    }
+    let mtime = nix::sys::time::TimeSpec::from(libc::timespec {
+        tv_sec: metadata.mtime(),
+        tv_nsec: metadata.mtime_nsec(),
+    });
+    with_path(
+        destination,
+        nix::sys::stat::utimensat(
+            None,
+            destination,
+            &mtime,
+            &mtime,
+            nix::sys::stat::UtimensatFlags::NoFollowSymlink,
+        ),
+    )
+}
+
+// Recursively copies a tree with clone_entry().
+fn clone_tree(source: &Path, destination: &Path) -> Result<(), String> {
+    clone_entry(source, destination)?;
+    if std::fs::symlink_metadata(source).is_ok_and(|m| m.is_dir()) {
+        for entry in with_path(source, std::fs::read_dir(source))? {
+            let name = with_path(source, entry)?.file_name();
+            clone_tree(&source.join(&name), &destination.join(&name))?;
+        }
+        // Creating the entries has changed the modification time.
+        clone_entry(source, destination)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<(), String> {
+    let result = match std::fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(_) => Ok(()),
+    };
+    with_path(path, result)
+}
+
+// Copies the lower directory to destination and applies the changes.
+fn to_dir(
+    upper: &Path,
+    lower: &Path,
+    changes: &[Change],
+    destination: &Path,
+) -> Result<(), String> {
+    if destination.exists() {
+        return Err(format!("{} already exists", destination.display()));
+    }
+    clone_tree(lower, destination)?;
+    for change in changes {
+        let target = overlay::join(destination, &change.path);
+        if change.kind == Kind::Deleted {
+            remove(&target)?;
+            continue;
+        }
+        let source = overlay::join(upper, &change.path);
+        // Objects that change their type replace the lower object entirely.
+        let is_dir = |p: &Path| std::fs::symlink_metadata(p).is_ok_and(|m| m.is_dir());
+        if !is_dir(&source) || !is_dir(&target) {
+            remove(&target)?;
+        }
+        clone_entry(&source, &target)?;
+    }
+    // Directories were copied before their contents changed.
+    for change in changes.iter().rev().filter(|c| c.kind != Kind::Deleted) {
+        let source = overlay::join(upper, &change.path);
+        if source.is_dir() {
+            clone_entry(&source, &overlay::join(destination, &change.path))?;
+        }
+    }
+    Ok(())
+}
+
+// Name of the whiteout file that marks the deletion of name in OCI image layers.
+fn whiteout_name(path: &Path) -> PathBuf {
+    let mut name = b".wh.".to_vec();
+    name.extend_from_slice(path.file_name().unwrap().as_bytes());
+    path.with_file_name(std::ffi::OsStr::from_bytes(&name))
+}
+
+// Creates the parents of path inside root, copying their metadata from the upper directory.
+fn clone_parents(upper: &Path, root: &Path, path: &Path) -> Result<(), String> {
+    for parent in path
+        .ancestors()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        let target = overlay::join(root, parent);
+        if !target.exists() {
+            clone_entry(&overlay::join(upper, parent), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// Writes the changes as a layer archive. The archive is compressed according to its suffix
+// (e.g., .tar.zst) by the host's tar.
+fn to_layer(upper: &Path, changes: &[Change], output: &Path) -> Result<(), String> {
+    // The layer is assembled next to the archive.
+    let tree = output.with_file_name(format!(
+        ".{}.tree",
+        output.file_name().unwrap().to_string_lossy()
+    ));
+    remove(&tree)?;
+    let result = (|| {
+        clone_entry(upper, &tree)?;
+        for change in changes {
+            clone_parents(upper, &tree, &change.path)?;
+            let target = overlay::join(&tree, &change.path);
+            if change.kind == Kind::Deleted {
+                let whiteout = whiteout_name(&target);
+                with_path(&whiteout, std::fs::File::create(&whiteout))?;
+            } else {
+                clone_entry(&overlay::join(upper, &change.path), &target)?;
+            }
+        }
+        for change in changes.iter().rev().filter(|c| c.kind != Kind::Deleted) {
+            let source = overlay::join(upper, &change.path);
+            if source.is_dir() {
+                clone_entry(&source, &overlay::join(&tree, &change.path))?;
+            }
+        }
+        let status = Command::new("tar")
+            .args(["--auto-compress", "--numeric-owner", "-c", "-f"])
+            .arg(output)
+            .arg("-C")
+            .arg(&tree)
+            .arg(".")
+            .status()
+            .map_err(|e| format!("failed to run tar: {}", e))?;
+        if !status.success() {
+            return Err(format!("tar failed with {}", status));
+        }
+        Ok(())
+    })();
+    let _ = remove(&tree);
+    result
+}
+
+pub fn run(
+    state_dir: &StateDir,
+    run: &str,
+    lower: Option<&Path>,
+    to: Option<&Path>,
+    layer: Option<&Path>,
+) -> i32 {
+    let (upper, lower) = match overlay::resolve(state_dir, run, lower) {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let (upper, lower) = (upper.as_path(), lower.as_path());
+    let changes = match overlay::changes(upper, lower) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!(
+                "failed to compare {} to {}: {}",
+                upper.display(),
+                lower.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    let result = match (to, layer) {
+        (Some(dir), _) => to_dir(upper, lower, &changes, dir),
+        (None, Some(output)) => to_layer(upper, &changes, output),
+        (None, None) => unreachable!(),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("failed to commit {}: {}", upper.display(), e);
+            1
+        }
+    }
+}
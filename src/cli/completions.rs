@@ -0,0 +1,8 @@
+use std::str::FromStr;
+
+// Prints a completion script for the given shell to stdout.
+pub fn run(mut app: clap::App, shell: &str) -> i32 {
+    let shell = clap::Shell::from_str(shell).unwrap();
+    app.gen_completions_to("cbuildrt", shell, &mut std::io::stdout());
+    0
+}
@@ -0,0 +1,91 @@
+// Lists the files that a build added, modified or deleted in an overlayfs upper directory
+// (see overlay.rs), relative to the lower (pristine) rootfs.
+
+use crate::cli::output::OutputFormat;
+use crate::cli::overlay::{self, Change, Kind};
+use crate::cli::state::StateDir;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Serialize)]
+struct Diff {
+    changes: Vec<Change>,
+}
+
+// Writes the added and modified files to a tar archive (using the host's tar).
+fn write_tar(upper: &Path, changes: &[Change], output: &Path) -> Result<(), String> {
+    let mut tar = Command::new("tar")
+        .arg("-c")
+        .arg("-f")
+        .arg(output)
+        .arg("-C")
+        .arg(upper)
+        .args(["--no-recursion", "--null", "-T", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run tar: {}", e))?;
+    let mut list = Vec::new();
+    for change in changes.iter().filter(|c| c.kind != Kind::Deleted) {
+        list.extend_from_slice(b".");
+        list.extend_from_slice(change.path.as_os_str().as_encoded_bytes());
+        list.push(0);
+    }
+    tar.stdin
+        .take()
+        .unwrap()
+        .write_all(&list)
+        .map_err(|e| format!("failed to write to tar: {}", e))?;
+    match tar.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("tar failed with {}", status)),
+        Err(e) => Err(format!("failed to wait for tar: {}", e)),
+    }
+}
+
+pub fn run(
+    format: OutputFormat,
+    state_dir: &StateDir,
+    run: &str,
+    lower: Option<&Path>,
+    tar: Option<&Path>,
+) -> i32 {
+    let (upper, lower) = match overlay::resolve(state_dir, run, lower) {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let (upper, lower) = (upper.as_path(), lower.as_path());
+    let changes = match overlay::changes(upper, lower) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!(
+                "failed to compare {} to {}: {}",
+                upper.display(),
+                lower.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    if let Some(output) = tar {
+        if let Err(e) = write_tar(upper, &changes, output) {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    format.emit(&Diff { changes }, |d| {
+        for change in &d.changes {
+            let marker = match change.kind {
+                Kind::Added => 'A',
+                Kind::Modified => 'M',
+                Kind::Deleted => 'D',
+            };
+            println!("{} {}", marker, change.path.display());
+        }
+    });
+    0
+}
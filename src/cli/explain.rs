@@ -0,0 +1,35 @@
+// Diagnostics for errors of the runtime and the catalogue behind --explain.
+
+use cbuildrt::Error;
+use std::io::IsTerminal;
+
+// Whether diagnostics on stderr are colorized (see https://no-color.org).
+fn use_color() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+// Prints an error as "error[E0006]: message" followed by a pointer to --explain.
+pub fn report(e: &Error) {
+    if use_color() {
+        eprintln!("\x1b[1;31merror[{}]\x1b[0m\x1b[1m: {}\x1b[0m", e.code(), e);
+    } else {
+        eprintln!("error[{}]: {}", e.code(), e);
+    }
+    eprintln!(
+        "run `cbuildrt --explain {}` for possible causes and fixes",
+        e.code()
+    );
+}
+
+pub fn run(code: &str) -> i32 {
+    match Error::explain(code) {
+        Some(explanation) => {
+            println!("{}", explanation);
+            0
+        }
+        None => {
+            eprintln!("{} is not an error code of cbuildrt", code);
+            1
+        }
+    }
+}
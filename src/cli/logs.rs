@@ -0,0 +1,143 @@
+// Output logs of runs. With --capture-output, the output of a sandbox (stdout and stderr) is
+// copied to output.log in the sandbox's state directory, in addition to being written to
+// cbuildrt's own stdout and stderr.
+
+use crate::cli::state::{StateDir, Status};
+use nix::fcntl::{FcntlArg, OFlag};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const FILE_NAME: &str = "output.log";
+
+// Copies the output of a sandbox to a log file. The log is created before the run ID is known;
+// it is moved into the state directory of the sandbox by keep().
+pub struct Capture {
+    path: PathBuf,
+    threads: Vec<JoinHandle<()>>,
+}
+
+// Copies everything from the pipe to output and to the log.
+fn copy(pipe: RawFd, mut output: File, mut log: File) {
+    let mut pipe = unsafe { File::from_raw_fd(pipe) };
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = match pipe.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let _ = output.write_all(&buffer[..n]);
+        let _ = log.write_all(&buffer[..n]);
+    }
+}
+
+impl Capture {
+    // Redirects stdout and stderr of the calling process to pipes while spawn() runs, such
+    // that the sandbox inherits the pipes.
+    pub fn around<T, F: FnOnce() -> T>(
+        state_dir: &StateDir,
+        spawn: F,
+    ) -> Result<(Capture, T), String> {
+        let path = state_dir.temp_file(&format!("output-{}.log", std::process::id()))?;
+        let log = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        let mut capture = Capture {
+            path,
+            threads: Vec::new(),
+        };
+        let mut saved = Vec::new();
+        for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            let (read, write) =
+                nix::unistd::pipe2(OFlag::O_CLOEXEC).expect("failed to create output pipe");
+            let original = nix::fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(3))
+                .expect("failed to duplicate stdio");
+            nix::unistd::dup2(write, fd).expect("failed to redirect stdio");
+            nix::unistd::close(write).expect("failed to close output pipe");
+            saved.push(original);
+            let output = unsafe { File::from_raw_fd(nix::unistd::dup(original).unwrap()) };
+            let log = log.try_clone().expect("failed to duplicate log file");
+            capture
+                .threads
+                .push(std::thread::spawn(move || copy(read, output, log)));
+        }
+
+        let result = spawn();
+
+        // Afterwards, only the sandbox holds the pipes.
+        for (fd, original) in [libc::STDOUT_FILENO, libc::STDERR_FILENO].iter().zip(saved) {
+            nix::unistd::dup2(original, *fd).expect("failed to restore stdio");
+            nix::unistd::close(original).expect("failed to close stdio");
+        }
+        Ok((capture, result))
+    }
+
+    // Moves the log into the state directory of the sandbox.
+    pub fn keep(&mut self, state_dir: &StateDir, id: &str) -> Result<(), String> {
+        let path = state_dir.dir(id)?.join(FILE_NAME);
+        std::fs::rename(&self.path, &path)
+            .map_err(|e| format!("failed to move output log to {}: {}", path.display(), e))?;
+        self.path = path;
+        Ok(())
+    }
+
+    // Waits until all processes of the sandbox have closed their output. Logs that were not
+    // kept are removed.
+    pub fn finish(self, kept: bool) {
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        if !kept {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Prints the log of a sandbox. With follow, waits for more output until the sandbox exits.
+pub fn run(state_dir: &StateDir, id: &str, follow: bool) -> i32 {
+    let result = state_dir.load(id).and_then(|_| {
+        let path = state_dir.dir(id)?.join(FILE_NAME);
+        let mut log = File::open(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!(
+                "no output was captured for sandbox {} (see --capture-output)",
+                id
+            ),
+            _ => format!("failed to open {}: {}", path.display(), e),
+        })?;
+        let mut stdout = std::io::stdout();
+        loop {
+            // The state is checked before copying, such that output that precedes the exit
+            // is printed.
+            let running = follow
+                && state_dir
+                    .load(id)
+                    .is_ok_and(|state| state.status != Status::Stopped);
+            std::io::copy(&mut log, &mut stdout)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            if !running {
+                return Ok(());
+            }
+            let _ = stdout.flush();
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
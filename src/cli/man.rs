@@ -0,0 +1,364 @@
+// Generates a man page from the help output of the CLI definition.
+
+use clap::{crate_description, crate_version};
+
+// Keys of cbuild.json, described in the CONFIGURATION section.
+// Keep this in sync with cbuildrt::Config.
+const CONFIG_FIELDS: &[(&str, &str)] = &[
+    ("rootfs", "Host path of the root file system."),
+    (
+        "runId",
+        "ID of the run (exported as CBUILDRT_RUN_ID and included in logs and results); \
+        a random ID is generated if unset.",
+    ),
+    (
+        "user",
+        "Object with the uid and gid of the process inside the sandbox.",
+    ),
+    ("process.args", "Command line of the process."),
+    (
+        "process.script",
+        "Shell script that is run instead of process.args. The script is written to a file \
+        inside the sandbox and executed by /bin/sh -e; hence, it needs no quoting.",
+    ),
+    (
+        "process.environ",
+        "Map of environment variables of the process; they override the variables that \
+        cbuildrt sets.",
+    ),
+    (
+        "process.clearEnviron",
+        "Start the process from an empty environment instead of inheriting cbuildrt's.",
+    ),
+    (
+        "process.cwd",
+        "Absolute working directory of the process inside the sandbox (default: /).",
+    ),
+    (
+        "bindMounts",
+        "List of objects with a host source and a sandbox destination that are bind mounted. \
+        If verifyWritable is set, cbuildrt checks that the sandbox user can write to the mount \
+        before running the process.",
+    ),
+    (
+        "isolateNetwork",
+        "Run the process in an empty network namespace.",
+    ),
+    (
+        "rootfsWritable",
+        "Do not remount the rootfs read-only. Writable runs lock the rootfs exclusively.",
+    ),
+    (
+        "sysctls",
+        "Map of namespaced sysctls (net.*, kernel.shm*, ...) to set inside the sandbox. Non-net sysctls require user.uid 0.",
+    ),
+    (
+        "hostname",
+        "Hostname inside the sandbox (in a new UTS namespace).",
+    ),
+    (
+        "home",
+        "Object with the path of HOME inside the sandbox and a create flag \
+        (which mounts an empty tmpfs owned by the sandbox user at the path).",
+    ),
+    (
+        "xdgDirs",
+        "Set XDG_CACHE_HOME, XDG_CONFIG_HOME and XDG_RUNTIME_DIR to private directories \
+        below /run.",
+    ),
+    (
+        "gui",
+        "Make the host's X11 and Wayland displays (DISPLAY, XAUTHORITY and WAYLAND_DISPLAY) \
+        available inside the sandbox.",
+    ),
+    (
+        "dbus",
+        "Either \"session\" (forward the host's session bus) or an object with the host path \
+        of the socket of a dedicated bus; sets DBUS_SESSION_BUS_ADDRESS.",
+    ),
+    (
+        "locale",
+        "Object with LANG (lang), a map of LC_* categories and the source of the locale data \
+        (\"host\" bind mounts /usr/lib/locale, \"generate\" compiles C.UTF-8 inside the sandbox).",
+    ),
+    (
+        "secrets",
+        "Map of names to objects with a host source and an optional env variable. \
+        Secrets are copied to a read-only tmpfs at /run/cbuildrt/secrets/NAME and \
+        scrubbed from error messages and syscall traces. Cannot be combined with \
+        --capture-output.",
+    ),
+    (
+        "reproducible",
+        "Enable the reproducible-build preset (fixed environment, hostname, umask and machine-id).",
+    ),
+    (
+        "sourceDateEpoch",
+        "SOURCE_DATE_EPOCH for reproducible builds.",
+    ),
+    (
+        "proxy",
+        "Either \"host\" (forward the host's proxy variables) or \"none\" (clear them).",
+    ),
+    (
+        "downloadCache",
+        "Object with a host cache dir, a port (default 3128), URL suffixes to cache and \
+        optionally the hosts that may be contacted. Isolates the network and points the \
+        proxy variables to a proxy on the host that caches plain HTTP downloads by SHA-256; \
+        HTTPS is only tunnelled. The proxy refuses loopback, link-local and private \
+        addresses.",
+    ),
+    (
+        "ccache",
+        "Object with the host cache dir and a stats flag; mounted at /run/ccache.",
+    ),
+    (
+        "sccache",
+        "Object with serverSocket or serverPort, rustcWrapper and an environ map.",
+    ),
+    (
+        "distcc",
+        "Object with hosts, an optional netns to join and an optional configDir. Cannot be combined with isolateNetwork.",
+    ),
+    (
+        "toolMounts, sourceMounts, sysrootMounts",
+        "Maps of names to objects with a host source and a writable flag; \
+        mounted below /run/xbstrap.",
+    ),
+    (
+        "accessManifest",
+        "Host path of a JSON manifest of the host files that the build accessed.",
+    ),
+    (
+        "traceSyscalls",
+        "Object with an output file, an optional filter and an optional host strace binary.",
+    ),
+    (
+        "perf",
+        "Object with an output file, events, a callGraph flag and an optional host perf binary.",
+    ),
+    (
+        "qemuUser",
+        "Host path of a static qemu-user binary for foreign-architecture rootfs trees. \
+        For binfmt_misc handlers without the F flag, it is mounted to the handler's \
+        interpreter path, which requires rootfsWritable unless that path exists in the rootfs.",
+    ),
+    (
+        "debug",
+        "Object with an optional host gdbserver binary and a port.",
+    ),
+    (
+        "staging",
+        "List of objects with a host source and a sandbox destination that are copied \
+        into the sandbox before the run.",
+    ),
+    (
+        "artifacts",
+        "List of objects with a sandbox source and a host destination that are copied \
+        out of the sandbox after the run.",
+    ),
+    (
+        "disableAslr",
+        "Disable address space layout randomization (ADDR_NO_RANDOMIZE) for the process.",
+    ),
+    (
+        "console",
+        "Run the process in a new session on a pseudo terminal, which is also available as \
+        /dev/console and is the controlling terminal of the process. The rootfs needs to \
+        contain /dev/console as a mount point.",
+    ),
+    (
+        "timeout",
+        "Time in seconds after which the process is killed. Before that, the remaining \
+        processes are logged (as when cbuildrt receives SIGQUIT).",
+    ),
+    (
+        "pathPrepend",
+        "List of directories that are added to the front of the default PATH.",
+    ),
+    (
+        "pathAppend",
+        "List of directories that are added to the end of the default PATH.",
+    ),
+    (
+        "init",
+        "Command line of an init (e.g., tini) that runs as PID 1 of a nested PID namespace. \
+        process.args are appended to it.",
+    ),
+    (
+        "ambientCapabilities",
+        "List of capabilities (e.g., CAP_NET_BIND_SERVICE) that the process keeps within the \
+        user namespace.",
+    ),
+    (
+        "preload",
+        "List of shared objects that are added to LD_PRELOAD. Each entry has a path inside \
+        the sandbox or, if host is set, a path on the host (the object is then mounted into \
+        the sandbox).",
+    ),
+    (
+        "ldCache",
+        "Regenerate /etc/ld.so.cache (using the rootfs' ldconfig) after all mounts have been \
+        performed. directories lists additional library directories.",
+    ),
+    (
+        "resources",
+        "Object with resource settings that are enforced through a cgroup below the caller's \
+        (delegated) cgroup or cgroupParent: numaNode restricts CPUs and memory to a NUMA node; \
+        memory is an object with max and swapMax (in bytes); pidsMax limits the number of \
+        processes; cpu is an object with weight (1 to 10000), quota and period (in \
+        microseconds); io is an object with weight (1 to 10000). Controllers that are bound to \
+        cgroup v1 hierarchies (on hosts with a legacy or hybrid layout) are used there. \
+        mountCgroup mounts the sandbox's cgroup read-only at /sys/fs/cgroup and makes it the \
+        root of a cgroup namespace, such that tools can read their limits. hugetlb maps huge \
+        page sizes (e.g., 2MB) to limits in bytes.",
+    ),
+    (
+        "hugepages",
+        "Object with a host hugetlbfs mount (source) and a mount point inside the sandbox \
+        (destination); both default to /dev/hugepages. Each run uses a private directory on \
+        the mount, which is removed after the run.",
+    ),
+    (
+        "workDir",
+        "Host directory for per-run data. Staged copies are stored in a subdirectory \
+        (named after the run ID) that is removed after the run.",
+    ),
+    (
+        "keepWorkDir",
+        "Keep the per-run subdirectory of workDir for debugging.",
+    ),
+];
+
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+// Help output, split into sections (e.g., USAGE or OPTIONS) of (head, description) entries.
+// The header (name, version and description) is returned as a section without a title.
+type Sections = Vec<(String, Vec<(String, String)>)>;
+
+fn help_sections(app: &mut clap::App) -> Sections {
+    let mut help = Vec::new();
+    app.write_long_help(&mut help).unwrap();
+    let mut sections: Sections = vec![(String::new(), Vec::new())];
+    for line in String::from_utf8(help).unwrap().lines() {
+        let indent = line.len() - line.trim_start().len();
+        if indent == 0 && line.ends_with(':') {
+            sections.push((line.trim_end_matches(':').to_string(), Vec::new()));
+            continue;
+        }
+        let entries = &mut sections.last_mut().unwrap().1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Descriptions are printed on separate lines (with a deeper indentation).
+        if indent < 12 || entries.is_empty() {
+            entries.push((line.trim().to_string(), String::new()));
+        } else {
+            let description = &mut entries.last_mut().unwrap().1;
+            if !description.is_empty() {
+                description.push(' ');
+            }
+            description.push_str(line.trim());
+        }
+    }
+    sections
+}
+
+fn write_entries(page: &mut String, entries: &[(String, String)]) {
+    for (head, description) in entries {
+        // Every subcommand has these; they are already described for cbuildrt itself.
+        if head.starts_with("-h, --help") || head.starts_with("-V, --version") {
+            continue;
+        }
+        page.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            escape(head),
+            escape(description)
+        ));
+    }
+}
+
+// Prints a man page (in roff format) to stdout.
+pub fn run(mut app: clap::App, subcommands: Vec<clap::App>) -> i32 {
+    let mut page = String::new();
+    page.push_str(&format!(
+        ".TH CBUILDRT 1 \"\" \"cbuildrt {}\"\n",
+        crate_version!()
+    ));
+    page.push_str(&format!(
+        ".SH NAME\ncbuildrt \\- {}\n",
+        escape(crate_description!())
+    ));
+
+    let sections = help_sections(&mut app);
+    page.push_str(".SH SYNOPSIS\n");
+    for (title, entries) in &sections {
+        if title == "USAGE" {
+            for (usage, _) in entries {
+                page.push_str(&format!("{}\n.br\n", escape(usage)));
+            }
+        }
+    }
+
+    page.push_str(
+        ".SH DESCRIPTION\n\
+        cbuildrt runs a process inside an unprivileged container that is described \
+        by a cbuild.json file (see CONFIGURATION). It is primarily used by xbstrap \
+        to isolate builds from the host.\n",
+    );
+
+    page.push_str(".SH OPTIONS\n");
+    for (title, entries) in &sections {
+        if title == "FLAGS" || title == "OPTIONS" || title == "ARGS" {
+            write_entries(&mut page, entries);
+        }
+    }
+
+    page.push_str(".SH COMMANDS\n");
+    for mut subcommand in subcommands {
+        let sections = help_sections(&mut subcommand);
+        page.push_str(&format!(".SS {}\n", escape(subcommand.get_name())));
+        for (title, entries) in &sections {
+            match title.as_str() {
+                // The first line of the header is the name.
+                "" => {
+                    for (about, _) in entries.iter().skip(1) {
+                        page.push_str(&format!("{}\n", escape(about)));
+                    }
+                    page.push_str(".PP\n");
+                }
+                "USAGE" => {
+                    for (usage, _) in entries {
+                        page.push_str(&format!("cbuildrt {}\n.br\n", escape(usage)));
+                    }
+                }
+                "FLAGS" | "OPTIONS" | "ARGS" => write_entries(&mut page, entries),
+                _ => (),
+            }
+        }
+    }
+
+    page.push_str(
+        ".SH CONFIGURATION\n\
+        The cbuild.json file is a JSON object with the following keys. \
+        Paths inside the sandbox are absolute.\n",
+    );
+    for (key, description) in CONFIG_FIELDS {
+        page.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            escape(key),
+            escape(description)
+        ));
+    }
+
+    page.push_str(".SH SEE ALSO\nxbstrap(1)\n");
+    print!("{}", page);
+    0
+}
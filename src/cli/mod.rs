@@ -0,0 +1,43 @@
+// Subcommands of the cbuildrt binary. The runtime itself is implemented by the library.
+
+pub mod attach;
+pub mod batch;
+pub mod check;
+#[cfg(target_os = "linux")]
+pub mod commit;
+pub mod completions;
+#[cfg(target_os = "linux")]
+pub mod diff;
+pub mod explain;
+pub mod logs;
+pub mod man;
+pub mod notify;
+pub mod oci;
+pub mod output;
+#[cfg(target_os = "linux")]
+pub mod overlay;
+#[cfg(target_os = "linux")]
+pub mod ps;
+pub mod remote;
+pub mod run;
+#[cfg(target_os = "linux")]
+pub mod selftest;
+pub mod state;
+#[cfg(target_os = "linux")]
+pub mod stats;
+pub mod validate;
+pub mod version;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+use cbuildrt::{Config, Error};
+use std::fs::File;
+use std::path::Path;
+
+// Reads a cbuild.json file.
+pub fn load_config(path: &Path) -> Result<Config, Error> {
+    let f = File::open(path)
+        .map_err(|e| Error::InvalidConfig(format!("unable to open {}: {}", path.display(), e)))?;
+    serde_json::from_reader(f)
+        .map_err(|e| Error::InvalidConfig(format!("failed to parse {}: {}", path.display(), e)))
+}
@@ -0,0 +1,65 @@
+// Notifications to the service manager (systemd's sd_notify() protocol).
+// These are only sent if cbuildrt runs as a service, i.e., if NOTIFY_SOCKET is set.
+
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Option<Notifier> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let path = path.to_string_lossy();
+        // Names that start with @ refer to the abstract namespace.
+        let addr = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            _ => SocketAddr::from_pathname(&*path),
+        }
+        .ok()?;
+        let socket = UnixDatagram::unbound().ok()?;
+
+        // The watchdog applies to the process that systemd started (see sd_watchdog_enabled()).
+        let pid_matches = std::env::var("WATCHDOG_PID")
+            .map(|pid| pid == std::process::id().to_string())
+            .unwrap_or(true);
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && pid_matches)
+            .map(Duration::from_micros);
+        Some(Notifier {
+            socket,
+            addr,
+            watchdog,
+        })
+    }
+
+    fn send(&self, state: &str) {
+        // The service manager may not listen (anymore); notifications are best-effort.
+        let _ = self.socket.send_to_addr(state.as_bytes(), &self.addr);
+    }
+
+    pub fn ready(&self, status: &str) {
+        self.send(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    // Interval in which keepalive() needs to be called (half of the watchdog timeout).
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    pub fn keepalive(&self) {
+        self.send("WATCHDOG=1");
+    }
+}
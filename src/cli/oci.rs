@@ -0,0 +1,271 @@
+// OCI runtime command line (create, start, state, kill and delete).
+// The container is supervised by a detached monitor process that records its state.
+
+use crate::cli::state::{State, StateDir, Status, OCI_VERSION};
+use cbuildrt::{Event, Sandbox};
+use nix::poll::{poll, PollFd, PollFlags};
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const EXEC_FIFO: &str = "exec.fifo";
+
+const RUN_ID_ANNOTATION: &str = "org.managarm.cbuildrt.run-id";
+
+// Runs the sandbox and reports (through report) once it has been created.
+// Afterwards, waits for the sandbox to terminate and records that in the state directory.
+fn monitor(state_dir: &StateDir, mut state: State, sandbox: Sandbox, report: &mut std::fs::File) {
+    let mut handle = match sandbox.spawn() {
+        Ok(handle) => handle,
+        Err(e) => {
+            let _ = writeln!(report, "{}", e);
+            return;
+        }
+    };
+
+    let mut ready = false;
+    while !ready {
+        let mut fds = [
+            PollFd::new(handle.events_fd(), PollFlags::POLLIN),
+            PollFd::new(handle.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            result => result.expect("failed to poll sandbox"),
+        };
+        for event in handle.events() {
+            if let Event::Ready = event {
+                ready = true;
+            }
+        }
+        if !ready && fds[1].revents().is_some_and(|r| !r.is_empty()) {
+            let msg = match handle.wait() {
+                Ok(code) => format!("sandbox terminated with exit code {}", code),
+                Err(e) => e.to_string(),
+            };
+            let _ = writeln!(report, "{}", msg);
+            return;
+        }
+    }
+
+    state.status = Status::Created;
+    state.set_pid(handle.init_pid().unwrap_or(0) as i32);
+    state
+        .annotations
+        .insert(RUN_ID_ANNOTATION.to_string(), handle.run_id().to_string());
+    if let Err(e) = state_dir.store(&state) {
+        let _ = writeln!(report, "{}", e);
+        let _ = handle.kill();
+        let _ = handle.wait();
+        return;
+    }
+    let _ = writeln!(report, "ok {}", state.pid);
+
+    let _ = handle.wait();
+    // The state directory is gone if the container was deleted in the meantime.
+    if let Ok(mut state) = state_dir.load(&state.id) {
+        state.status = Status::Stopped;
+        let _ = state_dir.store(&state);
+    }
+}
+
+fn create_sandbox(
+    state_dir: &StateDir,
+    dir: &Path,
+    id: &str,
+    bundle: &Path,
+) -> Result<(State, Sandbox), String> {
+    let bundle = std::fs::canonicalize(bundle)
+        .map_err(|e| format!("invalid bundle {}: {}", bundle.display(), e))?;
+    let cfg = cbuildrt::oci::config_from_bundle(&bundle).map_err(|e| e.to_string())?;
+    let fifo = dir.join(EXEC_FIFO);
+    nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::from_bits_truncate(0o600))
+        .map_err(|e| format!("failed to create {}: {}", fifo.display(), e))?;
+    let sandbox = Sandbox::from_config(cfg)
+        .map_err(|e| e.to_string())?
+        .start_gate(fifo);
+    let mut state = State {
+        oci_version: OCI_VERSION.to_string(),
+        id: id.to_string(),
+        status: Status::Creating,
+        pid: 0,
+        bundle,
+        annotations: Default::default(),
+    };
+    state.set_rootfs(&sandbox.config().rootfs);
+    state_dir.store(&state)?;
+    Ok((state, sandbox))
+}
+
+pub fn create(state_dir: &StateDir, id: &str, bundle: &Path, pid_file: Option<&Path>) -> i32 {
+    let dir = match state_dir.create(id) {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let (state, sandbox) = match create_sandbox(state_dir, &dir, id, bundle) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            let _ = state_dir.remove(id);
+            return 1;
+        }
+    };
+
+    let (report_read, report_write) =
+        nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).expect("failed to create pipe");
+    match unsafe { nix::unistd::fork() }.expect("failed to fork monitor") {
+        nix::unistd::ForkResult::Child => {
+            let _ = nix::unistd::close(report_read);
+            // Detach from the caller's session such that the monitor survives it.
+            let _ = nix::unistd::setsid();
+            let mut report = unsafe { std::fs::File::from_raw_fd(report_write) };
+            monitor(state_dir, state, sandbox, &mut report);
+            unsafe { libc::_exit(0) }
+        }
+        nix::unistd::ForkResult::Parent { .. } => {
+            let _ = nix::unistd::close(report_write);
+        }
+    }
+
+    // The sandbox's processes inherit the pipe, hence only read a single line.
+    let mut line = String::new();
+    let report = unsafe { std::fs::File::from_raw_fd(report_read) };
+    let _ = BufReader::new(report).read_line(&mut line);
+    match line.trim_end().strip_prefix("ok ") {
+        Some(pid) => {
+            if let Some(path) = pid_file {
+                if let Err(e) = std::fs::write(path, pid) {
+                    eprintln!("failed to write {}: {}", path.display(), e);
+                    return 1;
+                }
+            }
+            0
+        }
+        None => {
+            eprintln!("failed to create sandbox {}: {}", id, line.trim_end());
+            let _ = state_dir.remove(id);
+            1
+        }
+    }
+}
+
+pub fn start(state_dir: &StateDir, id: &str) -> i32 {
+    let result = state_dir.load(id).and_then(|mut state| {
+        if state.status != Status::Created {
+            return Err(format!("sandbox {} is not in the created state", id));
+        }
+        let fifo: PathBuf = state_dir.dir(id)?.join(EXEC_FIFO);
+        // Unblocks the process, which waits for a reader of the FIFO.
+        let contents = std::fs::read(&fifo)
+            .map_err(|e| format!("failed to open {}: {}", fifo.display(), e))?;
+        if contents.is_empty() {
+            return Err(format!("sandbox {} terminated before it was started", id));
+        }
+        let _ = std::fs::remove_file(&fifo);
+        state.status = Status::Running;
+        state_dir.store(&state)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+pub fn state(state_dir: &StateDir, id: &str) -> i32 {
+    match state_dir.load(id) {
+        Ok(state) => {
+            println!("{}", serde_json::to_string_pretty(&state).unwrap());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn parse_signal(name: &str) -> Result<nix::sys::signal::Signal, String> {
+    if let Ok(number) = name.parse::<i32>() {
+        return nix::sys::signal::Signal::try_from(number)
+            .map_err(|_| format!("invalid signal {}", name));
+    }
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    nix::sys::signal::Signal::from_str(&name).map_err(|_| format!("invalid signal {}", name))
+}
+
+// Sends a signal to the sandbox. init forwards it to the process (see Handle::signal()).
+pub fn kill(state_dir: &StateDir, id: &str, signal: &str) -> i32 {
+    let result = state_dir.load(id).and_then(|state| {
+        let signal = parse_signal(signal)?;
+        if state.status == Status::Stopped {
+            return Err(format!("sandbox {} is not running", id));
+        }
+        state
+            .open_init()?
+            .signal(signal as libc::c_int)
+            .map_err(|e| format!("failed to signal sandbox {}: {}", id, e))
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+pub fn delete(state_dir: &StateDir, id: &str, force: bool) -> i32 {
+    let result = state_dir.load(id).and_then(|state| {
+        if state.status != Status::Stopped {
+            if !force {
+                return Err(format!("sandbox {} has not stopped (use --force)", id));
+            }
+            // init may terminate in the meantime.
+            if let Ok(init) = state.open_init() {
+                let _ = init.signal(libc::SIGKILL);
+                // Killing init tears down the entire PID namespace.
+                while init.is_alive() {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            }
+        }
+        state_dir.remove(id)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::signal::Signal;
+
+    #[test]
+    fn signal_names_and_numbers() {
+        assert_eq!(parse_signal("TERM"), Ok(Signal::SIGTERM));
+        assert_eq!(parse_signal("sigkill"), Ok(Signal::SIGKILL));
+        assert_eq!(parse_signal("SIGINT"), Ok(Signal::SIGINT));
+        assert_eq!(parse_signal("9"), Ok(Signal::SIGKILL));
+        assert!(parse_signal("FOO").is_err());
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("-1").is_err());
+    }
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_matches(matches: &clap::ArgMatches) -> OutputFormat {
+        match matches.value_of("output-format") {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Human,
+        }
+    }
+
+    // Prints value as a single line of JSON to stdout, or calls human to print it otherwise.
+    pub fn emit<T: Serialize, F: FnOnce(&T)>(self, value: &T, human: F) {
+        match self {
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string(value).unwrap())
+            }
+            OutputFormat::Human => human(value),
+        }
+    }
+}
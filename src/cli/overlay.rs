@@ -0,0 +1,218 @@
+// Interprets the upper directory of an overlayfs mount, e.g., of a rootfs that is an overlayfs
+// mount and that is run with rootfsWritable. Whiteouts (character devices with device number
+// 0:0) mark deleted files; opaque directories (overlay.opaque xattr) hide the lower directory.
+// The redirect_dir and metacopy features of overlayfs are not interpreted.
+// Instead of an upper directory, commit and diff accept the ID of a run that is still
+// registered in the state directory (e.g., with --keep-state). Its upper and lower
+// directories are looked up from the overlayfs mount at the run's rootfs.
+
+use crate::cli::state::StateDir;
+use serde::Serialize;
+use std::ffi::CString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    // Absolute path inside the rootfs.
+    pub path: PathBuf,
+    pub kind: Kind,
+}
+
+// Resolves the path of a change below the given directory.
+pub fn join(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap())
+}
+
+pub fn is_whiteout(metadata: &std::fs::Metadata) -> bool {
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+// overlayfs uses the trusted namespace, or the user namespace with the userxattr option.
+pub fn is_opaque(path: &Path) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    ["trusted.overlay.opaque", "user.overlay.opaque"]
+        .iter()
+        .any(|name| {
+            let name = CString::new(*name).unwrap();
+            let mut value = [0u8; 1];
+            let n = unsafe {
+                libc::lgetxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_mut_ptr() as *mut libc::c_void,
+                    value.len(),
+                )
+            };
+            n == 1 && value[0] == b'y'
+        })
+}
+
+// Returns the entries of a directory, sorted by name.
+fn entries(dir: &Path) -> std::io::Result<Vec<std::ffi::OsString>> {
+    let mut names = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+fn walk(
+    upper: &Path,
+    lower: Option<&Path>,
+    path: &Path,
+    changes: &mut Vec<Change>,
+) -> std::io::Result<()> {
+    let names = entries(upper)?;
+    if let Some(lower) = lower.filter(|_| is_opaque(upper)) {
+        for name in entries(lower)? {
+            if !names.contains(&name) {
+                changes.push(Change {
+                    path: path.join(&name),
+                    kind: Kind::Deleted,
+                });
+            }
+        }
+    }
+    for name in names {
+        let upper = upper.join(&name);
+        let metadata = std::fs::symlink_metadata(&upper)?;
+        let lower = lower
+            .map(|l| l.join(&name))
+            .filter(|l| std::fs::symlink_metadata(l).is_ok());
+        let path = path.join(&name);
+        if is_whiteout(&metadata) {
+            // Whiteouts of files that do not exist in the lower directory have no effect.
+            if lower.is_some() {
+                changes.push(Change {
+                    path,
+                    kind: Kind::Deleted,
+                });
+            }
+            continue;
+        }
+        if metadata.is_dir() {
+            // Directories that exist in both layers are not reported themselves;
+            // they are copied up whenever their contents change.
+            let lower = lower.filter(|l| l.is_dir());
+            if lower.is_none() {
+                changes.push(Change {
+                    path: path.clone(),
+                    kind: Kind::Added,
+                });
+            }
+            walk(&upper, lower.as_deref(), &path, changes)?;
+            continue;
+        }
+        changes.push(Change {
+            path,
+            kind: if lower.is_some() {
+                Kind::Modified
+            } else {
+                Kind::Added
+            },
+        });
+    }
+    Ok(())
+}
+
+// Returns the changes that the upper directory applies to the lower directory.
+pub fn changes(upper: &Path, lower: &Path) -> std::io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    walk(upper, Some(lower), Path::new("/"), &mut changes)?;
+    Ok(changes)
+}
+
+// Decodes the octal escapes (e.g., \040 for spaces) of /proc/self/mountinfo.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| bytes[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match escape {
+            Some(digits) => {
+                decoded.push(digits.iter().fold(0u8, |n, d| n * 8 + (d - b'0')));
+                i += 4;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(decoded))
+}
+
+// Returns the upper directory and the lower directories of the overlayfs mount at mount_point.
+fn overlay_dirs(mount_point: &Path) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| format!("failed to read /proc/self/mountinfo: {}", e))?;
+    // The last mount at a path hides the earlier ones.
+    let options = mountinfo
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            // The file system type, the source and the super block options follow
+            // the " - " separator.
+            let (fields, rest) = line.split_once(" - ")?;
+            let target = fields.split_whitespace().nth(4)?;
+            let mut rest = rest.split_whitespace();
+            let fstype = rest.next()?;
+            let options = rest.nth(1).unwrap_or_default();
+            Some((unescape(target), fstype, options))
+        })
+        .find(|(target, _, _)| target == mount_point)
+        .filter(|(_, fstype, _)| *fstype == "overlay")
+        .map(|(_, _, options)| options)
+        .ok_or_else(|| format!("{} is not an overlayfs mount", mount_point.display()))?;
+    let option = |name: &str| {
+        options
+            .split(',')
+            .find_map(|o| o.strip_prefix(name)?.strip_prefix('='))
+    };
+    let upper = option("upperdir")
+        .ok_or_else(|| format!("{} has no upper directory", mount_point.display()))?;
+    let lowers = option("lowerdir").map_or_else(Vec::new, |l| l.split(':').map(unescape).collect());
+    Ok((unescape(upper), lowers))
+}
+
+// Resolves the argument of commit and diff (a run ID or an upper directory) and --lower
+// to the upper and the lower directory.
+pub fn resolve(
+    state_dir: &StateDir,
+    run: &str,
+    lower: Option<&Path>,
+) -> Result<(PathBuf, PathBuf), String> {
+    if Path::new(run).is_dir() {
+        let lower = lower.ok_or("--lower is required if an upper directory is given")?;
+        return Ok((PathBuf::from(run), lower.to_path_buf()));
+    }
+    let state = state_dir.load(run)?;
+    let rootfs = state
+        .rootfs()
+        .ok_or_else(|| format!("sandbox {} does not record its rootfs", run))?;
+    let (upper, lowers) = overlay_dirs(&rootfs)?;
+    let lower = match (lower, lowers.as_slice()) {
+        (Some(lower), _) => lower.to_path_buf(),
+        (None, [lower]) => lower.clone(),
+        (None, _) => {
+            return Err(format!(
+                "{} does not have a single lower directory, use --lower",
+                rootfs.display()
+            ))
+        }
+    };
+    Ok((upper, lower))
+}
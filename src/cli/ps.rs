@@ -0,0 +1,150 @@
+// Lists the processes of a running sandbox: its init and all descendants of init.
+// PIDs are reported as seen inside the sandbox's PID namespace, together with the host PIDs.
+
+use crate::cli::output::OutputFormat;
+use crate::cli::state::{StateDir, Status};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Process {
+    pid: i32,
+    ppid: i32,
+    host_pid: i32,
+    state: String,
+    // User and system CPU time.
+    cpu_ms: u64,
+    rss_bytes: u64,
+    cmdline: String,
+}
+
+#[derive(Serialize)]
+struct ProcessList {
+    processes: Vec<Process>,
+}
+
+// Host view of a process.
+struct HostProcess {
+    ppid: i32,
+    state: String,
+    cpu_ticks: u64,
+    rss_pages: u64,
+    // PIDs in the nested PID namespaces, from the outermost to the innermost one.
+    ns_pids: Vec<i32>,
+    cmdline: String,
+}
+
+fn read_process(pid: i32) -> Option<HostProcess> {
+    let dir = std::path::PathBuf::from(format!("/proc/{}", pid));
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    // The command name may contain spaces and parentheses, hence split after the last ')'.
+    let (comm, rest) = stat.rsplit_once(')')?;
+    let comm = comm.split_once('(')?.1;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let number = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+
+    let status = std::fs::read_to_string(dir.join("status")).ok()?;
+    let ns_pids = status
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .filter_map(|p| p.parse().ok())
+        .collect();
+
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    let cmdline = if cmdline.is_empty() {
+        // Zombies do not have a command line.
+        format!("[{}]", comm)
+    } else {
+        String::from_utf8_lossy(&cmdline)
+            .trim_end_matches('\0')
+            .replace('\0', " ")
+    };
+    Some(HostProcess {
+        ppid: fields.get(1)?.parse().ok()?,
+        state: fields.first()?.to_string(),
+        cpu_ticks: number(11)? + number(12)?,
+        rss_pages: number(21)?,
+        ns_pids,
+        cmdline,
+    })
+}
+
+// Returns the processes of the sandbox whose init has the given (host) PID.
+fn list(init: i32) -> Result<Vec<Process>, String> {
+    let mut host = BTreeMap::new();
+    let entries = std::fs::read_dir("/proc").map_err(|e| format!("failed to read /proc: {}", e))?;
+    for entry in entries.flatten() {
+        if let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            // Processes may terminate while we iterate.
+            if let Some(p) = read_process(pid) {
+                host.insert(pid, p);
+            }
+        }
+    }
+    let level = match host.get(&init) {
+        Some(p) => p.ns_pids.len() - 1,
+        None => return Err("the sandbox has terminated".to_string()),
+    };
+
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let sandbox_pid = |pid: i32| {
+        host.get(&pid)
+            .and_then(|p| p.ns_pids.get(level).copied())
+            .unwrap_or(0)
+    };
+    let mut processes = Vec::new();
+    let mut queue = vec![init];
+    while let Some(pid) = queue.pop() {
+        let p = &host[&pid];
+        processes.push(Process {
+            pid: sandbox_pid(pid),
+            ppid: if pid == init { 0 } else { sandbox_pid(p.ppid) },
+            host_pid: pid,
+            state: p.state.clone(),
+            cpu_ms: p.cpu_ticks * 1000 / ticks,
+            rss_bytes: p.rss_pages * page_size,
+            cmdline: p.cmdline.clone(),
+        });
+        queue.extend(host.iter().filter(|(_, c)| c.ppid == pid).map(|(c, _)| *c));
+    }
+    processes.sort_by_key(|p| p.pid);
+    Ok(processes)
+}
+
+pub fn run(format: OutputFormat, state_dir: &StateDir, id: &str) -> i32 {
+    let result = state_dir.load(id).and_then(|state| {
+        if state.status != Status::Running && state.status != Status::Created {
+            return Err(format!("sandbox {} is not running", id));
+        }
+        list(state.pid)
+    });
+    let processes = match result {
+        Ok(processes) => processes,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    format.emit(&ProcessList { processes }, |l| {
+        println!(
+            "{:>7} {:>7} {:>7} {:5} {:>9} {:>9} COMMAND",
+            "PID", "PPID", "HOSTPID", "STATE", "CPU", "RSS"
+        );
+        for p in &l.processes {
+            println!(
+                "{:>7} {:>7} {:>7} {:5} {:>8.1}s {:>8}K {}",
+                p.pid,
+                p.ppid,
+                p.host_pid,
+                p.state,
+                p.cpu_ms as f64 / 1000.0,
+                p.rss_bytes / 1024,
+                p.cmdline
+            );
+        }
+    });
+    0
+}
@@ -0,0 +1,59 @@
+// Runs a cbuild.json file on a remote host via ssh. The remote host needs cbuildrt as well.
+// The configuration is read (and validated) locally, but its paths refer to the remote host.
+
+use crate::cli::output::OutputFormat;
+use cbuildrt::{Error, Sandbox};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Quotes a string for POSIX shells.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Shell command that writes the configuration to a temporary file and runs it.
+fn remote_command(config: &str, cbuildrt: &str, format: OutputFormat) -> String {
+    let format = match format {
+        OutputFormat::Human => "human",
+        OutputFormat::Json => "json",
+    };
+    let script = format!(
+        "f=$(mktemp) && trap 'rm -f \"$f\"' EXIT && printf '%s' {} > \"$f\" && {} --output-format {} \"$f\"",
+        quote(config),
+        quote(cbuildrt),
+        format
+    );
+    // The login shell of the remote user is not necessarily a POSIX shell.
+    format!("sh -c {}", quote(&script))
+}
+
+// Returns the exit code of the remote process. The remote cbuildrt reports the result itself.
+pub fn run(format: OutputFormat, matches: &clap::ArgMatches, host: &str) -> Result<i32, Error> {
+    let mut cfg = crate::cli::run::load(matches)?;
+    if let Some(rootfs) = matches.value_of("remote-rootfs") {
+        cfg.rootfs = PathBuf::from(rootfs);
+    }
+    let sandbox = Sandbox::from_config(cfg)?;
+    let config = serde_json::to_string(sandbox.config()).unwrap();
+
+    let mut ssh = Command::new("ssh");
+    // With a terminal, signals (e.g., ^C) reach the remote process.
+    if std::io::stdin().is_terminal() {
+        ssh.arg("-t");
+    }
+    ssh.arg("--").arg(host).arg(remote_command(
+        &config,
+        matches.value_of("remote-cbuildrt").unwrap(),
+        format,
+    ));
+    let status = ssh
+        .status()
+        .map_err(|e| Error::Unsupported(format!("failed to run ssh: {}", e)))?;
+    match status.code() {
+        // ssh itself reports connection errors.
+        Some(255) => Err(Error::Setup(format!("ssh to {} failed", host))),
+        Some(code) => Ok(code),
+        None => Err(Error::Setup("ssh was terminated by a signal".to_string())),
+    }
+}
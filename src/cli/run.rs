@@ -0,0 +1,365 @@
+use crate::cli::attach::Session;
+use crate::cli::logs::Capture;
+use crate::cli::notify::Notifier;
+use crate::cli::output::OutputFormat;
+use crate::cli::state::StateDir;
+use cbuildrt::{Config, Debug, DiskUsage, Error, Event, Handle, Perf, Sandbox, SyscallTrace};
+use nix::fcntl::{FcntlArg, FdFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Instant;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub run_id: Option<String>,
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    pub error_code: Option<&'static str>,
+    // Disk space consumed by the writable layers of the sandbox.
+    pub disk_usage: Vec<DiskUsage>,
+}
+
+// Applies the command line options that override parts of cbuild.json.
+fn apply_overrides(cfg: &mut Config, matches: &clap::ArgMatches) {
+    if matches.is_present("reproducible") {
+        cfg.reproducible = true;
+    }
+    if let Some(path) = matches.value_of("trace-access") {
+        cfg.access_manifest = Some(PathBuf::from(path));
+    }
+    if matches.is_present("trace-syscalls") {
+        let strace = cfg.trace_syscalls.take().and_then(|t| t.strace);
+        cfg.trace_syscalls = Some(SyscallTrace {
+            filter: matches.value_of("trace-syscalls").map(String::from),
+            output: PathBuf::from(matches.value_of("trace-output").unwrap()),
+            strace,
+        });
+    }
+    if matches.is_present("perf") {
+        let perf = cfg.perf.get_or_insert(Perf {
+            output: PathBuf::new(),
+            events: None,
+            call_graph: false,
+            perf: None,
+        });
+        perf.output = PathBuf::from(matches.value_of("perf-output").unwrap());
+    }
+    if matches.is_present("keep-workdir") {
+        cfg.keep_work_dir = true;
+    }
+    if matches.is_present("debug") || matches.is_present("gdbserver") {
+        let debug = cfg.debug.get_or_insert_with(Debug::default);
+        if let Some(path) = matches.value_of("gdbserver") {
+            debug.gdbserver = Some(PathBuf::from(path));
+        }
+    }
+}
+
+// PID of the sandbox's supervisor, which forwards SIGQUIT to init.
+static SUPERVISOR_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_quit(_signal: libc::c_int) {
+    unsafe {
+        libc::kill(SUPERVISOR_PID.load(Ordering::Relaxed), libc::SIGQUIT);
+    }
+}
+
+// On SIGQUIT, the sandbox logs its remaining processes and terminates.
+fn forward_quit_to(handle: &Handle) {
+    SUPERVISOR_PID.store(handle.id() as i32, Ordering::Relaxed);
+    let action = nix::sys::signal::SigAction::new(
+        nix::sys::signal::SigHandler::Handler(forward_quit),
+        nix::sys::signal::SaFlags::SA_RESTART,
+        nix::sys::signal::SigSet::empty(),
+    );
+    unsafe { nix::sys::signal::sigaction(nix::sys::signal::Signal::SIGQUIT, &action) }
+        .expect("failed to install signal handler");
+}
+
+// Written to --ready-fd once the sandbox is set up.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Ready<'a> {
+    init_pid: Option<i32>,
+    run_id: &'a str,
+}
+
+// Takes ownership of the file descriptor that is passed to --ready-fd.
+fn ready_file(fd: &str) -> Result<File, String> {
+    let fd = fd
+        .parse::<RawFd>()
+        .ok()
+        .filter(|fd| *fd > 2)
+        .ok_or_else(|| format!("--ready-fd {} is not a valid file descriptor", fd))?;
+    // The sandbox's processes must not inherit the descriptor.
+    nix::fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+        .map_err(|_| format!("--ready-fd {} is not an open file descriptor", fd))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// Records a run in the state directory: its state, with --capture-output its output
+// (see logs.rs) and, with --detach, the socket of its console (see attach.rs).
+// The entry is removed once the run completes, unless --keep-state is given.
+struct Record<'a> {
+    state_dir: &'a StateDir,
+    config: &'a Path,
+    rootfs: &'a Path,
+    capture: Option<Capture>,
+    session: Option<Session>,
+    registered: bool,
+    keep: bool,
+}
+
+impl Record<'_> {
+    fn started(&mut self, id: &str, pid: i32) {
+        match self
+            .state_dir
+            .register_run(id, pid, self.config, self.rootfs)
+        {
+            Ok(()) => self.registered = true,
+            Err(e) => eprintln!("warning: {}", e),
+        }
+        let registered = self.registered;
+        if let Some(capture) = self.capture.as_mut().filter(|_| registered) {
+            if let Err(e) = capture.keep(self.state_dir, id) {
+                eprintln!("warning: {}", e);
+            }
+        }
+        if let Some(session) = &mut self.session {
+            let dir = self.state_dir.dir(id).ok().filter(|_| registered);
+            session.started(dir, id);
+        }
+    }
+
+    fn finish(self, id: Option<&str>) {
+        let registered = self.registered;
+        if let Some(capture) = self.capture {
+            capture.finish(registered);
+        }
+        if let Some(session) = self.session {
+            session.finish();
+        }
+        if let Some(id) = id.filter(|_| registered) {
+            let result = if self.keep {
+                self.state_dir.mark_stopped(id)
+            } else {
+                self.state_dir.remove(id)
+            };
+            if let Err(e) = result {
+                eprintln!("warning: {}", e);
+            }
+        }
+    }
+}
+
+// Like Handle::wait() but records the sandbox in the state directory, reports when the
+// sandbox is ready (to the service manager and/or to --ready-fd), keeps the service manager
+// informed about the phase of the run and sends watchdog keepalives while the process is
+// running.
+fn wait_reporting(
+    handle: &mut Handle,
+    mut record: Record,
+    notifier: Option<&Notifier>,
+    mut ready: Option<File>,
+    command: &str,
+) -> Result<i32, Error> {
+    let timeout = notifier
+        .and_then(Notifier::keepalive_interval)
+        .map_or(-1, |interval| interval.as_millis().max(1) as i32);
+    let mut init_pid = None;
+    let result = loop {
+        let mut fds = [
+            PollFd::new(handle.events_fd(), PollFlags::POLLIN),
+            PollFd::new(handle.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, timeout) {
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            result => result.expect("failed to poll sandbox"),
+        };
+        for event in handle.events() {
+            match event {
+                Event::Started { init_pid: pid, .. } => {
+                    init_pid = Some(pid);
+                    record.started(handle.run_id(), pid);
+                }
+                Event::Ready => {
+                    if let Some(notifier) = notifier {
+                        notifier.ready(&format!("running {} (run {})", command, handle.run_id()));
+                    }
+                    // The descriptor is closed after writing, which signals EOF to readers.
+                    if let Some(mut file) = ready.take() {
+                        let mut line = serde_json::to_vec(&Ready {
+                            init_pid,
+                            run_id: handle.run_id(),
+                        })
+                        .unwrap();
+                        line.push(b'\n');
+                        if let Err(e) = file.write_all(&line) {
+                            eprintln!("warning: failed to write to --ready-fd: {}", e);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        match handle.try_wait() {
+            Ok(None) => (),
+            result => break result.map(Option::unwrap),
+        }
+        if let Some(notifier) = notifier.filter(|_| timeout >= 0) {
+            notifier.keepalive();
+        }
+    };
+    record.finish(Some(handle.run_id()));
+    if let (Some(notifier), Ok(code)) = (notifier, &result) {
+        notifier.status(&format!("{} exited with code {}", command, code));
+    }
+    result
+}
+
+// Reads the cbuild.json file and applies the command line options.
+pub fn load(matches: &clap::ArgMatches) -> Result<Config, Error> {
+    let mut cfg = crate::cli::load_config(Path::new(matches.value_of("cbuild-json").unwrap()))?;
+    apply_overrides(&mut cfg, matches);
+    Ok(cfg)
+}
+
+// Stores the ID of the run in run_id once the sandbox has been started
+// and the disk usage of its writable layers once it has terminated.
+fn load_and_run(
+    matches: &clap::ArgMatches,
+    state_dir: &StateDir,
+    ready: Option<File>,
+    mut session: Option<Session>,
+    run_id: &mut Option<String>,
+    disk_usage: &mut Vec<DiskUsage>,
+) -> Result<i32, Error> {
+    let notifier = Notifier::from_env();
+    let cfg = load(matches)?;
+    if session.is_some() && !cfg.console {
+        return Err(Error::InvalidConfig(
+            "--detach requires console to be enabled".to_string(),
+        ));
+    }
+    // Unlike traces, the captured output is not scrubbed of the secrets' values (see secrets.rs).
+    if matches.is_present("capture-output") && !cfg.secrets.is_empty() {
+        return Err(Error::InvalidConfig(
+            "--capture-output cannot be used together with secrets".to_string(),
+        ));
+    }
+    let command = match &cfg.process.script {
+        Some(_) => "process.script".to_string(),
+        None => cfg.process.args.first().cloned().unwrap_or_default(),
+    };
+    if let Some(notifier) = &notifier {
+        notifier.status(&format!("setting up sandbox for {}", command));
+    }
+    let mut sandbox = Sandbox::from_config(cfg)?;
+    if matches.is_present("wait-for-rootfs") {
+        sandbox = sandbox.wait_if_locked();
+    }
+    // Without --capture-output, the sandbox inherits cbuildrt's stdout and stderr
+    // (e.g., such that tools can detect terminals).
+    let spawn = || {
+        if !matches.is_present("capture-output") {
+            return (None, sandbox.spawn());
+        }
+        match Capture::around(state_dir, || sandbox.spawn()) {
+            Ok((capture, spawned)) => (Some(capture), spawned),
+            Err(e) => {
+                eprintln!("warning: {}", e);
+                (None, sandbox.spawn())
+            }
+        }
+    };
+    // The session's pipes become the stdio that Capture copies the output to.
+    let (capture, spawned) = match &mut session {
+        Some(session) => session.around(spawn),
+        None => spawn(),
+    };
+    let record = Record {
+        state_dir,
+        config: Path::new(matches.value_of("cbuild-json").unwrap()),
+        rootfs: &sandbox.config().rootfs,
+        capture,
+        session,
+        registered: false,
+        keep: matches.is_present("keep-state"),
+    };
+    let mut handle = match spawned {
+        Ok(handle) => handle,
+        Err(e) => {
+            record.finish(None);
+            return Err(e);
+        }
+    };
+    *run_id = Some(handle.run_id().to_string());
+    forward_quit_to(&handle);
+    let result = wait_reporting(&mut handle, record, notifier.as_ref(), ready, &command);
+    *disk_usage = handle.disk_usage().to_vec();
+    result
+}
+
+// Runs a cbuild.json file and returns the exit code of the process.
+pub fn run(format: OutputFormat, matches: &clap::ArgMatches, state_dir: &StateDir) -> i32 {
+    let ready = match matches.value_of("ready-fd").map(ready_file) {
+        Some(Ok(file)) => Some(file),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+        None => None,
+    };
+    let session = if matches.is_present("detach") {
+        match Session::detach(format, state_dir) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+    let start = Instant::now();
+    let mut run_id = None;
+    let mut disk_usage = Vec::new();
+    let result = match matches.value_of("remote") {
+        Some(host) => match crate::cli::remote::run(format, matches, host) {
+            // The remote cbuildrt reports the result itself.
+            Ok(code) => return code,
+            Err(e) => Err(e),
+        },
+        None => load_and_run(
+            matches,
+            state_dir,
+            ready,
+            session,
+            &mut run_id,
+            &mut disk_usage,
+        ),
+    };
+    let summary = Summary {
+        run_id,
+        exit_code: *result.as_ref().unwrap_or(&1),
+        duration_ms: start.elapsed().as_millis() as u64,
+        error: result.as_ref().err().map(|e| e.to_string()),
+        error_code: result.as_ref().err().map(Error::code),
+        disk_usage,
+    };
+    // The human-readable output only consists of the process' own output
+    // (and diagnostics of the runtime).
+    format.emit(&summary, |_| {
+        if let Err(e) = &result {
+            crate::cli::explain::report(e);
+        }
+    });
+    summary.exit_code
+}
@@ -0,0 +1,316 @@
+// End-to-end test of the runtime on the current host.
+// The sandbox uses a throwaway rootfs that consists of the cbuildrt binary itself
+// (and the shared objects that it is linked against). Inside the sandbox, the binary
+// runs the hidden probe subcommand, which checks the environment and reports the results
+// through a bind mounted directory.
+
+use crate::cli::output::OutputFormat;
+use cbuildrt::Sandbox;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Name of the hidden subcommand that runs inside the sandbox.
+pub const PROBE: &str = "__self-test-probe";
+
+const NAMESPACES: &[&str] = &["user", "pid", "mnt"];
+
+#[derive(Serialize, Deserialize)]
+struct Capability {
+    name: String,
+    ok: bool,
+    detail: String,
+}
+
+fn capability<S: Into<String>>(name: &str, result: Result<S, String>) -> Capability {
+    let (ok, detail) = match result {
+        Ok(detail) => (true, detail.into()),
+        Err(detail) => (false, detail),
+    };
+    Capability {
+        name: name.to_string(),
+        ok,
+        detail,
+    }
+}
+
+fn namespace_id(ns: &str) -> String {
+    std::fs::read_link(format!("/proc/self/ns/{}", ns))
+        .map(|link| link.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Returns the path of the ELF interpreter (PT_INTERP) of a binary, if it has one.
+fn interpreter(binary: &Path) -> std::io::Result<Option<PathBuf>> {
+    let mut elf = Vec::new();
+    std::fs::File::open(binary)?.read_to_end(&mut elf)?;
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed ELF file");
+    let read = |offset: usize, size: usize| -> std::io::Result<u64> {
+        let bytes = elf.get(offset..offset + size).ok_or_else(invalid)?;
+        let mut value = [0u8; 8];
+        value[..size].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(value))
+    };
+    // (e_phoff, e_phentsize, e_phnum, p_offset, p_filesz) offsets of ELFCLASS32 and ELFCLASS64.
+    let (phoff, phentsize, phnum, offset, filesz, width) = match elf.get(4) {
+        Some(1) => (0x1c, 0x2a, 0x2c, 0x4, 0x10, 4),
+        Some(2) => (0x20, 0x36, 0x38, 0x8, 0x20, 8),
+        _ => return Err(invalid()),
+    };
+    let phoff = read(phoff, width)? as usize;
+    let phentsize = read(phentsize, 2)? as usize;
+    for i in 0..read(phnum, 2)? as usize {
+        let header = phoff + i * phentsize;
+        // PT_INTERP.
+        if read(header, 4)? != 3 {
+            continue;
+        }
+        let start = read(header + offset, width)? as usize;
+        let size = read(header + filesz, width)? as usize;
+        let path = elf.get(start..start + size).ok_or_else(invalid)?;
+        let path = String::from_utf8_lossy(path)
+            .trim_end_matches('\0')
+            .to_string();
+        return Ok(Some(PathBuf::from(path)));
+    }
+    Ok(None)
+}
+
+// Returns the shared objects that are mapped into this process.
+fn loaded_objects() -> Vec<PathBuf> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    let mut objects: Vec<PathBuf> = maps
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(5))
+        .filter(|path| path.starts_with('/') && path.contains(".so"))
+        .map(PathBuf::from)
+        .collect();
+    objects.sort();
+    objects.dedup();
+    objects
+}
+
+fn copy_into(rootfs: &Path, host_path: &Path) -> std::io::Result<PathBuf> {
+    let target = rootfs.join(host_path.strip_prefix("/").unwrap());
+    std::fs::create_dir_all(target.parent().unwrap())?;
+    std::fs::copy(host_path, &target)?;
+    Ok(target)
+}
+
+// Creates the throwaway rootfs. Returns the command line of the probe inside the sandbox.
+fn make_rootfs(rootfs: &Path) -> std::io::Result<Vec<String>> {
+    for dir in &["dev/pts", "dev/shm", "proc", "run", "tmp", "mnt", "bin"] {
+        std::fs::create_dir_all(rootfs.join(dir))?;
+    }
+    // Mount points of the /dev overlays.
+    for dev in &["tty", "null", "zero", "full", "random", "urandom"] {
+        std::fs::File::create(rootfs.join("dev").join(dev))?;
+    }
+
+    let exe = std::env::current_exe()?;
+    std::fs::copy(&exe, rootfs.join("bin/cbuildrt"))?;
+    let mut args = Vec::new();
+    // The rootfs has no ld.so.cache; hence, run the dynamic linker explicitly.
+    if let Some(interpreter) = interpreter(&exe)? {
+        copy_into(rootfs, &interpreter)?;
+        let mut library_path = Vec::new();
+        for object in loaded_objects() {
+            copy_into(rootfs, &object)?;
+            let dir = object.parent().unwrap().to_string_lossy().into_owned();
+            if !library_path.contains(&dir) {
+                library_path.push(dir);
+            }
+        }
+        args.push(interpreter.to_string_lossy().into_owned());
+        args.push("--library-path".to_string());
+        args.push(library_path.join(":"));
+    }
+    args.push("/bin/cbuildrt".to_string());
+    args.push(PROBE.to_string());
+    for ns in NAMESPACES {
+        args.push(namespace_id(ns));
+    }
+    Ok(args)
+}
+
+fn run_sandbox(dir: &Path) -> Result<Vec<Capability>, String> {
+    let rootfs = dir.join("rootfs");
+    let shared = dir.join("shared");
+    std::fs::create_dir(&shared).map_err(|e| e.to_string())?;
+    std::fs::write(shared.join("marker"), "cbuildrt").map_err(|e| e.to_string())?;
+    let args = make_rootfs(&rootfs)
+        .map_err(|e| format!("failed to create rootfs {}: {}", rootfs.display(), e))?;
+
+    let code = Sandbox::builder()
+        .rootfs(&rootfs)
+        .user(0, 0)
+        .args(args)
+        .isolate_network(true)
+        .bind_mount(&shared, "/mnt")
+        .build()
+        .and_then(|sandbox| sandbox.run())
+        .map_err(|e| e.to_string())?;
+    let results = std::fs::File::open(shared.join("results.json"))
+        .map_err(|_| format!("the probe exited with code {} without results", code))?;
+    serde_json::from_reader(results).map_err(|e| e.to_string())
+}
+
+pub fn run(format: OutputFormat) -> i32 {
+    let mut capabilities: Vec<Capability> = cbuildrt::check_host()
+        .into_iter()
+        .filter(|check| check.required)
+        .map(|check| Capability {
+            name: check.name.to_string(),
+            ok: check.ok,
+            detail: check.detail,
+        })
+        .collect();
+
+    let dir = std::env::temp_dir().join(format!("cbuildrt-self-test.{}", nix::unistd::getpid()));
+    if let Err(e) = std::fs::create_dir(&dir) {
+        eprintln!("failed to create {}: {}", dir.display(), e);
+        return 1;
+    }
+    match run_sandbox(&dir) {
+        Ok(results) => {
+            capabilities.push(capability("sandbox", Ok("the sandbox ran")));
+            capabilities.extend(results);
+        }
+        Err(e) => capabilities.push(capability::<String>("sandbox", Err(e))),
+    }
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        eprintln!("failed to remove {}: {}", dir.display(), e);
+    }
+
+    format.emit(&capabilities, |capabilities| {
+        for c in capabilities.iter() {
+            let status = if c.ok { "ok" } else { "FAIL" };
+            println!("{:<16} {:<12} {}", c.name, status, c.detail);
+        }
+    });
+    if capabilities.iter().all(|c| c.ok) {
+        0
+    } else {
+        1
+    }
+}
+
+fn check_namespace(ns: &str, host_id: &str) -> Result<String, String> {
+    let id = namespace_id(ns);
+    if id.is_empty() || id == host_id {
+        return Err(format!("the sandbox shares the host's {} namespace", ns));
+    }
+    Ok(format!("{} (host: {})", id, host_id))
+}
+
+fn check_user_mapping() -> Result<String, String> {
+    let map = std::fs::read_to_string("/proc/self/uid_map").map_err(|e| e.to_string())?;
+    let uid = nix::unistd::getuid();
+    if !uid.is_root() {
+        return Err(format!("uid is {} instead of 0", uid));
+    }
+    Ok(format!(
+        "uid_map: {}",
+        map.split_whitespace().collect::<Vec<_>>().join(" ")
+    ))
+}
+
+fn check_pid() -> Result<String, String> {
+    let (pid, ppid) = (nix::unistd::getpid(), nix::unistd::getppid());
+    if ppid.as_raw() != 1 {
+        return Err(format!("parent is PID {} instead of init", ppid));
+    }
+    Ok(format!("running as PID {} below init", pid))
+}
+
+fn check_read_only_rootfs() -> Result<String, String> {
+    match std::fs::File::create("/probe") {
+        Ok(_) => Err("/ is writable".to_string()),
+        Err(e) if e.raw_os_error() == Some(libc::EROFS) => Ok("/ is read-only"),
+        Err(e) => Err(format!("unexpected error when writing to /: {}", e)),
+    }
+    .map(String::from)
+}
+
+fn check_bind_mount() -> Result<String, String> {
+    match std::fs::read_to_string("/mnt/marker") {
+        Ok(marker) if marker == "cbuildrt" => Ok("host directory is visible at /mnt".to_string()),
+        Ok(_) => Err("/mnt/marker has unexpected contents".to_string()),
+        Err(e) => Err(format!("failed to read /mnt/marker: {}", e)),
+    }
+}
+
+fn check_dev_overlays() -> Result<String, String> {
+    let mut zeros = [1u8; 16];
+    std::fs::File::open("/dev/zero")
+        .and_then(|mut f| f.read_exact(&mut zeros))
+        .map_err(|e| format!("/dev/zero: {}", e))?;
+    if zeros.iter().any(|b| *b != 0) {
+        return Err("/dev/zero returned non-zero bytes".to_string());
+    }
+    let mut random = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut random))
+        .map_err(|e| format!("/dev/urandom: {}", e))?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/null")
+        .and_then(|mut f| f.write_all(b"cbuildrt"))
+        .map_err(|e| format!("/dev/null: {}", e))?;
+    let full = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/full")
+        .and_then(|mut f| f.write_all(b"cbuildrt"));
+    if full.is_ok() {
+        return Err("/dev/full accepted writes".to_string());
+    }
+    Ok("/dev/null, /dev/zero, /dev/full and /dev/urandom work".to_string())
+}
+
+fn check_proc() -> Result<String, String> {
+    let status = std::fs::read_to_string("/proc/self/status").map_err(|e| e.to_string())?;
+    if !status.lines().any(|l| l.starts_with("Pid:")) {
+        return Err("/proc/self/status is malformed".to_string());
+    }
+    Ok("/proc is mounted".to_string())
+}
+
+fn check_tmpfs() -> Result<String, String> {
+    for dir in &["/tmp", "/run", "/dev/shm"] {
+        let fs = nix::sys::statfs::statfs(*dir).map_err(|e| format!("{}: {}", dir, e))?;
+        if fs.filesystem_type() != nix::sys::statfs::TMPFS_MAGIC {
+            return Err(format!("{} is not a tmpfs", dir));
+        }
+        std::fs::write(Path::new(dir).join("probe"), "cbuildrt")
+            .map_err(|e| format!("{} is not writable: {}", dir, e))?;
+    }
+    Ok("/tmp, /run and /dev/shm are writable tmpfs mounts".to_string())
+}
+
+// Runs inside the sandbox. host_namespaces are the IDs of the host's namespaces (see NAMESPACES).
+pub fn probe(host_namespaces: &[&str]) -> i32 {
+    let mut results = Vec::new();
+    for (ns, host_id) in NAMESPACES.iter().zip(host_namespaces) {
+        results.push(capability(
+            &format!("{}Namespace", ns),
+            check_namespace(ns, host_id),
+        ));
+    }
+    results.push(capability("userMapping", check_user_mapping()));
+    results.push(capability("pid", check_pid()));
+    results.push(capability("readOnlyRootfs", check_read_only_rootfs()));
+    results.push(capability("bindMount", check_bind_mount()));
+    results.push(capability("devOverlays", check_dev_overlays()));
+    results.push(capability("proc", check_proc()));
+    results.push(capability("tmpfs", check_tmpfs()));
+
+    let f = match std::fs::File::create("/mnt/results.json") {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("failed to create /mnt/results.json: {}", e);
+            return 1;
+        }
+    };
+    serde_json::to_writer(f, &results).unwrap();
+    0
+}
@@ -0,0 +1,257 @@
+// State directory of sandboxes that outlive a single cbuildrt invocation
+// (e.g., OCI containers between create and delete).
+
+use cbuildrt::ProcessRef;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+pub const OCI_VERSION: &str = "1.0.2";
+
+// Start time of init, which tells init apart from a process that reuses its PID.
+const START_TIME_ANNOTATION: &str = "org.managarm.cbuildrt.start-time";
+// Absolute path of the rootfs, which commit and diff use to find the overlayfs mount.
+const ROOTFS_ANNOTATION: &str = "org.managarm.cbuildrt.rootfs";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    Creating,
+    Created,
+    Running,
+    Stopped,
+}
+
+// The state of a sandbox; the format follows the OCI runtime spec.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct State {
+    pub oci_version: String,
+    pub id: String,
+    pub status: Status,
+    // PID of the sandbox's init (outside of the sandbox's PID namespace).
+    #[serde(default)]
+    pub pid: i32,
+    pub bundle: PathBuf,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl State {
+    // Records the PID of init (together with its start time).
+    pub fn set_pid(&mut self, pid: i32) {
+        self.pid = pid;
+        match start_time(pid) {
+            Some(time) => self
+                .annotations
+                .insert(START_TIME_ANNOTATION.to_string(), time),
+            None => self.annotations.remove(START_TIME_ANNOTATION),
+        };
+    }
+
+    pub fn set_rootfs(&mut self, rootfs: &Path) {
+        let rootfs = std::fs::canonicalize(rootfs).unwrap_or_else(|_| rootfs.to_path_buf());
+        self.annotations.insert(
+            ROOTFS_ANNOTATION.to_string(),
+            rootfs.to_string_lossy().into_owned(),
+        );
+    }
+
+    // Only commit and diff (which are Linux-only) need the rootfs.
+    #[cfg(target_os = "linux")]
+    pub fn rootfs(&self) -> Option<PathBuf> {
+        self.annotations.get(ROOTFS_ANNOTATION).map(PathBuf::from)
+    }
+
+    // Opens init such that it can be signaled without racing against the reuse of its PID.
+    pub fn open_init(&self) -> Result<ProcessRef, String> {
+        let not_running = || format!("sandbox {} is not running", self.id);
+        if self.pid <= 0 {
+            return Err(not_running());
+        }
+        let init = ProcessRef::open(self.pid as u32).map_err(|_| not_running())?;
+        // The PID may have been reused before it was opened.
+        match self.annotations.get(START_TIME_ANNOTATION) {
+            Some(time) if start_time(self.pid).as_ref() != Some(time) => Err(not_running()),
+            _ => Ok(init),
+        }
+    }
+}
+
+// Start time of a process in clock ticks since boot (see proc(5)).
+fn start_time(pid: i32) -> Option<String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = stat.rsplit_once(')')?.1;
+    fields.split_whitespace().nth(19).map(|s| s.to_string())
+}
+
+// Fails if a directory of the state directory could have been planted or modified by another
+// user (e.g., in the predictable fallback below /tmp), as ps, kill and attach act on its
+// entries. Directories that do not exist are accepted; the callers report them.
+fn check_private(dir: &Path) -> Result<(), String> {
+    let metadata = match std::fs::symlink_metadata(dir) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("failed to access {}: {}", dir.display(), e)),
+    };
+    if metadata.file_type().is_symlink() {
+        return Err(format!("{} is a symbolic link", dir.display()));
+    }
+    if !metadata.file_type().is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    if metadata.uid() != nix::unistd::geteuid().as_raw() {
+        return Err(format!(
+            "{} is not owned by the current user",
+            dir.display()
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(format!("{} is writable by other users", dir.display()));
+    }
+    Ok(())
+}
+
+// Creates a directory that is only accessible to the current user.
+fn create_private(dir: &Path) -> std::io::Result<()> {
+    std::fs::DirBuilder::new().mode(0o700).create(dir)
+}
+
+pub struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    // Uses the given directory, or a per-user directory below XDG_RUNTIME_DIR (or /tmp).
+    pub fn new(root: Option<&str>) -> StateDir {
+        let root = match (root, std::env::var_os("XDG_RUNTIME_DIR")) {
+            (Some(root), _) => PathBuf::from(root),
+            (None, Some(runtime_dir)) => Path::new(&runtime_dir).join("cbuildrt"),
+            (None, None) => PathBuf::from(format!("/tmp/cbuildrt-{}", nix::unistd::getuid())),
+        };
+        StateDir { root }
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, String> {
+        if id.is_empty() || id.contains('/') || id.starts_with('.') {
+            return Err(format!("invalid sandbox ID {:?}", id));
+        }
+        Ok(self.root.join(id))
+    }
+
+    // Returns the directory of a sandbox after checking that it can be trusted.
+    pub fn dir(&self, id: &str) -> Result<PathBuf, String> {
+        let dir = self.path(id)?;
+        check_private(&self.root)?;
+        check_private(&dir)?;
+        Ok(dir)
+    }
+
+    // Creates the state directory itself (but not its parents) such that only the current
+    // user can access it.
+    fn create_root(&self) -> Result<(), String> {
+        let result = match self.root.parent() {
+            Some(parent) => std::fs::create_dir_all(parent),
+            None => Ok(()),
+        };
+        match result.and_then(|()| create_private(&self.root)) {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+                return Err(format!("failed to create {}: {}", self.root.display(), e))
+            }
+            _ => (),
+        }
+        check_private(&self.root)
+    }
+
+    // Returns the path of a file that is not associated with a sandbox (yet).
+    pub fn temp_file(&self, name: &str) -> Result<PathBuf, String> {
+        self.create_root()?;
+        Ok(self.root.join(format!(".{}", name)))
+    }
+
+    // Creates the directory of a new sandbox.
+    pub fn create(&self, id: &str) -> Result<PathBuf, String> {
+        let dir = self.path(id)?;
+        self.create_root()?;
+        create_private(&dir).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!("sandbox {} already exists", id),
+            _ => format!("failed to create {}: {}", dir.display(), e),
+        })?;
+        Ok(dir)
+    }
+
+    // Loads the state of a sandbox. Sandboxes whose init has terminated are reported as stopped.
+    pub fn load(&self, id: &str) -> Result<State, String> {
+        let path = self.dir(id)?.join("state.json");
+        let f = std::fs::File::open(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!("sandbox {} does not exist", id),
+            _ => format!("failed to open {}: {}", path.display(), e),
+        })?;
+        let mut state: State = serde_json::from_reader(f)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        let started = state.status == Status::Created || state.status == Status::Running;
+        if started && state.open_init().is_err() {
+            state.status = Status::Stopped;
+        }
+        Ok(state)
+    }
+
+    // Stores the state of a sandbox. The file is replaced atomically.
+    pub fn store(&self, state: &State) -> Result<(), String> {
+        let dir = self.dir(&state.id)?;
+        let temp = dir.join("state.json.tmp");
+        let result = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::to_writer(f, state).map_err(|e| e.to_string()))
+            .and_then(|_| {
+                std::fs::rename(&temp, dir.join("state.json")).map_err(|e| e.to_string())
+            });
+        result.map_err(|e| format!("failed to store state of {}: {}", state.id, e))
+    }
+
+    // Records a run of a cbuild.json file, such that other subcommands (e.g., ps and logs)
+    // can find it. The bundle is the path of the configuration. Like the entries of OCI
+    // containers, the entry is only accessible to the current user (see create()); runs are
+    // not recorded if the state directory cannot be trusted.
+    pub fn register_run(
+        &self,
+        id: &str,
+        pid: i32,
+        config: &Path,
+        rootfs: &Path,
+    ) -> Result<(), String> {
+        self.create(id)?;
+        let mut state = State {
+            oci_version: OCI_VERSION.to_string(),
+            id: id.to_string(),
+            status: Status::Running,
+            pid: 0,
+            bundle: config.to_path_buf(),
+            annotations: BTreeMap::new(),
+        };
+        state.set_pid(pid);
+        state.set_rootfs(rootfs);
+        self.store(&state).inspect_err(|_| {
+            let _ = self.remove(id);
+        })
+    }
+
+    // Marks a sandbox as stopped. The entry is kept until the sandbox is deleted.
+    pub fn mark_stopped(&self, id: &str) -> Result<(), String> {
+        let mut state = self.load(id)?;
+        state.status = Status::Stopped;
+        self.store(&state)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<(), String> {
+        let dir = self.dir(id)?;
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| format!("failed to remove {}: {}", dir.display(), e))
+    }
+}
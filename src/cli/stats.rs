@@ -0,0 +1,123 @@
+// Periodically prints the resource usage of a running sandbox's cgroup until the sandbox exits.
+
+use crate::cli::output::OutputFormat;
+use crate::cli::state::{StateDir, Status};
+use cbuildrt::CgroupStats;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sample {
+    // Average CPU usage since the previous sample, in percent of a single CPU.
+    cpu_percent: Option<f64>,
+    #[serde(flatten)]
+    stats: CgroupStats,
+}
+
+// Parses intervals like 2s, 500ms or 1m (plain numbers are seconds).
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else if let Some(mins) = s.strip_suffix('m') {
+        (mins, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(Duration::from_secs_f64(n * scale)),
+        _ => Err(format!("invalid interval {}", s)),
+    }
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+// Formats a value together with its limit (if any).
+fn with_limit(value: Option<u64>, max: Option<u64>, fmt: fn(u64) -> String) -> String {
+    match (value, max) {
+        (Some(value), Some(max)) => format!("{} / {}", fmt(value), fmt(max)),
+        (Some(value), None) => fmt(value),
+        (None, _) => "-".to_string(),
+    }
+}
+
+fn print_human(sample: &Sample) {
+    let stats = &sample.stats;
+    let io = match (stats.io_read_bytes, stats.io_write_bytes) {
+        (Some(read), Some(write)) => format!("{} / {}", mib(read), mib(write)),
+        _ => "-".to_string(),
+    };
+    println!(
+        "{:>8} {:>25} {:>13} {:>25}",
+        sample
+            .cpu_percent
+            .map_or("-".to_string(), |p| format!("{:.1}%", p)),
+        with_limit(stats.memory_bytes, stats.memory_max, mib),
+        with_limit(stats.pids, stats.pids_max, |n| n.to_string()),
+        io
+    );
+}
+
+pub fn run(format: OutputFormat, state_dir: &StateDir, id: &str, interval: &str) -> i32 {
+    let interval = match parse_interval(interval) {
+        Ok(interval) => interval,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let is_running = || {
+        state_dir
+            .load(id)
+            .map(|state| (state.status != Status::Stopped).then_some(state.pid))
+    };
+    let pid = match is_running() {
+        Ok(Some(pid)) => pid,
+        Ok(None) => {
+            eprintln!("sandbox {} is not running", id);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    if format == OutputFormat::Human {
+        println!(
+            "{:>8} {:>25} {:>13} {:>25}",
+            "CPU", "MEMORY / LIMIT", "PIDS / LIMIT", "IO READ / WRITE"
+        );
+    }
+    let mut previous: Option<(Instant, u64)> = None;
+    loop {
+        // The cgroup disappears once the sandbox exits.
+        let stats = match cbuildrt::cgroup_stats(pid) {
+            Ok(stats) => stats,
+            Err(_) if !matches!(is_running(), Ok(Some(_))) => return 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        let now = Instant::now();
+        let cpu_percent = match (previous, stats.cpu_usec) {
+            (Some((then, before)), Some(usec)) => Some(
+                usec.saturating_sub(before) as f64 / now.duration_since(then).as_micros() as f64
+                    * 100.0,
+            ),
+            _ => None,
+        };
+        previous = stats.cpu_usec.map(|usec| (now, usec));
+        format.emit(&Sample { cpu_percent, stats }, print_human);
+
+        std::thread::sleep(interval);
+        if !matches!(is_running(), Ok(Some(_))) {
+            return 0;
+        }
+    }
+}
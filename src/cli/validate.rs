@@ -0,0 +1,31 @@
+use crate::cli::output::OutputFormat;
+use cbuildrt::{Error, Sandbox};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Validation {
+    valid: bool,
+    error: Option<String>,
+    error_code: Option<&'static str>,
+}
+
+// Checks that a cbuild.json file can be parsed and is consistent, without running it.
+pub fn run(format: OutputFormat, path: &Path) -> i32 {
+    let result = crate::cli::load_config(path).and_then(Sandbox::from_config);
+    let validation = Validation {
+        valid: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        error_code: result.as_ref().err().map(Error::code),
+    };
+    format.emit(&validation, |_| match &result {
+        Ok(_) => println!("{} is valid", path.display()),
+        Err(e) => crate::cli::explain::report(e),
+    });
+    if validation.valid {
+        0
+    } else {
+        1
+    }
+}
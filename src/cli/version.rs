@@ -0,0 +1,44 @@
+use crate::cli::output::OutputFormat;
+use serde::Serialize;
+
+// Output of --version: the crate version followed by the build metadata (see build.rs).
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("CBUILDRT_GIT_COMMIT"),
+    "\nbuild date: ",
+    env!("CBUILDRT_BUILD_DATE"),
+    "\ntarget: ",
+    env!("CBUILDRT_TARGET"),
+    " (",
+    env!("CBUILDRT_PROFILE"),
+    ")\nfeatures: ",
+    env!("CBUILDRT_FEATURES_DISPLAY"),
+);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Version {
+    version: &'static str,
+    git_commit: &'static str,
+    build_date: &'static str,
+    target: &'static str,
+    profile: &'static str,
+    features: Vec<&'static str>,
+}
+
+pub fn run(format: OutputFormat) -> i32 {
+    let version = Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("CBUILDRT_GIT_COMMIT"),
+        build_date: env!("CBUILDRT_BUILD_DATE"),
+        target: env!("CBUILDRT_TARGET"),
+        profile: env!("CBUILDRT_PROFILE"),
+        features: env!("CBUILDRT_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect(),
+    };
+    format.emit(&version, |_| println!("cbuildrt {}", LONG_VERSION));
+    0
+}
@@ -0,0 +1,236 @@
+// Re-runs a cbuild.json file whenever watched host paths change.
+// The next sandbox is prepared while waiting: it is set up entirely and only waits for
+// its start gate to be opened, which keeps the latency of re-runs low.
+
+use crate::cli::output::OutputFormat;
+use crate::cli::run::Summary;
+use cbuildrt::{Error, Event, Handle, Sandbox};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+// Set on SIGINT and SIGTERM, which stop watching.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+// Changes that arrive within this time (in ms) after a change trigger only a single run.
+const DEBOUNCE_MS: i32 = 100;
+
+struct Watcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> nix::Result<Watcher> {
+        Ok(Watcher {
+            inotify: Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?,
+            dirs: HashMap::new(),
+        })
+    }
+
+    // Watches path and (if it is a directory) all directories below it.
+    fn add(&mut self, path: &Path) -> nix::Result<()> {
+        let flags = AddWatchFlags::IN_MODIFY
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO;
+        let wd = self.inotify.add_watch(path, flags)?;
+        if !path.is_dir() {
+            return Ok(());
+        }
+        self.dirs.insert(wd, path.to_path_buf());
+        for entry in std::fs::read_dir(path).into_iter().flatten().flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.add(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reads pending events. Returns true if there were any.
+    fn drain(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(events) = self.inotify.read_events() {
+            for event in events {
+                changed = true;
+                // New directories need watches of their own.
+                if event
+                    .mask
+                    .contains(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ISDIR)
+                {
+                    if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), &event.name) {
+                        let _ = self.add(&dir.join(name));
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    // Blocks until a change occurs and no further changes follow within DEBOUNCE_MS.
+    // Returns false if watching was stopped instead.
+    fn wait_for_change(&mut self) -> bool {
+        let mut timeout = -1;
+        loop {
+            let mut fds = [PollFd::new(self.inotify.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, timeout) {
+                // poll() is not restarted after signal handlers.
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) if STOP.load(Ordering::Relaxed) => {
+                    return false
+                }
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Ok(0) => return true,
+                result => result.expect("failed to poll inotify"),
+            };
+            if self.drain() {
+                timeout = DEBOUNCE_MS;
+            }
+        }
+    }
+}
+
+// Spawns a sandbox and waits until it is ready to execute the process.
+fn prepare(path: &Path, fifo: &Path) -> Result<Handle, Error> {
+    let cfg = crate::cli::load_config(path)?;
+    let mut handle = Sandbox::from_config(cfg)?.start_gate(fifo).spawn()?;
+    loop {
+        let mut fds = [
+            PollFd::new(handle.events_fd(), PollFlags::POLLIN),
+            PollFd::new(handle.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            result => result.expect("failed to poll sandbox"),
+        };
+        if handle.events().iter().any(|e| matches!(e, Event::Ready)) {
+            return Ok(handle);
+        }
+        if let Some(code) = handle.try_wait()? {
+            return Err(Error::Setup(format!(
+                "sandbox terminated with exit code {} before it was started",
+                code
+            )));
+        }
+    }
+}
+
+// Opens the start gate of a prepared sandbox and waits for the process.
+fn execute(handle: &mut Handle, fifo: &Path) -> Result<i32, Error> {
+    // Unblocks the process, which waits for a reader of the FIFO.
+    std::fs::read(fifo)
+        .map_err(|e| Error::Setup(format!("failed to open {}: {}", fifo.display(), e)))?;
+    handle.wait()
+}
+
+// Errors are reported separately (see explain::report()).
+fn report(format: OutputFormat, summary: &Summary) {
+    format.emit(summary, |s| {
+        if s.error.is_none() {
+            eprintln!(
+                "run {} exited with code {} ({} ms)",
+                s.run_id.as_deref().unwrap_or("-"),
+                s.exit_code,
+                s.duration_ms
+            );
+        }
+    });
+}
+
+fn watch(format: OutputFormat, path: &Path, paths: &[&str], fifo: &Path) -> Result<(), String> {
+    let mut watcher = Watcher::new().map_err(|e| format!("failed to set up inotify: {}", e))?;
+    for p in paths {
+        watcher
+            .add(Path::new(p))
+            .map_err(|e| format!("failed to watch {}: {}", p, e))?;
+    }
+
+    // The first run and retries after failed preparations do not wait for a change.
+    let mut run_now = true;
+    loop {
+        let mut run_id = None;
+        let mut disk_usage = Vec::new();
+        let mut start = Instant::now();
+        let result = match prepare(path, fifo) {
+            Ok(mut handle) => {
+                if !run_now && !watcher.wait_for_change() {
+                    let _ = handle.kill();
+                    let _ = handle.wait();
+                    return Ok(());
+                }
+                run_id = Some(handle.run_id().to_string());
+                start = Instant::now();
+                let result = execute(&mut handle, fifo);
+                disk_usage = handle.disk_usage().to_vec();
+                result
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            if format == OutputFormat::Human {
+                crate::cli::explain::report(e);
+            }
+        }
+        report(
+            format,
+            &Summary {
+                run_id: run_id.clone(),
+                exit_code: *result.as_ref().unwrap_or(&1),
+                duration_ms: start.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|e| e.to_string()),
+                error_code: result.as_ref().err().map(Error::code),
+                disk_usage,
+            },
+        );
+
+        // Changes that the run itself made (e.g., build outputs) do not trigger another run.
+        watcher.drain();
+        // If the sandbox could not be prepared, retrying only makes sense after a change.
+        run_now = run_id.is_none();
+        if STOP.load(Ordering::Relaxed) || (run_now && !watcher.wait_for_change()) {
+            return Ok(());
+        }
+    }
+}
+
+pub fn run(format: OutputFormat, matches: &clap::ArgMatches) -> i32 {
+    let path = Path::new(matches.value_of("cbuild-json").unwrap());
+    let paths: Vec<&str> = matches.values_of("paths").unwrap().collect();
+    let fifo = std::env::temp_dir().join(format!("cbuildrt-watch.{}.fifo", std::process::id()));
+    if let Err(e) = nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::from_bits_truncate(0o600)) {
+        eprintln!("failed to create {}: {}", fifo.display(), e);
+        return 1;
+    }
+    let action = nix::sys::signal::SigAction::new(
+        nix::sys::signal::SigHandler::Handler(stop),
+        nix::sys::signal::SaFlags::SA_RESTART,
+        nix::sys::signal::SigSet::empty(),
+    );
+    for signal in &[
+        nix::sys::signal::Signal::SIGINT,
+        nix::sys::signal::Signal::SIGTERM,
+    ] {
+        unsafe { nix::sys::signal::sigaction(*signal, &action) }
+            .expect("failed to install signal handler");
+    }
+
+    let result = watch(format, path, &paths, &fifo);
+    let _ = std::fs::remove_file(&fifo);
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use libc::{gid_t, uid_t};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BindMount {
+    pub destination: PathBuf,
+    pub source: PathBuf,
+    // Whether to verify (before the process is executed) that the sandbox user
+    // can create files in the mount.
+    #[serde(default)]
+    pub verify_writable: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Artifact {
+    // Path inside the sandbox.
+    pub source: PathBuf,
+    // Path on the host.
+    pub destination: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Staging {
+    // Path on the host.
+    pub source: PathBuf,
+    // Path inside the sandbox; must be on a writable mount.
+    pub destination: PathBuf,
+    // Whether to try sharing data blocks with the source (FICLONE).
+    #[serde(default)]
+    pub reflink: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NamedMount {
+    // Path on the host.
+    pub source: PathBuf,
+    #[serde(default)]
+    pub writable: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct User {
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Process {
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Shell script that is run by /bin/sh -e instead of args.
+    pub script: Option<String>,
+    // Variables that are added to the environment of the process. They take precedence over
+    // the variables that cbuildrt sets.
+    #[serde(default)]
+    pub environ: BTreeMap<String, String>,
+    // Whether the process starts from an empty environment instead of inheriting cbuildrt's
+    // (e.g., for OCI bundles, whose process.env is the complete environment).
+    #[serde(default)]
+    pub clear_environ: bool,
+    // Working directory of the process inside the sandbox; / if unset.
+    pub cwd: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LdCache {
+    // Library directories that are not listed in the rootfs' /etc/ld.so.conf
+    // (e.g., the lib directories of tool mounts).
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Preload {
+    // Path of the shared object. Unless host is set, this is a path inside the sandbox.
+    pub path: PathBuf,
+    // Whether the shared object is taken from the host (it is mounted into the sandbox).
+    #[serde(default)]
+    pub host: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Home {
+    // Value of HOME inside the sandbox.
+    pub path: PathBuf,
+    // Whether to mount an empty tmpfs (owned by the sandbox user) at path.
+    #[serde(default)]
+    pub create: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ccache {
+    // Host directory that holds the cache.
+    pub dir: PathBuf,
+    // Whether to report hits and misses after the run.
+    #[serde(default)]
+    pub stats: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sccache {
+    // Host path of the sccache server's Unix domain socket.
+    pub server_socket: Option<PathBuf>,
+    // Port of an sccache server on localhost (requires network access).
+    pub server_port: Option<u16>,
+    // Whether to set RUSTC_WRAPPER such that cargo uses sccache.
+    #[serde(default)]
+    pub rustc_wrapper: bool,
+    // Additional configuration (e.g., SCCACHE_BUCKET or SCCACHE_ENDPOINT for remote caches).
+    #[serde(default)]
+    pub environ: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Distcc {
+    // Value of DISTCC_HOSTS.
+    pub hosts: String,
+    // Network namespace to join instead of sharing the host's network.
+    pub netns: Option<PathBuf>,
+    // Host directory with distcc configuration (e.g., a hosts file).
+    pub config_dir: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyscallTrace {
+    // strace filter expression (as passed to strace -e).
+    pub filter: Option<String>,
+    // Host file that receives the trace.
+    pub output: PathBuf,
+    // Host strace binary that is used if the rootfs does not provide one.
+    pub strace: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Perf {
+    // Host file that receives perf.data.
+    pub output: PathBuf,
+    // Events to record (as passed to perf record -e).
+    pub events: Option<String>,
+    // Whether to record call graphs.
+    #[serde(default)]
+    pub call_graph: bool,
+    // Host perf binary that is used if the rootfs does not provide one.
+    pub perf: Option<PathBuf>,
+}
+
+fn default_gdbserver_port() -> u16 {
+    2345
+}
+
+fn default_hugetlbfs() -> PathBuf {
+    PathBuf::from("/dev/hugepages")
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HugePages {
+    // hugetlbfs mount on the host; each run uses a private directory on it.
+    #[serde(default = "default_hugetlbfs")]
+    pub source: PathBuf,
+    // Mount point of the directory inside the sandbox.
+    #[serde(default = "default_hugetlbfs")]
+    pub destination: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Debug {
+    // Host gdbserver binary; if set, the process is run under gdbserver.
+    pub gdbserver: Option<PathBuf>,
+    #[serde(default = "default_gdbserver_port")]
+    pub port: u16,
+}
+
+impl Default for Debug {
+    fn default() -> Debug {
+        Debug {
+            gdbserver: None,
+            port: default_gdbserver_port(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Proxy {
+    // Forward the host's proxy configuration.
+    Host,
+    // Do not use any proxy, even if the host configures one.
+    None,
+}
+
+fn default_download_cache_port() -> u16 {
+    3128
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadCache {
+    // Host directory of the cache; it can be shared between runs (see downloadcache.rs).
+    pub dir: PathBuf,
+    // Port of the proxy on the sandbox's loopback interface.
+    #[serde(default = "default_download_cache_port")]
+    pub port: u16,
+    // Suffixes of the URL paths that are cached (e.g., ".tar.gz"); common archive formats
+    // are cached if unset.
+    pub suffixes: Option<Vec<String>>,
+    // Hosts that the proxy may connect to; any public host if empty.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Memory {
+    // Limit of the memory usage in bytes.
+    pub max: Option<u64>,
+    // Limit of the swap usage in bytes. With 0, builds that exceed max fail
+    // instead of swapping.
+    pub swap_max: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cpu {
+    // Relative share of CPU time (1 to 10000, the default is 100).
+    pub weight: Option<u64>,
+    // CPU time in microseconds that the sandbox may use per period.
+    pub quota: Option<u64>,
+    // Length of a period in microseconds (defaults to 100000).
+    pub period: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Io {
+    // Relative share of block I/O (1 to 10000, the default is 100).
+    pub weight: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    // NUMA node that the CPUs and memory of the sandbox are restricted to.
+    pub numa_node: Option<u32>,
+    pub memory: Option<Memory>,
+    // Maximal number of processes (and threads) in the sandbox.
+    pub pids_max: Option<u64>,
+    pub cpu: Option<Cpu>,
+    pub io: Option<Io>,
+    // cgroup (relative to the root of the cgroup hierarchy) below which the sandbox's cgroup
+    // is created. Defaults to the caller's cgroup; it needs to be delegated to the caller.
+    // On cgroup v1 hosts, the path applies to each hierarchy.
+    pub cgroup_parent: Option<PathBuf>,
+    // Limits (in bytes) of the huge pages of each size (e.g., "2MB").
+    #[serde(default)]
+    pub hugetlb: BTreeMap<String, u64>,
+    // Mounts the sandbox's cgroup read-only at /sys/fs/cgroup (in a cgroup namespace),
+    // such that tools inside can read their limits.
+    #[serde(default)]
+    pub mount_cgroup: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Secret {
+    // Host file that holds the secret.
+    pub source: PathBuf,
+    // Environment variable that is set to the path of the secret inside the sandbox.
+    pub env: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LocaleData {
+    // Bind mount the host's compiled locales.
+    Host,
+    // Compile C.UTF-8 inside the sandbox (using the rootfs' localedef).
+    Generate,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Locale {
+    // Value of LANG.
+    pub lang: String,
+    // Values of individual categories (e.g., LC_COLLATE).
+    #[serde(default)]
+    pub categories: BTreeMap<String, String>,
+    pub data: Option<LocaleData>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Dbus {
+    // Forward the host's session bus.
+    Session,
+    // Host path of the socket of a dedicated bus.
+    Socket(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    pub rootfs: PathBuf,
+    // Identifies the run in logs, events and results; a random ID is generated if unset.
+    pub run_id: Option<String>,
+    pub user: User,
+    pub process: Process,
+    #[serde(default)]
+    pub isolate_network: bool,
+    #[serde(default)]
+    pub rootfs_writable: bool,
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    pub ccache: Option<Ccache>,
+    pub sccache: Option<Sccache>,
+    pub distcc: Option<Distcc>,
+    pub proxy: Option<Proxy>,
+    // Routes downloads through a caching proxy on the host; implies isolateNetwork.
+    pub download_cache: Option<DownloadCache>,
+    pub hostname: Option<String>,
+    // Home directory of the sandbox user (see home.rs).
+    pub home: Option<Home>,
+    // Points the XDG base directories to private directories on the /run tmpfs (see xdg.rs).
+    #[serde(default)]
+    pub xdg_dirs: bool,
+    // Makes the host's X11 and Wayland displays available (see gui.rs).
+    #[serde(default)]
+    pub gui: bool,
+    // D-Bus session bus that is made available inside the sandbox (see dbus.rs).
+    pub dbus: Option<Dbus>,
+    // Locale of the process; overrides the caller's locale variables (see locale.rs).
+    pub locale: Option<Locale>,
+    // Credentials (by name) that are made available below /run/cbuildrt/secrets.
+    // Their values are scrubbed from error messages and traces (see secrets.rs).
+    #[serde(default)]
+    pub secrets: BTreeMap<String, Secret>,
+    // Enables the settings of the reproducible-build preset (see reproducible.rs).
+    #[serde(default)]
+    pub reproducible: bool,
+    // SOURCE_DATE_EPOCH for reproducible builds.
+    pub source_date_epoch: Option<u64>,
+    pub bind_mounts: Vec<BindMount>,
+    // Directories of xbstrap that are mounted to standard locations (see xbstrap.rs).
+    #[serde(default)]
+    pub tool_mounts: BTreeMap<String, NamedMount>,
+    #[serde(default)]
+    pub source_mounts: BTreeMap<String, NamedMount>,
+    #[serde(default)]
+    pub sysroot_mounts: BTreeMap<String, NamedMount>,
+    // Host path of a JSON manifest of the host files that the build accessed (see trace.rs).
+    pub access_manifest: Option<PathBuf>,
+    // Runs the process under strace (see strace.rs).
+    pub trace_syscalls: Option<SyscallTrace>,
+    // Runs the process under perf record (see perf.rs).
+    pub perf: Option<Perf>,
+    // Static qemu-user binary for foreign-architecture rootfs trees (see binfmt.rs).
+    pub qemu_user: Option<PathBuf>,
+    // Debug mode. The sandbox does not restrict ptrace() by default yet, but restrictions
+    // that are added in the future must be lifted in debug mode.
+    pub debug: Option<Debug>,
+    // Files and directories that are copied into the sandbox before the run.
+    #[serde(default)]
+    pub staging: Vec<Staging>,
+    // Files and directories that are copied out of the sandbox after the run.
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    // Disables address space layout randomization for the process (and its children).
+    #[serde(default)]
+    pub disable_aslr: bool,
+    // Runs the process on a pseudo terminal that is also available as /dev/console and is the
+    // controlling terminal of the process (see console.rs).
+    #[serde(default)]
+    pub console: bool,
+    // Time (in seconds) after which the process is killed. Before that, the remaining
+    // processes are logged (as on SIGQUIT).
+    pub timeout: Option<u32>,
+    // Directories that are added to the front and to the end of the default PATH.
+    #[serde(default)]
+    pub path_prepend: Vec<PathBuf>,
+    #[serde(default)]
+    pub path_append: Vec<PathBuf>,
+    // Command line of an init (e.g., ["/usr/bin/tini", "--"]) that runs as PID 1 of a nested
+    // PID namespace; process.args are appended to it.
+    pub init: Option<Vec<String>>,
+    // Capabilities (e.g., CAP_NET_BIND_SERVICE) that the process keeps within the user namespace.
+    #[serde(default)]
+    pub ambient_capabilities: Vec<String>,
+    // Shared objects that are added to LD_PRELOAD of the process.
+    #[serde(default)]
+    pub preload: Vec<Preload>,
+    // Regenerates the cache of the dynamic linker after all mounts have been performed.
+    pub ld_cache: Option<LdCache>,
+    // Makes huge pages available through a hugetlbfs mount (see hugetlb.rs).
+    pub hugepages: Option<HugePages>,
+    // Resource settings that are enforced through a cgroup (see cgroup.rs).
+    pub resources: Option<Resources>,
+    // Host directory for per-run data (e.g., staged copies). Each run uses a subdirectory
+    // that is named after its run ID and removed after the run (unless keepWorkDir is set).
+    pub work_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub keep_work_dir: bool,
+}
+
+impl Config {
+    // Whether the sandbox gets its own (empty) network namespace.
+    // With a download cache, the proxy is the only way out of the namespace.
+    pub(crate) fn network_isolated(&self) -> bool {
+        self.isolate_network || self.reproducible || self.download_cache.is_some()
+    }
+
+    // Per-run subdirectory of the work directory.
+    pub(crate) fn run_dir(&self, run_id: &str) -> Option<PathBuf> {
+        self.work_dir.as_ref().map(|dir| dir.join(run_id))
+    }
+
+    pub(crate) fn hostname(&self) -> Option<&str> {
+        match &self.hostname {
+            Some(hostname) => Some(hostname),
+            None if self.reproducible => Some(crate::reproducible::HOSTNAME),
+            None => None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Console of the sandbox (see Config::console). init allocates a pseudo terminal from the
+// sandbox's devpts instance and bind mounts it to /dev/console. The process runs in a new session
+// with the terminal as its controlling terminal and stdio; init relays between the terminal
+// and its own stdio.
+
+use nix::sys::termios::{self, SetArg, Termios};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub const SANDBOX_PATH: &str = "/dev/console";
+
+pub struct Console {
+    master: File,
+}
+
+// Allocates the terminal and mounts it to /dev/console. Must be called after /dev/pts
+// has been mounted (with ptmxmode=0666).
+pub fn create(rootfs: &Path) -> Result<Console, crate::Error> {
+    let master = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+        .open(crate::concat_absolute(rootfs, "/dev/pts/ptmx"))
+        .map_err(crate::setup_error("open /dev/pts/ptmx"))?;
+    let mut number: libc::c_uint = 0;
+    let unlock: libc::c_int = 0;
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSPTLCK, &unlock) } < 0
+        || unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPTN, &mut number) } < 0
+    {
+        return Err(crate::setup_error("set up pseudo terminal")(
+            std::io::Error::last_os_error(),
+        ));
+    }
+
+    // The window size is only copied once; later changes are not propagated.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 {
+        unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+    }
+
+    nix::mount::mount(
+        Some(&crate::concat_absolute(
+            rootfs,
+            format!("/dev/pts/{}", number),
+        )),
+        &crate::concat_absolute(rootfs, SANDBOX_PATH),
+        None::<&str>,
+        nix::mount::MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .map_err(crate::setup_error("mount /dev/console"))?;
+    Ok(Console { master })
+}
+
+// Makes /dev/console the controlling terminal and the stdio of the calling process.
+// Must be called in the child after it entered the rootfs.
+pub fn attach() {
+    nix::unistd::setsid().expect("failed to create session");
+    let fd = nix::fcntl::open(
+        SANDBOX_PATH,
+        nix::fcntl::OFlag::O_RDWR,
+        nix::sys::stat::Mode::empty(),
+    )
+    .expect("failed to open /dev/console");
+    if unsafe { libc::ioctl(fd, libc::TIOCSCTTY, 0) } < 0 {
+        panic!(
+            "failed to acquire controlling terminal: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    for target in 0..3 {
+        nix::unistd::dup2(fd, target).expect("failed to redirect stdio to /dev/console");
+    }
+    nix::unistd::close(fd).expect("failed to close /dev/console");
+}
+
+// Relays between the terminal and init's stdio while the process runs.
+pub struct Relay {
+    output: std::thread::JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+    // Settings of init's stdin if it is a terminal (which is put into raw mode meanwhile).
+    saved: Option<Termios>,
+}
+
+impl Console {
+    // Starts relaying. Must be called by init after the child has been forked.
+    pub fn relay(self) -> Relay {
+        // With a raw host terminal, control characters (e.g., ^C) reach the console
+        // (and generate signals there) instead of being handled by the host terminal.
+        let saved = termios::tcgetattr(libc::STDIN_FILENO).ok();
+        if let Some(saved) = &saved {
+            let mut raw = saved.clone();
+            termios::cfmakeraw(&mut raw);
+            termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &raw)
+                .expect("failed to put terminal into raw mode");
+        }
+
+        // The input thread is not joined; it may block on stdin until init exits.
+        let mut input = self
+            .master
+            .try_clone()
+            .expect("failed to duplicate console");
+        std::thread::spawn(move || {
+            let mut stdin = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+            let _ = std::io::copy(&mut stdin, &mut input);
+            std::mem::forget(stdin);
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let output = {
+            let stop = stop.clone();
+            let mut master = self.master;
+            std::thread::spawn(move || relay_output(&mut master, &stop))
+        };
+        Relay {
+            output,
+            stop,
+            saved,
+        }
+    }
+}
+
+// Copies the output of the terminal to stdout. Once stop is set, this returns as soon as
+// no more output arrives (background processes may keep the terminal open).
+fn relay_output(master: &mut File, stop: &AtomicBool) {
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let mut fds = [nix::poll::PollFd::new(
+            master.as_raw_fd(),
+            nix::poll::PollFlags::POLLIN,
+        )];
+        match nix::poll::poll(&mut fds, 100) {
+            Ok(0) if stop.load(Ordering::Relaxed) => return,
+            Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Ok(_) => (),
+            Err(_) => return,
+        }
+        // Reading fails with EIO once no process has the terminal open anymore.
+        match master.read(&mut buffer) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => {
+                if stdout.write_all(&buffer[..n]).is_err() {
+                    return;
+                }
+                let _ = stdout.flush();
+            }
+        }
+    }
+}
+
+impl Relay {
+    // Copies the remaining output and restores init's terminal.
+    // Must be called after the child has exited.
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.output.join();
+        if let Some(saved) = &self.saved {
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, saved);
+        }
+    }
+}
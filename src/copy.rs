@@ -0,0 +1,52 @@
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// _IOW(0x94, 9, int); not exported by the libc crate.
+const FICLONE: libc::c_ulong = 0x4004_9409;
+
+// Copies a single file. If reflink is true, tries to share the data blocks with the source
+// and falls back to a regular copy if the filesystem does not support that.
+fn copy_file(source: &Path, destination: &Path, reflink: bool) -> std::io::Result<()> {
+    if reflink {
+        let source_file = std::fs::File::open(source)?;
+        let destination_file = std::fs::File::create(destination)?;
+        let result = unsafe {
+            libc::ioctl(
+                destination_file.as_raw_fd(),
+                FICLONE as _,
+                source_file.as_raw_fd(),
+            )
+        };
+        if result == 0 {
+            return destination_file.set_permissions(source_file.metadata()?.permissions());
+        }
+    }
+    std::fs::copy(source, destination).map(|_| ())
+}
+
+// Recursively copies source to destination, preserving symlinks and permissions.
+// Returns the number of files (including symlinks) that were copied.
+pub fn copy_tree(source: &Path, destination: &Path, reflink: bool) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(source)?, destination)?;
+        Ok(1)
+    } else if file_type.is_dir() {
+        std::fs::create_dir_all(destination)?;
+        let mut count = 0;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            count += copy_tree(&entry.path(), &destination.join(entry.file_name()), reflink)?;
+        }
+        std::fs::set_permissions(
+            destination,
+            std::fs::Permissions::from_mode(metadata.permissions().mode()),
+        )?;
+        Ok(count)
+    } else {
+        copy_file(source, destination, reflink)?;
+        Ok(1)
+    }
+}
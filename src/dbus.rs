@@ -0,0 +1,45 @@
+use crate::Dbus;
+use std::path::{Path, PathBuf};
+
+// Location of the bus socket inside the sandbox.
+const SANDBOX_SOCKET: &str = "/run/cbuildrt/dbus/bus";
+
+// Returns the host path of the session bus socket, based on the caller's environment.
+// Only unix:path= addresses can be forwarded; abstract sockets are bound to the
+// host's network namespace.
+fn session_socket() -> Option<PathBuf> {
+    if let Ok(address) = std::env::var("DBUS_SESSION_BUS_ADDRESS") {
+        for entry in address.split(';') {
+            let params = match entry.strip_prefix("unix:") {
+                Some(params) => params,
+                None => continue,
+            };
+            if let Some(path) = params.split(',').find_map(|p| p.strip_prefix("path=")) {
+                return Some(PathBuf::from(path));
+            }
+        }
+    }
+    // sd-bus and GDBus fall back to this location.
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| Path::new(&dir).join("bus"))
+        .filter(|socket| socket.exists())
+}
+
+// Bind mounts the bus socket to SANDBOX_SOCKET.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, cfg: &Dbus) -> Result<(), crate::Error> {
+    let socket = match cfg {
+        Dbus::Session => session_socket().ok_or_else(|| {
+            crate::Error::Setup("unable to find the host's session bus".to_string())
+        })?,
+        Dbus::Socket(socket) => socket.clone(),
+    };
+    crate::bind_into_sandbox(rootfs, &socket, SANDBOX_SOCKET, false)
+}
+
+pub fn environment() -> Vec<(String, String)> {
+    vec![(
+        "DBUS_SESSION_BUS_ADDRESS".to_string(),
+        format!("unix:path={}", SANDBOX_SOCKET),
+    )]
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+// Location of the host's gdbserver inside the sandbox.
+const SANDBOX_GDBSERVER: &str = "/run/cbuildrt/gdbserver";
+
+// Makes the host's gdbserver available inside the sandbox.
+// Must be called after /run has been mounted.
+pub fn setup_gdbserver(rootfs: &Path, host_binary: &Path) -> Result<(), crate::Error> {
+    crate::bind_into_sandbox(rootfs, host_binary, SANDBOX_GDBSERVER, true)
+}
+
+// Returns the command line that runs args under gdbserver.
+pub fn wrap(cfg: &crate::Debug, args: &[String]) -> Vec<String> {
+    let mut wrapped = vec![
+        SANDBOX_GDBSERVER.to_string(),
+        "--once".to_string(),
+        format!("localhost:{}", cfg.port),
+    ];
+    wrapped.extend(args.iter().cloned());
+    wrapped
+}
+
+// Prints the command that attaches gdb to the gdbserver inside the sandbox.
+pub fn print_attach_command(
+    cfg: &crate::Debug,
+    network_isolated: bool,
+    init_pid: nix::unistd::Pid,
+) {
+    if network_isolated {
+        // The sandbox's loopback interface is only reachable from within its network namespace.
+        log!(
+            "attach using: gdb -ex 'target remote | nsenter -t {} -U -n --preserve-credentials \
+            socat STDIO TCP:localhost:{}'",
+            init_pid,
+            cfg.port
+        );
+    } else {
+        log!(
+            "attach using: gdb -ex 'target remote localhost:{}'",
+            cfg.port
+        );
+    }
+}
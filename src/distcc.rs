@@ -0,0 +1,33 @@
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// Location of the distcc configuration and state directory inside the sandbox.
+pub const SANDBOX_DIR: &str = "/run/distcc";
+
+// Moves the calling process into the network namespace at the given path.
+// Note that this requires CAP_SYS_ADMIN over the namespace; hence, it needs to happen
+// before entering cbuildrt's own user namespace.
+pub fn join_netns(path: &Path) -> Result<(), crate::Error> {
+    let file = std::fs::File::open(path).map_err(crate::setup_error(format!(
+        "open network namespace {}",
+        path.display()
+    )))?;
+    nix::sched::setns(file.as_raw_fd(), nix::sched::CloneFlags::CLONE_NEWNET).map_err(
+        crate::setup_error(format!("join network namespace {}", path.display())),
+    )
+}
+
+// Bind mounts the host's distcc configuration directory to SANDBOX_DIR.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, host_dir: &Path) -> Result<(), crate::Error> {
+    crate::bind_into_sandbox(rootfs, host_dir, SANDBOX_DIR, false)
+}
+
+// Returns the environment variables that configure distcc inside the sandbox.
+pub fn environment(cfg: &crate::Distcc) -> Vec<(String, String)> {
+    let mut env = vec![("DISTCC_HOSTS".to_string(), cfg.hosts.clone())];
+    if cfg.config_dir.is_some() {
+        env.push(("DISTCC_DIR".to_string(), SANDBOX_DIR.to_string()));
+    }
+    env
+}
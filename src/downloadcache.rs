@@ -0,0 +1,621 @@
+// Caching proxy for source downloads. The sandbox gets its own network namespace, in which init
+// listens on the loopback interface and hands the listening socket to the caller of
+// Sandbox::spawn(). The caller serves the connections from the host's network namespace;
+// hence, the proxy is the only way out of the sandbox.
+//
+// Successful GET requests for plain HTTP URLs whose paths end in one of the configured
+// suffixes are stored in the cache directory, which can be shared between runs:
+//   sha256/DIGEST  contents, addressed by their SHA-256
+//   urls/DIGEST    digest of the contents of the URL whose SHA-256 is DIGEST
+// HTTPS is tunnelled through CONNECT (to port 443 only). It cannot be cached since the
+// proxy does not see the decrypted traffic.
+//
+// Since the proxy runs in the host's network namespace, it refuses to connect to loopback,
+// link-local and private addresses; otherwise, the sandbox could reach the services of the
+// host and its local network. Targets are resolved before they are checked, and the proxy
+// connects to the checked address, such that names that resolve to such addresses are
+// refused as well. If the config lists hosts, the proxy only connects to those.
+
+use crate::sha256::{self, Sha256};
+use crate::{DownloadCache, Error};
+use nix::poll::{PollFd, PollFlags};
+use nix::sys::socket::{AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags, SockFlag};
+use nix::sys::uio::IoVec;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Suffixes of source archives that are cached if the config does not list any.
+const DEFAULT_SUFFIXES: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.lz", ".tar.zst",
+    ".zip", ".crate", ".gem", ".whl", ".jar",
+];
+
+// Limit of the size of request and response heads.
+const MAX_HEAD: usize = 64 * 1024;
+
+// Headers that only apply to a single connection and are not forwarded.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+// Returns the environment variables that point the sandbox's tools to the proxy.
+pub fn environment(cfg: &DownloadCache) -> Vec<(String, String)> {
+    let url = format!("http://127.0.0.1:{}", cfg.port);
+    let mut env = Vec::new();
+    for (name, value) in [
+        ("http_proxy", url.as_str()),
+        ("https_proxy", url.as_str()),
+        ("no_proxy", "localhost,127.0.0.1"),
+    ] {
+        env.push((name.to_string(), value.to_string()));
+        env.push((name.to_uppercase(), value.to_string()));
+    }
+    env
+}
+
+// The loopback interface of a new network namespace is down.
+fn bring_up_loopback() -> Result<(), Error> {
+    let socket = nix::sys::socket::socket(
+        AddressFamily::Inet,
+        nix::sys::socket::SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .map_err(crate::setup_error("create socket"))?;
+    // Closes the socket on all paths.
+    let socket = unsafe { File::from_raw_fd(socket) };
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (c, b) in request.ifr_name.iter_mut().zip(b"lo\0") {
+        *c = *b as libc::c_char;
+    }
+    unsafe {
+        if libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut request) < 0 {
+            return Err(crate::setup_error("get flags of lo")(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        if libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS as _, &request) < 0 {
+            return Err(crate::setup_error("bring up lo")(
+                std::io::Error::last_os_error(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Listens on the proxy's port and sends the listening socket through the channel.
+// Must be called by init after it has entered the sandbox's network namespace.
+pub fn listen(cfg: &DownloadCache, channel: RawFd) -> Result<(), Error> {
+    bring_up_loopback()?;
+    let listener = TcpListener::bind(("127.0.0.1", cfg.port))
+        .map_err(crate::setup_error(format!("listen on port {}", cfg.port)))?;
+    nix::sys::socket::sendmsg(
+        channel,
+        &[IoVec::from_slice(b"l")],
+        &[ControlMessage::ScmRights(&[listener.as_raw_fd()])],
+        MsgFlags::empty(),
+        None,
+    )
+    .map_err(crate::setup_error("send the download cache's socket"))?;
+    nix::unistd::close(channel).map_err(crate::setup_error("close download cache channel"))?;
+    Ok(())
+}
+
+struct Cache {
+    dir: PathBuf,
+    suffixes: Vec<String>,
+    // Hosts that the proxy may connect to; any host if empty.
+    hosts: Vec<String>,
+}
+
+// Names of temporary files (which are unique across processes due to the PID).
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+impl Cache {
+    fn is_allowed(&self, host: &str) -> bool {
+        self.hosts.is_empty() || self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+    }
+
+    fn is_cacheable(&self, path: &str) -> bool {
+        let path = path.split(['?', '#']).next().unwrap();
+        self.suffixes.iter().any(|s| path.ends_with(s.as_str()))
+    }
+
+    fn url_file(&self, url: &str) -> PathBuf {
+        self.dir.join("urls").join(sha256::digest(url.as_bytes()))
+    }
+
+    fn temp_file(&self) -> PathBuf {
+        let n = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        self.dir
+            .join("tmp")
+            .join(format!("{}-{}", std::process::id(), n))
+    }
+
+    fn lookup(&self, url: &str) -> Option<File> {
+        let digest = std::fs::read_to_string(self.url_file(url)).ok()?;
+        let digest = digest.trim();
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        File::open(self.dir.join("sha256").join(digest)).ok()
+    }
+
+    // Moves a completely downloaded file into the cache.
+    fn insert(&self, url: &str, temp: &PathBuf, digest: &str) -> std::io::Result<()> {
+        std::fs::rename(temp, self.dir.join("sha256").join(digest))?;
+        let index = self.temp_file();
+        std::fs::write(&index, format!("{}\n", digest))?;
+        std::fs::rename(&index, self.url_file(url))
+    }
+}
+
+// Reads up to (and including) the empty line that terminates a request or response head.
+// Returns the head and the bytes that were read past it.
+fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buffer.split_off(end + 4);
+            return Ok(Some((String::from_utf8_lossy(&buffer).into_owned(), rest)));
+        }
+        if buffer.len() > MAX_HEAD {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+// Returns the value of a header (if it is present).
+fn header<'a>(headers: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| *v)
+}
+
+// Splits an absolute http:// URL into host, port and path.
+fn split_url(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+        _ => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port, path))
+}
+
+// Whether the proxy may connect to an address, i.e., whether it is neither the host's nor
+// on its local network.
+fn is_public(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast())
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            let unique_local = first & 0xfe00 == 0xfc00;
+            let link_local = first & 0xffc0 == 0xfe80;
+            !(v6.is_loopback() || v6.is_unspecified() || unique_local || link_local)
+        }
+    }
+}
+
+// Connects to a target that the proxy may connect to. On failure, returns the status of the
+// response to the client.
+fn connect(cache: &Cache, host: &str, port: u16) -> Result<TcpStream, &'static str> {
+    if !cache.is_allowed(host) {
+        return Err("403 Forbidden");
+    }
+    let addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|_| "502 Bad Gateway")?
+        .collect();
+    let mut allowed = addrs.iter().filter(|a| is_public(a.ip())).peekable();
+    if allowed.peek().is_none() {
+        return Err("403 Forbidden");
+    }
+    allowed
+        .find_map(|a| TcpStream::connect(a).ok())
+        .ok_or("502 Bad Gateway")
+}
+
+fn respond(client: &mut TcpStream, status: &str) -> std::io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.0 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
+
+// Relays the traffic of a CONNECT request.
+fn tunnel(cache: &Cache, mut client: TcpStream, target: &str, rest: &[u8]) -> std::io::Result<()> {
+    let host = match target.rsplit_once(':') {
+        Some((host, "443")) => host.trim_start_matches('[').trim_end_matches(']'),
+        _ => return respond(&mut client, "403 Forbidden"),
+    };
+    let mut upstream = match connect(cache, host, 443) {
+        Ok(upstream) => upstream,
+        Err(status) => return respond(&mut client, status),
+    };
+    client.write_all(b"HTTP/1.0 200 Connection established\r\n\r\n")?;
+    upstream.write_all(rest)?;
+    let (mut from_client, mut to_upstream) = (client.try_clone()?, upstream.try_clone()?);
+    let outgoing = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut from_client, &mut to_upstream);
+        let _ = to_upstream.shutdown(Shutdown::Write);
+    });
+    let _ = std::io::copy(&mut upstream, &mut client);
+    let _ = client.shutdown(Shutdown::Write);
+    let _ = outgoing.join();
+    Ok(())
+}
+
+// Copies the body of a successful response to the client and into the cache. Responses are
+// only cached if they are complete, i.e., if their length matches Content-Length.
+// The download continues if the client disconnects, such that it is cached nevertheless.
+fn store(
+    cache: &Cache,
+    url: &str,
+    length: u64,
+    mut upstream: TcpStream,
+    mut client: TcpStream,
+    rest: &[u8],
+) -> std::io::Result<()> {
+    let temp = cache.temp_file();
+    let mut file = File::create(&temp)?;
+    let mut hasher = Sha256::new();
+    let mut total = 0;
+    let mut client_ok = true;
+    let mut sink = |data: &[u8]| {
+        client_ok = client_ok && client.write_all(data).is_ok();
+        hasher.update(data);
+        total += data.len() as u64;
+        file.write_all(data)
+    };
+    let mut chunk = vec![0u8; 64 * 1024];
+    let result = sink(rest).and_then(|()| loop {
+        match upstream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => sink(&chunk[..n])?,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    });
+    let result = result.and_then(|()| {
+        if total != length {
+            return Ok(false);
+        }
+        cache.insert(url, &temp, &hasher.finish())?;
+        Ok(true)
+    });
+    match result {
+        Ok(true) => {
+            log!("added {} to the download cache", url);
+            Ok(())
+        }
+        other => {
+            let _ = std::fs::remove_file(&temp);
+            other.map(|_| ())
+        }
+    }
+}
+
+fn handle(cache: &Cache, mut client: TcpStream) -> std::io::Result<()> {
+    let (head, rest) = match read_head(&mut client)? {
+        Some(head) => head,
+        None => return respond(&mut client, "400 Bad Request"),
+    };
+    let mut lines = head.split("\r\n").filter(|l| !l.is_empty());
+    let request_line: Vec<&str> = lines.next().unwrap_or("").split(' ').collect();
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim(), v.trim()))
+        .collect();
+    let (method, target) = match request_line[..] {
+        [method, target, _] => (method, target),
+        _ => return respond(&mut client, "400 Bad Request"),
+    };
+    if method == "CONNECT" {
+        return tunnel(cache, client, target, &rest);
+    }
+    let (host, port, path) = match split_url(target) {
+        Some(url) => url,
+        None => return respond(&mut client, "400 Bad Request"),
+    };
+    if !cache.is_allowed(host) {
+        return respond(&mut client, "403 Forbidden");
+    }
+    let cacheable =
+        method == "GET" && header(&headers, "range").is_none() && cache.is_cacheable(path);
+    if cacheable {
+        if let Some(mut file) = cache.lookup(target) {
+            log!("serving {} from the download cache", target);
+            write!(
+                client,
+                "HTTP/1.0 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                file.metadata()?.len()
+            )?;
+            std::io::copy(&mut file, &mut client)?;
+            return Ok(());
+        }
+    }
+
+    if header(&headers, "transfer-encoding").is_some() {
+        return respond(&mut client, "411 Length Required");
+    }
+    let mut upstream = match connect(cache, host, port) {
+        Ok(upstream) => upstream,
+        Err(status) => return respond(&mut client, status),
+    };
+    // HTTP/1.0 responses end when the server closes the connection.
+    let mut request = format!("{} {} HTTP/1.0\r\n", method, path);
+    for (name, value) in &headers {
+        let name_lower = name.to_ascii_lowercase();
+        // Cached contents must not depend on the encodings that the client accepts.
+        let skip = HOP_BY_HOP.contains(&name_lower.as_str())
+            || (cacheable && name_lower == "accept-encoding");
+        if !skip {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    upstream.write_all(request.as_bytes())?;
+    upstream.write_all(&rest)?;
+    let length: u64 = header(&headers, "content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let remaining = length.saturating_sub(rest.len() as u64);
+    std::io::copy(&mut (&client).take(remaining), &mut upstream)?;
+
+    if !cacheable {
+        std::io::copy(&mut upstream, &mut client)?;
+        return Ok(());
+    }
+    let (head, rest) = match read_head(&mut upstream)? {
+        Some(head) => head,
+        None => return respond(&mut client, "502 Bad Gateway"),
+    };
+    client.write_all(head.as_bytes())?;
+    let mut lines = head.split("\r\n");
+    let ok = lines.next().unwrap_or("").split(' ').nth(1) == Some("200");
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim(), v.trim()))
+        .collect();
+    match header(&headers, "content-length").and_then(|l| l.parse().ok()) {
+        Some(length) if ok => store(cache, target, length, upstream, client, &rest),
+        _ => {
+            client.write_all(&rest)?;
+            std::io::copy(&mut upstream, &mut client)?;
+            Ok(())
+        }
+    }
+}
+
+// Waits until fd becomes readable. Returns false if stop became readable instead.
+fn wait_for(fd: RawFd, stop: RawFd) -> bool {
+    let mut fds = [
+        PollFd::new(fd, PollFlags::POLLIN),
+        PollFd::new(stop, PollFlags::POLLIN),
+    ];
+    loop {
+        match nix::poll::poll(&mut fds, -1) {
+            Ok(_) => return fds[1].revents().is_none_or(|r| r.is_empty()),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(e) => panic!("poll() failed: {}", e),
+        }
+    }
+}
+
+// Receives the listening socket from init. Returns None if init fails before sending it.
+fn receive(channel: RawFd) -> Option<TcpListener> {
+    let mut byte = [0u8; 1];
+    let mut space = nix::cmsg_space!([RawFd; 1]);
+    let message = nix::sys::socket::recvmsg(
+        channel,
+        &[IoVec::from_mut_slice(&mut byte)],
+        Some(&mut space),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .ok()?;
+    message.cmsgs().find_map(|c| match c {
+        ControlMessageOwned::ScmRights(fds) => {
+            Some(unsafe { TcpListener::from_raw_fd(*fds.first()?) })
+        }
+        _ => None,
+    })
+}
+
+// Serves the proxy's connections on a thread until it is dropped.
+pub struct Server {
+    // The thread exits once the write end of this pipe is closed.
+    _stop: File,
+}
+
+impl Server {
+    // Prepares the cache directory and serves the connections of the socket that init sends
+    // through the returned channel (see listen()).
+    pub fn start(cfg: &DownloadCache) -> Result<(Server, RawFd), Error> {
+        for subdir in ["sha256", "urls", "tmp"] {
+            let dir = cfg.dir.join(subdir);
+            std::fs::create_dir_all(&dir).map_err(|e| {
+                Error::Setup(format!(
+                    "failed to create download cache directory {}: {}",
+                    dir.display(),
+                    e
+                ))
+            })?;
+        }
+        let cache = Arc::new(Cache {
+            dir: cfg.dir.clone(),
+            suffixes: match &cfg.suffixes {
+                Some(suffixes) => suffixes.clone(),
+                None => DEFAULT_SUFFIXES.iter().map(|s| s.to_string()).collect(),
+            },
+            hosts: cfg.hosts.clone(),
+        });
+        let (channel, sandbox_channel) = nix::sys::socket::socketpair(
+            AddressFamily::Unix,
+            nix::sys::socket::SockType::Stream,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .expect("failed to create download cache channel");
+        let (stop_read, stop_write) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)
+            .expect("failed to create download cache pipe");
+        let channel = unsafe { File::from_raw_fd(channel) };
+        let stop = unsafe { File::from_raw_fd(stop_read) };
+        std::thread::spawn(move || {
+            if !wait_for(channel.as_raw_fd(), stop.as_raw_fd()) {
+                return;
+            }
+            let listener = match receive(channel.as_raw_fd()) {
+                Some(listener) => listener,
+                None => return,
+            };
+            while wait_for(listener.as_raw_fd(), stop.as_raw_fd()) {
+                if let Ok((client, _)) = listener.accept() {
+                    let cache = cache.clone();
+                    std::thread::spawn(move || {
+                        let _ = handle(&cache, client);
+                    });
+                }
+            }
+        });
+        let server = Server {
+            _stop: unsafe { File::from_raw_fd(stop_write) },
+        };
+        Ok((server, sandbox_channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_urls() {
+        assert_eq!(
+            split_url("http://example.org/a/b.tar.gz"),
+            Some(("example.org", 80, "/a/b.tar.gz"))
+        );
+        assert_eq!(
+            split_url("http://example.org:8080"),
+            Some(("example.org", 8080, "/"))
+        );
+        assert_eq!(split_url("http://[::1]:8080/x"), Some(("::1", 8080, "/x")));
+        assert_eq!(split_url("http://[::1]/x"), Some(("::1", 80, "/x")));
+        assert_eq!(split_url("https://example.org/"), None);
+        assert_eq!(split_url("http:///x"), None);
+        assert_eq!(split_url("http://example.org:port/"), None);
+    }
+
+    #[test]
+    fn cacheable_paths() {
+        let cache = Cache {
+            dir: PathBuf::new(),
+            suffixes: vec![".tar.gz".to_string(), ".zip".to_string()],
+            hosts: Vec::new(),
+        };
+        assert!(cache.is_cacheable("/src/foo-1.0.tar.gz"));
+        assert!(cache.is_cacheable("/foo.zip?mirror=1"));
+        assert!(cache.is_cacheable("/foo.zip#top"));
+        assert!(!cache.is_cacheable("/foo.tar.gz.sig"));
+        assert!(!cache.is_cacheable("/index.html?file=foo.zip"));
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        let mut cache = Cache {
+            dir: PathBuf::new(),
+            suffixes: Vec::new(),
+            hosts: Vec::new(),
+        };
+        assert!(cache.is_allowed("example.org"));
+        cache.hosts = vec!["Example.org".to_string()];
+        assert!(cache.is_allowed("example.org"));
+        assert!(!cache.is_allowed("example.com"));
+    }
+
+    #[test]
+    fn public_addresses() {
+        for addr in [
+            "93.184.216.34",
+            "2606:2800:220:1::1",
+            "::ffff:93.184.216.34",
+        ] {
+            assert!(is_public(addr.parse().unwrap()), "{}", addr);
+        }
+        for addr in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(addr.parse().unwrap()), "{}", addr);
+        }
+    }
+
+    // Sends data through a loopback connection and reads a head from the other end.
+    fn read_head_of(data: &'static [u8]) -> Option<(String, Vec<u8>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut writer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut reader, _) = listener.accept().unwrap();
+        writer.write_all(data).unwrap();
+        drop(writer);
+        read_head(&mut reader).unwrap()
+    }
+
+    #[test]
+    fn read_heads() {
+        assert_eq!(
+            read_head_of(b"GET / HTTP/1.1\r\nHost: x\r\n\r\nbody"),
+            Some((
+                "GET / HTTP/1.1\r\nHost: x\r\n\r\n".to_string(),
+                b"body".to_vec()
+            ))
+        );
+        // The connection is closed before the head is complete.
+        assert_eq!(read_head_of(b"GET / HTTP/1.1\r\nHost: x\r\n"), None);
+    }
+}
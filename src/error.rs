@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+// Errors that prevent a sandbox from running.
+// Errors that occur inside the sandbox's processes are sent to the caller as events
+// (see handle.rs), hence this type needs to be serializable.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Error {
+    // The configuration is inconsistent or incomplete.
+    InvalidConfig(String),
+    // The host does not support a requested feature.
+    Unsupported(String),
+    // The rootfs cannot be used.
+    Rootfs {
+        rootfs: PathBuf,
+        reason: String,
+    },
+    // Another cbuildrt instance holds a conflicting lock on the rootfs.
+    RootfsLocked {
+        rootfs: PathBuf,
+        writable: bool,
+    },
+    // unshare() or fork() kept failing with EAGAIN.
+    ResourceLimit {
+        what: String,
+        attempts: u32,
+    },
+    // Setting up the sandbox failed (e.g., a mount could not be performed).
+    Setup(String),
+    // The sandbox user cannot write to a mount that the build is expected to write to.
+    MountNotWritable {
+        destination: PathBuf,
+        source: PathBuf,
+        uid: u32,
+        reason: String,
+    },
+    // The process could not be executed.
+    Exec {
+        program: String,
+        reason: String,
+    },
+}
+
+// Codes of the variants of Error (see Error::code()) and their explanations.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "The configuration is inconsistent or incomplete.\n\n\
+        Check the field that the message names against the documentation of cbuild.json \
+        (see `cbuildrt man`). `cbuildrt validate` checks a configuration without running it.",
+    ),
+    (
+        "E0002",
+        "The host does not support a feature that the configuration requests.\n\n\
+        `cbuildrt check` reports which features are available. Features that rely on \
+        cgroups require a delegated cgroup v2 subtree (e.g., systemd-run --user --scope -p \
+        Delegate=yes), writable cgroup v1 hierarchies or a cgroupParent; other features require host tools \
+        (e.g., strace or perf) to be installed.",
+    ),
+    (
+        "E0003",
+        "The rootfs cannot be used.\n\n\
+        The rootfs must be a directory on a local file system that is not mounted noexec. \
+        Network file systems (NFS, SMB) are known to cause permission errors in user \
+        namespaces; move the rootfs to a local disk.",
+    ),
+    (
+        "E0004",
+        "Another cbuildrt instance holds a conflicting lock on the rootfs.\n\n\
+        Runs with a writable rootfs need exclusive access and fail if the rootfs is in use. \
+        Read-only runs share the rootfs but fail while a writable run uses it, unless \
+        --wait-for-rootfs is given. Wait for the other run to finish or use a separate copy \
+        of the rootfs.",
+    ),
+    (
+        "E0005",
+        "Creating a namespace or a process kept failing with EAGAIN.\n\n\
+        A resource limit of the host was reached. Check user.max_user_namespaces, \
+        kernel.pid_max, RLIMIT_NPROC (ulimit -u) and the pids.max of your cgroup.",
+    ),
+    (
+        "E0006",
+        "Setting up the sandbox failed (e.g., a mount could not be performed).\n\n\
+        Mounts fail with EACCES or EPERM if the sandbox user cannot access the source, if \
+        the mount point does not exist in a read-only rootfs, or if the host restricts \
+        unprivileged user namespaces (e.g., via AppArmor or \
+        kernel.unprivileged_userns_clone). `cbuildrt self-test` checks the basic features \
+        of the sandbox on this host.",
+    ),
+    (
+        "E0007",
+        "The sandbox user cannot write to a mount that the build is expected to write to.\n\n\
+        The process runs with the configured uid inside the sandbox, which corresponds to \
+        the caller's uid on the host. Make the source directory writable for the caller \
+        (e.g., fix its owner) or disable verifyWritable for the mount.",
+    ),
+    (
+        "E0008",
+        "The process could not be executed.\n\n\
+        The program must exist inside the rootfs (not on the host) and be executable; \
+        relative names are looked up in the sandbox's PATH (see pathPrepend). ENOENT for \
+        an existing file usually means that its interpreter or dynamic loader is missing \
+        from the rootfs.",
+    ),
+];
+
+impl Error {
+    // Stable code that identifies the kind of error (see explain()).
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidConfig(_) => "E0001",
+            Error::Unsupported(_) => "E0002",
+            Error::Rootfs { .. } => "E0003",
+            Error::RootfsLocked { .. } => "E0004",
+            Error::ResourceLimit { .. } => "E0005",
+            Error::Setup(_) => "E0006",
+            Error::MountNotWritable { .. } => "E0007",
+            Error::Exec { .. } => "E0008",
+        }
+    }
+
+    // Returns the causes and fixes of the errors with the given code.
+    pub fn explain(code: &str) -> Option<&'static str> {
+        EXPLANATIONS
+            .iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(code))
+            .map(|(_, explanation)| *explanation)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidConfig(msg) | Error::Unsupported(msg) | Error::Setup(msg) => {
+                write!(f, "{}", msg)
+            }
+            Error::Rootfs { rootfs, reason } => write!(f, "rootfs {} {}", rootfs.display(), reason),
+            Error::RootfsLocked {
+                rootfs,
+                writable: true,
+            } => write!(
+                f,
+                "rootfs {} is in use by another cbuildrt instance",
+                rootfs.display()
+            ),
+            Error::RootfsLocked {
+                rootfs,
+                writable: false,
+            } => write!(
+                f,
+                "rootfs {} is locked for writing by another cbuildrt instance",
+                rootfs.display()
+            ),
+            Error::ResourceLimit { what, attempts } => write!(
+                f,
+                "failed to {}: resource limit reached (EAGAIN) after {} attempts; \
+                check user.max_user_namespaces, kernel.pid_max, RLIMIT_NPROC and pids.max",
+                what, attempts
+            ),
+            Error::MountNotWritable {
+                destination,
+                source,
+                uid,
+                reason,
+            } => write!(
+                f,
+                "the sandbox user (uid {}) cannot write to {} (mounted from {}): {}",
+                uid,
+                destination.display(),
+                source.display(),
+                reason
+            ),
+            Error::Exec { program, reason } => {
+                write!(f, "error when executing {}: {}", program, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_code_is_explained() {
+        let errors = [
+            Error::InvalidConfig(String::new()),
+            Error::Unsupported(String::new()),
+            Error::Rootfs {
+                rootfs: PathBuf::new(),
+                reason: String::new(),
+            },
+            Error::RootfsLocked {
+                rootfs: PathBuf::new(),
+                writable: false,
+            },
+            Error::ResourceLimit {
+                what: String::new(),
+                attempts: 0,
+            },
+            Error::Setup(String::new()),
+            Error::MountNotWritable {
+                destination: PathBuf::new(),
+                source: PathBuf::new(),
+                uid: 0,
+                reason: String::new(),
+            },
+            Error::Exec {
+                program: String::new(),
+                reason: String::new(),
+            },
+        ];
+        for error in &errors {
+            assert!(Error::explain(error.code()).is_some(), "{}", error.code());
+        }
+    }
+
+    #[test]
+    fn explain_ignores_case() {
+        assert_eq!(Error::explain("e0001"), Error::explain("E0001"));
+        assert!(Error::explain("E9999").is_none());
+    }
+}
@@ -0,0 +1,72 @@
+// C interface of the library (see include/cbuildrt.h).
+
+use crate::{Config, Error, Sandbox};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+#[repr(C)]
+pub struct CbuildrtResult {
+    // Exit code of the process (if cbuildrt_run() returns 0).
+    exit_code: c_int,
+    // Error message (if cbuildrt_run() returns -1). Owned by the library.
+    error: *mut c_char,
+}
+
+fn run_json(config_json: &CStr) -> Result<i32, Error> {
+    let json = config_json
+        .to_str()
+        .map_err(|_| Error::InvalidConfig("configuration is not valid UTF-8".to_string()))?;
+    let cfg: Config = serde_json::from_str(json)
+        .map_err(|e| Error::InvalidConfig(format!("failed to parse configuration: {}", e)))?;
+    Sandbox::from_config(cfg)?.run()
+}
+
+// Runs a sandbox that is described by a cbuild.json document.
+// Returns 0 if the process ran (even if it failed) and -1 if the sandbox could not be run.
+//
+// # Safety
+// config_json must be a NUL-terminated string and result_out must point to writable memory.
+// The result must be released using cbuildrt_result_free().
+#[no_mangle]
+pub unsafe extern "C" fn cbuildrt_run(
+    config_json: *const c_char,
+    result_out: *mut CbuildrtResult,
+) -> c_int {
+    let config_json = CStr::from_ptr(config_json);
+    // Panics must not unwind into C code.
+    let result = std::panic::catch_unwind(|| run_json(config_json)).unwrap_or_else(|_| {
+        Err(Error::Setup(
+            "cbuildrt panicked while setting up the sandbox".to_string(),
+        ))
+    });
+    match result {
+        Ok(code) => {
+            *result_out = CbuildrtResult {
+                exit_code: code,
+                error: ptr::null_mut(),
+            };
+            0
+        }
+        Err(e) => {
+            // Display output never contains NUL bytes, except for those in paths.
+            let msg = CString::new(e.to_string().replace('\0', "\\0")).unwrap();
+            *result_out = CbuildrtResult {
+                exit_code: -1,
+                error: msg.into_raw(),
+            };
+            -1
+        }
+    }
+}
+
+// # Safety
+// result must have been filled in by cbuildrt_run() and must not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn cbuildrt_result_free(result: *mut CbuildrtResult) {
+    if result.is_null() || (*result).error.is_null() {
+        return;
+    }
+    drop(CString::from_raw((*result).error));
+    (*result).error = ptr::null_mut();
+}
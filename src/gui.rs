@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+// Location of the X authority file inside the sandbox.
+const SANDBOX_XAUTHORITY: &str = "/run/cbuildrt/gui/Xauthority";
+
+// Directory of the X11 sockets (on the host and inside the sandbox).
+const X11_SOCKETS: &str = "/tmp/.X11-unix";
+
+// The host's display servers, as configured by the caller's environment.
+pub struct Displays {
+    // Value of DISPLAY.
+    x11: Option<String>,
+    xauthority: Option<PathBuf>,
+    // Host path of the Wayland socket.
+    wayland: Option<PathBuf>,
+}
+
+impl Displays {
+    pub fn from_environment() -> Displays {
+        let x11 = std::env::var("DISPLAY").ok().filter(|d| !d.is_empty());
+        let xauthority = std::env::var_os("XAUTHORITY")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".Xauthority")))
+            .filter(|path| x11.is_some() && path.exists());
+        // WAYLAND_DISPLAY is either a path or relative to XDG_RUNTIME_DIR.
+        let wayland = std::env::var_os("WAYLAND_DISPLAY")
+            .filter(|d| !d.is_empty())
+            .and_then(|display| {
+                let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").unwrap_or_default();
+                let socket = Path::new(&runtime_dir).join(display);
+                Some(socket).filter(|s| s.is_absolute() && s.exists())
+            });
+        Displays {
+            x11,
+            xauthority,
+            wayland,
+        }
+    }
+
+    fn wayland_display(&self) -> Option<&Path> {
+        self.wayland
+            .as_ref()
+            .map(|s| Path::new(s.file_name().unwrap()))
+    }
+
+    // Bind mounts the sockets of the display servers (and the X authority file).
+    // Must be called after /run and /tmp have been mounted.
+    pub fn mount(&self, rootfs: &Path, uid: libc::uid_t) -> Result<(), crate::Error> {
+        if self.x11.is_none() && self.wayland.is_none() {
+            log!("warning: gui is enabled but neither DISPLAY nor WAYLAND_DISPLAY is set");
+        }
+        // Remote X11 displays do not use the socket directory.
+        if self.x11.is_some() && Path::new(X11_SOCKETS).is_dir() {
+            crate::bind_into_sandbox(rootfs, Path::new(X11_SOCKETS), X11_SOCKETS, false)?;
+        }
+        if let Some(xauthority) = &self.xauthority {
+            crate::bind_into_sandbox(rootfs, xauthority, SANDBOX_XAUTHORITY, true)?;
+        }
+        if let (Some(socket), Some(display)) = (&self.wayland, self.wayland_display()) {
+            crate::xdg::create_runtime_dir(rootfs, uid)?;
+            let runtime_dir = Path::new(&crate::xdg::runtime_dir(uid)).join(display);
+            crate::bind_into_sandbox(rootfs, socket, runtime_dir, false)?;
+        }
+        Ok(())
+    }
+
+    // Returns the environment variables that let clients inside the sandbox find the displays.
+    pub fn environment(&self, uid: libc::uid_t) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if let Some(display) = &self.x11 {
+            env.push(("DISPLAY".to_string(), display.clone()));
+        }
+        if self.xauthority.is_some() {
+            env.push(("XAUTHORITY".to_string(), SANDBOX_XAUTHORITY.to_string()));
+        }
+        if let Some(display) = self.wayland_display() {
+            env.push((
+                "WAYLAND_DISPLAY".to_string(),
+                display.to_string_lossy().into_owned(),
+            ));
+            env.push(("XDG_RUNTIME_DIR".to_string(), crate::xdg::runtime_dir(uid)));
+        }
+        env
+    }
+}
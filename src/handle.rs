@@ -0,0 +1,300 @@
+use crate::platform::{Native, Platform};
+use crate::teardown::Teardown;
+use crate::Error;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+// Events that the sandbox's processes report to the caller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Event {
+    // init has been forked. The PID is relative to the caller's PID namespace.
+    Started { init_pid: i32, run_id: String },
+    // All namespaces, mounts and the environment are set up; the process is executed next
+    // (once the start gate is opened, if there is one).
+    Ready,
+    // Setting up or running the sandbox failed.
+    Failed(Error),
+    // Disk space consumed by the writable layers, measured after the process has exited.
+    DiskUsage(Vec<DiskUsage>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LayerKind {
+    // A tmpfs that cbuildrt mounts into the sandbox (e.g., /tmp).
+    Tmpfs,
+    // The per-run work directory on the host (see Config::work_dir).
+    WorkDir,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskUsage {
+    // Path inside the sandbox for tmpfs mounts, host path for the work directory.
+    pub path: PathBuf,
+    pub kind: LayerKind,
+    pub bytes: u64,
+}
+
+// Writes an event (as a line of JSON) to the events pipe.
+// Events are small enough that the write is atomic, even with multiple writers.
+pub(crate) fn send_event(fd: RawFd, event: &Event) {
+    let mut line = serde_json::to_vec(event).unwrap();
+    line.push(b'\n');
+    let _ = nix::unistd::write(fd, &line);
+}
+
+// A running sandbox, as returned by Sandbox::spawn().
+// The handle's file descriptor (see AsRawFd) refers to the supervisor (e.g., a pidfd). It becomes
+// readable once the sandbox has terminated, hence it can be registered with an event loop
+// (e.g., tokio's AsyncFd). Afterwards, try_wait() collects the exit code without blocking.
+pub struct Handle {
+    supervisor: Pid,
+    run_id: String,
+    process: File,
+    // Read end of the events pipe (non-blocking).
+    events: File,
+    buffer: Vec<u8>,
+    // PID of init and its descriptor (if the platform has one, see Platform::process_fd()).
+    init: Option<(Pid, Option<File>)>,
+    failure: Option<Error>,
+    disk_usage: Vec<DiskUsage>,
+    code: Option<i32>,
+    // Keeps the rootfs lock until the handle is dropped.
+    _teardown: Teardown,
+}
+
+impl Handle {
+    pub(crate) fn new(
+        supervisor: Pid,
+        process: File,
+        run_id: String,
+        events: File,
+        teardown: Teardown,
+    ) -> Result<Handle, Error> {
+        nix::fcntl::fcntl(
+            events.as_raw_fd(),
+            nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::O_NONBLOCK),
+        )
+        .map_err(crate::setup_error("make events pipe non-blocking"))?;
+        Ok(Handle {
+            supervisor,
+            run_id,
+            process,
+            events,
+            buffer: Vec::new(),
+            init: None,
+            failure: None,
+            disk_usage: Vec::new(),
+            code: None,
+            _teardown: teardown,
+        })
+    }
+
+    // PID of the supervisor process.
+    pub fn id(&self) -> u32 {
+        self.supervisor.as_raw() as u32
+    }
+
+    // ID of the run (see Config::run_id).
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    // PID of the sandbox's init (relative to the caller's PID namespace), once it is known.
+    pub fn init_pid(&mut self) -> Option<u32> {
+        self.events();
+        self.init.as_ref().map(|(pid, _)| pid.as_raw() as u32)
+    }
+
+    // File descriptor of the events pipe. It becomes readable when events() has new events.
+    pub fn events_fd(&self) -> RawFd {
+        self.events.as_raw_fd()
+    }
+
+    // Returns the events that were reported since the last call. Does not block.
+    pub fn events(&mut self) -> Vec<Event> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.events.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(_) => break,
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let event: Event = match serde_json::from_slice(&line) {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            match &event {
+                Event::Started { init_pid, .. } => {
+                    let pid = Pid::from_raw(*init_pid);
+                    // init may already have exited; signal() then fails.
+                    match Native::process_fd(pid) {
+                        Ok(fd) => self.init = Some((pid, Some(fd))),
+                        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                            self.init = Some((pid, None))
+                        }
+                        Err(_) => (),
+                    }
+                }
+                // Only the first failure is relevant; later ones are usually consequences of it.
+                Event::Failed(e) if self.failure.is_none() => self.failure = Some(e.clone()),
+                Event::DiskUsage(usage) => self.disk_usage = usage.clone(),
+                Event::Failed(_) | Event::Ready => (),
+            }
+            events.push(event);
+        }
+        events
+    }
+
+    // Disk space consumed by the writable layers of the sandbox.
+    // This is only known once the sandbox has terminated (and empty if it failed early).
+    pub fn disk_usage(&self) -> &[DiskUsage] {
+        &self.disk_usage
+    }
+
+    // Sends a signal to the process inside the sandbox. init forwards it to the process,
+    // except for SIGKILL, which terminates the entire sandbox, and SIGQUIT, which makes init
+    // log the remaining processes before terminating the sandbox.
+    pub fn signal(&mut self, signal: libc::c_int) -> std::io::Result<()> {
+        self.events();
+        match &self.init {
+            Some((pid, fd)) => Native::send_signal(*pid, fd.as_ref(), signal),
+            None => Err(std::io::Error::other(
+                "the sandbox has not been started yet",
+            )),
+        }
+    }
+
+    // Terminates the entire sandbox.
+    pub fn kill(&mut self) -> std::io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+
+    // Records the exit status of the supervisor. Returns false if it is still running.
+    fn finish(&mut self, status: nix::sys::wait::WaitStatus) -> bool {
+        let code = match status {
+            nix::sys::wait::WaitStatus::Exited(_, code) => code,
+            nix::sys::wait::WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+            // The supervisor is still running (or was stopped and continued).
+            _ => return false,
+        };
+        self.code = Some(code);
+        // All processes that could report events have terminated at this point.
+        self.events();
+        true
+    }
+
+    fn result(&self) -> Result<i32, Error> {
+        match (&self.failure, self.code) {
+            (Some(e), _) => Err(e.clone()),
+            (None, Some(code)) => Ok(code),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // Returns the exit code of the process if the sandbox has terminated. Does not block.
+    pub fn try_wait(&mut self) -> Result<Option<i32>, Error> {
+        if self.code.is_none() {
+            let status = nix::sys::wait::waitpid(
+                self.supervisor,
+                Some(nix::sys::wait::WaitPidFlag::WNOHANG),
+            )
+            .map_err(crate::setup_error("wait for supervisor"))?;
+            if !self.finish(status) {
+                return Ok(None);
+            }
+        }
+        self.result().map(Some)
+    }
+
+    // Waits for the sandbox to terminate and returns the exit code of the process.
+    pub fn wait(&mut self) -> Result<i32, Error> {
+        while self.code.is_none() {
+            let status = nix::sys::wait::waitpid(self.supervisor, None)
+                .map_err(crate::setup_error("wait for supervisor"))?;
+            self.finish(status);
+        }
+        self.result()
+    }
+}
+
+// Dropping the handle of a sandbox that is still running terminates it. Otherwise, the teardown
+// would remove resources (e.g., the cgroup and the work directory) that the sandbox still uses.
+impl Drop for Handle {
+    fn drop(&mut self) {
+        while self.code.is_none() {
+            if self.kill().is_ok() {
+                let _ = self.wait();
+                return;
+            }
+            // init has not been reported yet, or the supervisor failed before forking it.
+            match self.try_wait() {
+                Ok(None) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+    }
+}
+
+impl AsRawFd for Handle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.process.as_raw_fd()
+    }
+}
+
+// Refers to a process of a sandbox that another cbuildrt process spawned (e.g., init of a
+// sandbox whose PID was recorded in a state directory). Where the platform has process
+// descriptors, the process is signaled through its descriptor; hence, signals do not reach
+// another process that reuses the PID after the process terminated.
+pub struct ProcessRef {
+    pid: Pid,
+    fd: Option<File>,
+}
+
+impl ProcessRef {
+    // Fails if no process with this PID exists.
+    pub fn open(pid: u32) -> std::io::Result<ProcessRef> {
+        let pid = Pid::from_raw(pid as i32);
+        let fd = match Native::process_fd(pid) {
+            Ok(fd) => Some(fd),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => None,
+            Err(e) => return Err(e),
+        };
+        let process = ProcessRef { pid, fd };
+        process.signal(0)?;
+        Ok(process)
+    }
+
+    pub fn signal(&self, signal: libc::c_int) -> std::io::Result<()> {
+        Native::send_signal(self.pid, self.fd.as_ref(), signal)
+    }
+
+    // Whether the process has not been reaped yet.
+    pub fn is_alive(&self) -> bool {
+        self.signal(0).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn handle_is_send() {
+        assert_send::<Handle>();
+    }
+}
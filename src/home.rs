@@ -0,0 +1,21 @@
+use std::path::Path;
+
+// Mounts an empty tmpfs at the home directory.
+// init runs with the IDs of the sandbox user, hence the directory is owned by that user.
+// The mount point is created if necessary; this requires the parent to be writable
+// (e.g., for homes below /run or /tmp). Must be called after /run and /tmp have been mounted.
+pub fn create(rootfs: &Path, home: &Path) -> Result<(), crate::Error> {
+    let target = crate::concat_absolute(rootfs, home);
+    std::fs::create_dir_all(&target).map_err(crate::setup_error(format!(
+        "create home directory {}",
+        home.display()
+    )))?;
+    nix::mount::mount(
+        None::<&str>,
+        &target,
+        Some("tmpfs"),
+        nix::mount::MsFlags::MS_NOSUID | nix::mount::MsFlags::MS_NODEV,
+        Some("mode=0700"),
+    )
+    .map_err(crate::setup_error("mount home directory"))
+}
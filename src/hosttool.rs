@@ -0,0 +1,43 @@
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+// Directories in which we look for tools in the rootfs.
+const ROOTFS_DIRS: &[&str] = &["/usr/bin", "/bin", "/usr/sbin", "/sbin"];
+
+fn find_on_host(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+// Returns the path of a tool inside the sandbox. If the rootfs does not provide the tool,
+// the host's version (host_binary or the one found on the host's PATH) is bind mounted
+// to sandbox_path. Note that this only works if the host's tool is statically linked
+// (or if the rootfs happens to provide compatible libraries).
+// Must be called after /run has been mounted.
+pub fn provide(
+    rootfs: &Path,
+    name: &str,
+    host_binary: Option<&Path>,
+    sandbox_path: &str,
+) -> Result<String, Error> {
+    for dir in ROOTFS_DIRS {
+        let candidate = Path::new(dir).join(name);
+        if crate::concat_absolute(rootfs, &candidate).is_file() {
+            return Ok(candidate.to_string_lossy().into_owned());
+        }
+    }
+
+    let host_binary = host_binary
+        .map(Path::to_path_buf)
+        .or_else(|| find_on_host(name))
+        .ok_or_else(|| {
+            Error::Unsupported(format!(
+                "{} is neither available in the rootfs nor on the host",
+                name
+            ))
+        })?;
+    crate::bind_into_sandbox(rootfs, &host_binary, sandbox_path, true)?;
+    Ok(sandbox_path.to_string())
+}
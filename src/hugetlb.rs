@@ -0,0 +1,34 @@
+// Huge pages inside the sandbox (see Config::hugepages).
+// hugetlbfs cannot be mounted inside user namespaces. Instead, each run gets a private
+// directory on a hugetlbfs mount of the host, which is bind mounted into the sandbox and
+// removed after the run. Limits are enforced by the hugetlb cgroup controller (see cgroup.rs).
+
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+// Directory of the run on the hugetlbfs mount.
+pub fn run_dir(source: &Path, run_id: &str) -> PathBuf {
+    source.join(format!("cbuildrt-{}", run_id))
+}
+
+// Creates the directory of the run. Called by the caller, which owns the directory
+// (and whose UID is mapped to the sandbox user).
+pub fn create(source: &Path, run_id: &str) -> Result<PathBuf, Error> {
+    let is_hugetlbfs = nix::sys::statfs::statfs(source)
+        .is_ok_and(|fs| fs.filesystem_type() == nix::sys::statfs::HUGETLBFS_MAGIC);
+    if !is_hugetlbfs {
+        return Err(Error::Unsupported(format!(
+            "hugepages.source {} is not a hugetlbfs mount",
+            source.display()
+        )));
+    }
+    let dir = run_dir(source, run_id);
+    std::fs::create_dir(&dir).map_err(|e| {
+        Error::Setup(format!(
+            "failed to create huge page directory {}: {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    Ok(dir)
+}
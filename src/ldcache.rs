@@ -0,0 +1,34 @@
+use crate::{Error, LdCache};
+use std::path::Path;
+
+// Cache of the dynamic linker inside the sandbox.
+const CACHE: &str = "/etc/ld.so.cache";
+
+// Location of the regenerated cache inside the sandbox.
+const GENERATED_CACHE: &str = "/run/cbuildrt/ld.so.cache";
+
+// Regenerates the dynamic linker's cache and mounts it over /etc/ld.so.cache.
+// Must be called after all other mounts such that libraries that are mounted into
+// the sandbox are picked up.
+pub fn refresh(rootfs: &Path, cfg: &LdCache) -> Result<(), Error> {
+    std::fs::create_dir_all(crate::concat_absolute(rootfs, "/run/cbuildrt"))
+        .map_err(crate::setup_error("create /run/cbuildrt"))?;
+    // The rootfs may be read-only, hence -X skips updating the libraries' symbolic links.
+    let status = crate::sandbox_command(rootfs, "/sbin/ldconfig")
+        .args(["-X", "-C", GENERATED_CACHE])
+        .args(&cfg.directories)
+        .status()
+        .map_err(crate::setup_error("run ldconfig"))?;
+    if !status.success() {
+        return Err(Error::Setup(format!(
+            "failed to regenerate {} (ldconfig: {})",
+            CACHE, status
+        )));
+    }
+    crate::bind_into_sandbox(
+        rootfs,
+        &crate::concat_absolute(rootfs, GENERATED_CACHE),
+        CACHE,
+        true,
+    )
+}
@@ -0,0 +1,235 @@
+// cbuildrt as a library. Embedders construct a Sandbox (either via Sandbox::builder()
+// or from a deserialized Config) and run it; the cbuildrt binary is a thin CLI on top of this.
+
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::{ffi::OsStr, os::unix::process::CommandExt, process::Command};
+
+// Prints a diagnostic message. In the sandbox's processes, messages are prefixed
+// with the run ID such that the logs of concurrent runs can be told apart.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        match crate::runid::current() {
+            Some(id) => eprintln!("[{}] {}", id, format_args!($($arg)*)),
+            None => eprintln!($($arg)*),
+        }
+    };
+}
+
+mod check;
+// Only used by build.rs; declared here for its tests.
+#[cfg(test)]
+mod civil;
+mod config;
+mod copy;
+mod error;
+mod ffi;
+mod handle;
+pub mod oci;
+mod platform;
+mod reproducible;
+mod runid;
+mod sandbox;
+mod teardown;
+
+// Features of the Linux backend.
+#[cfg(target_os = "linux")]
+mod binfmt;
+#[cfg(target_os = "linux")]
+mod caps;
+#[cfg(target_os = "linux")]
+mod ccache;
+#[cfg(target_os = "linux")]
+mod cgroup;
+#[cfg(target_os = "linux")]
+mod console;
+#[cfg(target_os = "linux")]
+mod dbus;
+#[cfg(target_os = "linux")]
+mod debug;
+#[cfg(target_os = "linux")]
+mod distcc;
+#[cfg(target_os = "linux")]
+mod downloadcache;
+#[cfg(target_os = "linux")]
+mod gui;
+#[cfg(target_os = "linux")]
+mod home;
+#[cfg(target_os = "linux")]
+mod hosttool;
+#[cfg(target_os = "linux")]
+mod hugetlb;
+#[cfg(target_os = "linux")]
+mod ldcache;
+#[cfg(target_os = "linux")]
+mod locale;
+#[cfg(target_os = "linux")]
+mod numa;
+#[cfg(target_os = "linux")]
+mod perf;
+#[cfg(target_os = "linux")]
+mod preload;
+#[cfg(target_os = "linux")]
+mod proxy;
+#[cfg(target_os = "linux")]
+mod ptree;
+#[cfg(target_os = "linux")]
+mod sccache;
+#[cfg(target_os = "linux")]
+mod script;
+#[cfg(target_os = "linux")]
+mod secrets;
+#[cfg(target_os = "linux")]
+mod sha256;
+#[cfg(target_os = "linux")]
+mod strace;
+#[cfg(target_os = "linux")]
+mod trace;
+#[cfg(target_os = "linux")]
+mod usage;
+#[cfg(target_os = "linux")]
+mod xbstrap;
+#[cfg(target_os = "linux")]
+mod xdg;
+
+#[cfg(target_os = "linux")]
+pub use cgroup::{cgroup_stats, CgroupStats};
+pub use check::{check_host, HostCheck};
+pub use config::{
+    Artifact, BindMount, Ccache, Config, Cpu, Dbus, Debug, Distcc, DownloadCache, Home, HugePages,
+    Io, LdCache, Locale, LocaleData, Memory, NamedMount, Perf, Preload, Process, Proxy, Resources,
+    Sccache, Secret, Staging, SyscallTrace, User,
+};
+pub use error::Error;
+pub use handle::{DiskUsage, Event, Handle, LayerKind, ProcessRef};
+pub use sandbox::{Sandbox, SandboxBuilder};
+
+// Concatenates lhs and rhs as-if the rhs was a relative path.
+pub(crate) fn concat_absolute<L: AsRef<Path>, R: AsRef<Path>>(lhs: L, rhs: R) -> PathBuf {
+    lhs.as_ref().join(rhs.as_ref().strip_prefix("/").unwrap())
+}
+
+// Turns the error of a setup step into Error::Setup. what describes the step
+// (e.g., "mount /proc").
+pub(crate) fn setup_error<W: std::fmt::Display, E: std::fmt::Display>(
+    what: W,
+) -> impl FnOnce(E) -> Error {
+    move |e| Error::Setup(format!("failed to {}: {}", what, e))
+}
+
+// Bind mounts source to the given path inside the sandbox, creating the mount point if necessary.
+// This only works if the parent of the mount point is writable (e.g., below /run or /tmp).
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_into_sandbox<P: AsRef<Path>>(
+    rootfs: &Path,
+    source: &Path,
+    destination: P,
+    read_only: bool,
+) -> Result<(), Error> {
+    let target = concat_absolute(rootfs, destination);
+    let create_result = if source.is_dir() {
+        std::fs::create_dir_all(&target)
+    } else if target.exists() {
+        Ok(())
+    } else {
+        std::fs::create_dir_all(target.parent().unwrap()).and_then(|_| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&target)
+                .map(|_| ())
+        })
+    };
+    create_result.map_err(setup_error(format!(
+        "create mount point for {}",
+        source.display()
+    )))?;
+    nix::mount::mount(
+        Some(source),
+        &target,
+        None::<&str>,
+        nix::mount::MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .map_err(setup_error(format!(
+        "bind mount {} to {}",
+        source.display(),
+        target.display()
+    )))?;
+
+    if read_only {
+        nix::mount::mount(
+            Some(source),
+            &target,
+            None::<&str>,
+            nix::mount::MsFlags::MS_REMOUNT
+                | nix::mount::MsFlags::MS_BIND
+                | nix::mount::MsFlags::MS_RDONLY
+                | locked_mount_flags(source)?,
+            None::<&str>,
+        )
+        .map_err(setup_error(format!("make {} read-only", source.display())))?;
+    }
+    Ok(())
+}
+
+// chroot()s into the rootfs and changes the current directory to /.
+#[cfg(target_os = "linux")]
+pub(crate) fn enter_rootfs(rootfs: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::chroot(rootfs)?;
+    std::env::set_current_dir("/")
+}
+
+// Returns a Command that runs a program inside the sandbox.
+// init itself does not chroot() since it needs to access the host to copy artifacts.
+#[cfg(target_os = "linux")]
+pub(crate) fn sandbox_command<S: AsRef<OsStr>>(rootfs: &Path, program: S) -> Command {
+    let rootfs = rootfs.to_path_buf();
+    let mut command = Command::new(program);
+    unsafe {
+        command.pre_exec(move || enter_rootfs(&rootfs));
+    }
+    command
+}
+
+// Returns the flags of the mount that path resides on that need to be carried over
+// when remounting: inside the user namespace, the kernel locks these flags.
+#[cfg(target_os = "linux")]
+pub(crate) fn locked_mount_flags(path: &Path) -> Result<nix::mount::MsFlags, Error> {
+    let vfs = nix::sys::statvfs::statvfs(path)
+        .map_err(setup_error(format!("statvfs() {}", path.display())))?;
+    let mapping = [
+        (
+            nix::sys::statvfs::FsFlags::ST_NOSUID,
+            nix::mount::MsFlags::MS_NOSUID,
+        ),
+        (
+            nix::sys::statvfs::FsFlags::ST_NODEV,
+            nix::mount::MsFlags::MS_NODEV,
+        ),
+        (
+            nix::sys::statvfs::FsFlags::ST_NOEXEC,
+            nix::mount::MsFlags::MS_NOEXEC,
+        ),
+        (
+            nix::sys::statvfs::FsFlags::ST_NOATIME,
+            nix::mount::MsFlags::MS_NOATIME,
+        ),
+        (
+            nix::sys::statvfs::FsFlags::ST_NODIRATIME,
+            nix::mount::MsFlags::MS_NODIRATIME,
+        ),
+        (
+            nix::sys::statvfs::FsFlags::ST_RELATIME,
+            nix::mount::MsFlags::MS_RELATIME,
+        ),
+    ];
+    let mut flags = nix::mount::MsFlags::empty();
+    for (vfs_flag, mount_flag) in mapping.iter() {
+        if vfs.flags().contains(*vfs_flag) {
+            flags |= *mount_flag;
+        }
+    }
+    Ok(flags)
+}
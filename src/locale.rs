@@ -0,0 +1,60 @@
+use crate::{Error, Locale, LocaleData};
+use std::path::Path;
+
+// Directory of compiled locales on the host (and inside the sandbox).
+const HOST_LOCALES: &str = "/usr/lib/locale";
+
+// LOCPATH of generated locales inside the sandbox.
+const GENERATED_LOCALES: &str = "/run/cbuildrt/locale";
+
+// Makes the locale data available inside the sandbox.
+// Must be called after /run has been mounted.
+pub fn setup(rootfs: &Path, cfg: &Locale) -> Result<(), Error> {
+    match cfg.data {
+        // The format of compiled locales depends on the glibc version,
+        // hence this only works if the rootfs' glibc is close to the host's.
+        Some(LocaleData::Host) => {
+            crate::bind_into_sandbox(rootfs, Path::new(HOST_LOCALES), HOST_LOCALES, true)
+        }
+        Some(LocaleData::Generate) => {
+            let output = format!("{}/C.UTF-8", GENERATED_LOCALES);
+            std::fs::create_dir_all(crate::concat_absolute(rootfs, GENERATED_LOCALES))
+                .map_err(crate::setup_error("create /run/cbuildrt/locale"))?;
+            // -c writes the locale even if localedef emits warnings (exit code 1).
+            let status = crate::sandbox_command(rootfs, "localedef")
+                .args(["-c", "-i", "C", "-f", "UTF-8", &output])
+                .status()
+                .map_err(crate::setup_error("run localedef"))?;
+            if !matches!(status.code(), Some(0) | Some(1)) {
+                return Err(Error::Setup(format!(
+                    "failed to generate locale C.UTF-8 (localedef: {})",
+                    status
+                )));
+            }
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+// Returns the locale variables. These replace all locale variables of the caller.
+pub fn environment(cfg: &Locale) -> Vec<(String, String)> {
+    let mut env = vec![("LANG".to_string(), cfg.lang.clone())];
+    for (category, value) in &cfg.categories {
+        env.push((category.clone(), value.clone()));
+    }
+    if let Some(LocaleData::Generate) = cfg.data {
+        env.push(("LOCPATH".to_string(), GENERATED_LOCALES.to_string()));
+    }
+    env
+}
+
+// Removes all locale variables from the current environment.
+pub fn clear_environment() {
+    for (key, _) in std::env::vars_os() {
+        let key = key.to_string_lossy();
+        if key == "LANG" || key == "LANGUAGE" || key == "LOCPATH" || key.starts_with("LC_") {
+            std::env::remove_var(&*key);
+        }
+    }
+}
@@ -1,293 +1,473 @@
 use clap::crate_version;
-use libc::{gid_t, uid_t};
-use nix::fcntl::{flock, open, FlockArg, OFlag};
-use nix::sys::stat::Mode;
-use serde::{Deserialize, Serialize};
-use std::ffi::CString;
-use std::fs::File;
-use std::path::{Path, PathBuf};
+use cli::output::OutputFormat;
+use cli::state::StateDir;
+use std::path::Path;
 use std::process::exit;
 
-#[derive(Serialize, Deserialize)]
-struct BindMount {
-    destination: PathBuf,
-    source: PathBuf,
-}
-
-#[derive(Serialize, Deserialize)]
-struct User {
-    uid: uid_t,
-    gid: gid_t,
-}
+mod cli;
 
-#[derive(Serialize, Deserialize)]
-struct Process {
-    args: Vec<String>,
-}
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Config {
-    rootfs: PathBuf,
-    user: User,
-    process: Process,
-    #[serde(default)]
-    isolate_network: bool,
-    bind_mounts: Vec<BindMount>,
-}
-
-// TODO: This function does not really perform error checking;
-//       for now, we assume that xbstrap passes sane values.
-fn make_config_from_cli() -> Config {
-    let matches = clap::App::new("cbuildrt")
+fn make_app() -> clap::App<'static, 'static> {
+    clap::App::new("cbuildrt")
         .version(crate_version!())
+        .long_version(cli::version::LONG_VERSION)
+        // Without a subcommand, cbuildrt runs the given cbuild.json.
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .arg(
+            clap::Arg::with_name("output-format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .global(true)
+                .help("Format of the output (json prints a single line of JSON to stdout)"),
+        )
         .arg(
             clap::Arg::with_name("cbuild-json")
                 .help("cbuild.json file")
-                .required(true),
+                .required_unless("explain"),
         )
-        .get_matches();
-
-    let cfg_f =
-        File::open(matches.value_of("cbuild-json").unwrap()).expect("unable to open cbuild.json");
-
-    serde_json::from_reader(cfg_f).expect("failed to parse cbuild.json")
+        .arg(
+            clap::Arg::with_name("explain")
+                .long("explain")
+                .value_name("CODE")
+                .help("Explain the causes and fixes of an error code (e.g., E0006)"),
+        )
+        .arg(
+            clap::Arg::with_name("root")
+                .long("root")
+                .value_name("DIR")
+                .global(true)
+                .help("State directory of detached sandboxes [default: $XDG_RUNTIME_DIR/cbuildrt]"),
+        )
+        .arg(
+            clap::Arg::with_name("reproducible")
+                .long("reproducible")
+                .help("Enable the reproducible-build preset"),
+        )
+        .arg(
+            clap::Arg::with_name("trace-access")
+                .long("trace-access")
+                .value_name("FILE")
+                .help("Write a manifest of the host files accessed by the build to FILE"),
+        )
+        .arg(
+            clap::Arg::with_name("trace-syscalls")
+                .long("trace-syscalls")
+                .value_name("FILTER")
+                .min_values(0)
+                .require_equals(true)
+                .help("Run the process under strace, optionally with a filter expression"),
+        )
+        .arg(
+            clap::Arg::with_name("trace-output")
+                .long("trace-output")
+                .value_name("FILE")
+                .default_value("strace.log")
+                .help("Write the output of --trace-syscalls to FILE"),
+        )
+        .arg(
+            clap::Arg::with_name("perf")
+                .long("perf")
+                .help("Profile the process using perf record"),
+        )
+        .arg(
+            clap::Arg::with_name("perf-output")
+                .long("perf-output")
+                .value_name("FILE")
+                .default_value("perf.data")
+                .help("Write the output of --perf to FILE"),
+        )
+        .arg(
+            clap::Arg::with_name("wait-for-rootfs")
+                .long("wait-for-rootfs")
+                .help("Wait until a writable run releases the rootfs instead of failing (read-only runs)"),
+        )
+        .arg(
+            clap::Arg::with_name("keep-workdir")
+                .long("keep-workdir")
+                .help("Keep the per-run work directory (see workDir) for debugging"),
+        )
+        .arg(
+            clap::Arg::with_name("debug")
+                .long("debug")
+                .help("Enable debug mode, which permits ptrace() inside the sandbox"),
+        )
+        .arg(
+            clap::Arg::with_name("gdbserver")
+                .long("gdbserver")
+                .value_name("PATH")
+                .help("Run the process under the given host gdbserver (implies --debug)"),
+        )
+        .arg(
+            clap::Arg::with_name("detach")
+                .long("detach")
+                .short("d")
+                .conflicts_with("remote")
+                .help(
+                    "Continue in the background once the sandbox has started and print its \
+                    run ID (requires console; see the attach subcommand)",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("capture-output")
+                .long("capture-output")
+                .conflicts_with("remote")
+                .help(
+                    "Also copy the output of the sandbox to the state directory \
+                    (see the logs subcommand); the sandbox's stdout and stderr become pipes. \
+                    Cannot be used with secrets",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("keep-state")
+                .long("keep-state")
+                .conflicts_with("remote")
+                .help(
+                    "Keep the entry of the run (and its captured output) in the state \
+                    directory after it completed, until the delete subcommand removes it",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("ready-fd")
+                .long("ready-fd")
+                .value_name("FD")
+                .conflicts_with("remote")
+                .help("Write a line of JSON (initPid and runId) to FD once the sandbox is set up"),
+        )
+        .arg(
+            clap::Arg::with_name("remote")
+                .long("remote")
+                .value_name("HOST")
+                .help("Run on HOST via ssh (paths in cbuild.json refer to HOST)"),
+        )
+        .arg(
+            clap::Arg::with_name("remote-cbuildrt")
+                .long("remote-cbuildrt")
+                .value_name("PATH")
+                .default_value("cbuildrt")
+                .help("cbuildrt binary on the remote host"),
+        )
+        .arg(
+            clap::Arg::with_name("remote-rootfs")
+                .long("remote-rootfs")
+                .value_name("DIR")
+                .requires("remote")
+                .help("Use the rootfs DIR on the remote host instead of the configured one"),
+        )
+        .subcommands(subcommands())
 }
 
-// Concatenates lhs and rhs as-if the rhs was a relative path.
-fn concat_absolute<L: AsRef<Path>, R: AsRef<Path>>(lhs: L, rhs: R) -> PathBuf {
-    lhs.as_ref().join(rhs.as_ref().strip_prefix("/").unwrap())
+fn subcommands() -> Vec<clap::App<'static, 'static>> {
+    vec![
+        clap::SubCommand::with_name("attach")
+            .about("Attach the terminal to the console of a detached sandbox")
+            .arg(clap::Arg::with_name("id").help("Run ID").required(true)),
+        clap::SubCommand::with_name("batch")
+            .about("Run multiple cbuild.json files concurrently")
+            .arg(
+                clap::Arg::with_name("cbuild-json")
+                    .help("cbuild.json files")
+                    .multiple(true)
+                    .required_unless("manifest"),
+            )
+            .arg(
+                clap::Arg::with_name("manifest")
+                    .long("manifest")
+                    .value_name("FILE")
+                    .help("JSON file that lists the jobs and their dependencies (see README.md)"),
+            )
+            .arg(
+                clap::Arg::with_name("jobs")
+                    .long("jobs")
+                    .short("j")
+                    .value_name("N")
+                    .help(
+                        "Number of sandboxes that run at the same time [default: number of CPUs]",
+                    ),
+            )
+            .arg(
+                clap::Arg::with_name("keep-going")
+                    .long("keep-going")
+                    .short("k")
+                    .help("Keep starting jobs that do not depend on failed jobs"),
+            ),
+        clap::SubCommand::with_name("check")
+            .about("Check which features of cbuildrt the host supports"),
+        clap::SubCommand::with_name("commit")
+            .about("Fold the changes of an overlayfs rootfs into a new rootfs or a layer")
+            .arg(
+                clap::Arg::with_name("run")
+                    .value_name("ID|UPPERDIR")
+                    .help(
+                        "ID of a run whose rootfs is an overlayfs mount (see --keep-state), \
+                        or the upper directory of such a mount",
+                    )
+                    .required(true),
+            )
+            .arg(
+                clap::Arg::with_name("lower")
+                    .long("lower")
+                    .value_name("DIR")
+                    .help(
+                        "Lower (pristine) rootfs; defaults to the lower directory \
+                        of the run's overlayfs mount",
+                    ),
+            )
+            .arg(
+                clap::Arg::with_name("to")
+                    .long("to")
+                    .value_name("DIR")
+                    .help("Write a new rootfs (the lower rootfs with the changes applied) to DIR"),
+            )
+            .arg(
+                clap::Arg::with_name("layer")
+                    .long("layer")
+                    .value_name("FILE")
+                    .help("Write the changes as a layer archive (e.g., out.tar.zst) to FILE"),
+            )
+            .group(
+                clap::ArgGroup::with_name("output")
+                    .args(&["to", "layer"])
+                    .required(true),
+            ),
+        clap::SubCommand::with_name("completions")
+            .about("Print a shell completion script")
+            .arg(
+                clap::Arg::with_name("shell")
+                    .possible_values(&["bash", "zsh", "fish"])
+                    .required(true),
+            ),
+        clap::SubCommand::with_name("create")
+            .about("Create an OCI container from a bundle (without starting its process)")
+            .arg(clap::Arg::with_name("id").required(true))
+            .arg(
+                clap::Arg::with_name("bundle")
+                    .long("bundle")
+                    .short("b")
+                    .value_name("DIR")
+                    .default_value(".")
+                    .help("Bundle directory that contains config.json"),
+            )
+            .arg(
+                clap::Arg::with_name("pid-file")
+                    .long("pid-file")
+                    .value_name("FILE")
+                    .help("Write the PID of the container's init to FILE"),
+            ),
+        clap::SubCommand::with_name("delete")
+            .about("Delete a stopped OCI container")
+            .arg(clap::Arg::with_name("id").required(true))
+            .arg(
+                clap::Arg::with_name("force")
+                    .long("force")
+                    .short("f")
+                    .help("Kill the container if it is still running"),
+            ),
+        clap::SubCommand::with_name("diff")
+            .about("List the changes of an overlayfs rootfs relative to its lower directory")
+            .arg(
+                clap::Arg::with_name("run")
+                    .value_name("ID|UPPERDIR")
+                    .help(
+                        "ID of a run whose rootfs is an overlayfs mount (see --keep-state), \
+                        or the upper directory of such a mount",
+                    )
+                    .required(true),
+            )
+            .arg(
+                clap::Arg::with_name("lower")
+                    .long("lower")
+                    .value_name("DIR")
+                    .help(
+                        "Lower (pristine) rootfs; defaults to the lower directory \
+                        of the run's overlayfs mount",
+                    ),
+            )
+            .arg(
+                clap::Arg::with_name("tar")
+                    .long("tar")
+                    .value_name("FILE")
+                    .help("Also write the added and modified files to a tar archive"),
+            ),
+        clap::SubCommand::with_name("kill")
+            .about("Send a signal to an OCI container")
+            .arg(clap::Arg::with_name("id").required(true))
+            .arg(clap::Arg::with_name("signal").default_value("SIGTERM")),
+        clap::SubCommand::with_name("logs")
+            .about("Print the output of a sandbox")
+            .arg(clap::Arg::with_name("id").help("Run ID").required(true))
+            .arg(
+                clap::Arg::with_name("follow")
+                    .long("follow")
+                    .short("f")
+                    .help("Keep printing new output until the sandbox exits"),
+            ),
+        clap::SubCommand::with_name("man").about("Print a man page in roff format"),
+        clap::SubCommand::with_name("ps")
+            .about("List the processes of a running sandbox")
+            .arg(
+                clap::Arg::with_name("id")
+                    .help("Run ID (or ID of an OCI container)")
+                    .required(true),
+            ),
+        clap::SubCommand::with_name("self-test")
+            .about("Run a throwaway sandbox to test the runtime on this host"),
+        clap::SubCommand::with_name("start")
+            .about("Start the process of a created OCI container")
+            .arg(clap::Arg::with_name("id").required(true)),
+        clap::SubCommand::with_name("state")
+            .about("Print the state of an OCI container")
+            .arg(clap::Arg::with_name("id").required(true)),
+        clap::SubCommand::with_name("stats")
+            .about("Periodically print the cgroup resource usage of a running sandbox")
+            .arg(
+                clap::Arg::with_name("id")
+                    .help("Run ID (or ID of an OCI container)")
+                    .required(true),
+            )
+            .arg(
+                clap::Arg::with_name("interval")
+                    .long("interval")
+                    .value_name("DURATION")
+                    .default_value("2s")
+                    .help("Time between samples (e.g., 500ms, 2s or 1m)"),
+            ),
+        clap::SubCommand::with_name("version")
+            .about("Print the version and build metadata of cbuildrt"),
+        clap::SubCommand::with_name("watch")
+            .about("Re-run a cbuild.json file whenever watched host paths change")
+            .arg(
+                clap::Arg::with_name("cbuild-json")
+                    .help("cbuild.json file")
+                    .required(true),
+            )
+            .arg(
+                clap::Arg::with_name("paths")
+                    .long("paths")
+                    .value_name("PATH")
+                    .multiple(true)
+                    .required(true)
+                    .help("Host paths to watch (directories are watched recursively)"),
+            ),
+        clap::SubCommand::with_name("validate")
+            .about("Check a cbuild.json file without running it")
+            .arg(
+                clap::Arg::with_name("cbuild-json")
+                    .help("cbuild.json file")
+                    .required(true),
+            ),
+    ]
 }
 
-fn run_init(cfg: &Config) -> ! {
-    // We can now set up the remaining namespaces and perform mounts.
-    let mut clone_flags = nix::sched::CloneFlags::CLONE_NEWNS;
-    if cfg.isolate_network {
-        clone_flags |= nix::sched::CloneFlags::CLONE_NEWNET;
+fn main() {
+    // The probe of self-test is dispatched before clap since clap's bash completion
+    // generator panics on its (hidden) subcommand.
+    #[cfg(target_os = "linux")]
+    {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.first().map(String::as_str) == Some(cli::selftest::PROBE) {
+            let host_namespaces: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            exit(cli::selftest::probe(&host_namespaces));
+        }
     }
-    nix::sched::unshare(clone_flags).expect("failed to unshare()");
-
-    // First, we need to get a read-only rootfs.
-    // Mounting with MS_BIND ignored MS_RDONLY, but MS_REMOUNT respects it.
-
-    nix::mount::mount(
-        Some(&cfg.rootfs),
-        &cfg.rootfs,
-        None::<&str>,
-        nix::mount::MsFlags::MS_BIND,
-        None::<&str>,
-    )
-    .expect("failed to bind mount rootfs to itself");
 
-    // The fs might be mounted as nosuid/nodev and we will not have permissions
-    // to strip these mount options.
-    // Instead of parsing the current mount table, just set these flags unconditionally for now.
-    nix::mount::mount(
-        Some(&cfg.rootfs),
-        &cfg.rootfs,
-        None::<&str>,
-        nix::mount::MsFlags::MS_REMOUNT
-            | nix::mount::MsFlags::MS_BIND
-            | nix::mount::MsFlags::MS_RDONLY
-            | nix::mount::MsFlags::MS_NOSUID
-            | nix::mount::MsFlags::MS_NODEV,
-        None::<&str>,
-    )
-    .expect("failed to make rootfs read-only");
+    let matches = make_app().get_matches();
+    let format = OutputFormat::from_matches(&matches);
+    let state_dir = || StateDir::new(matches.value_of("root"));
 
-    // Perform mounts of /dev, /dev/pts, /dev/shm, /run, /tmp and /proc.
-
-    let dev_overlays = vec!["tty", "null", "zero", "full", "random", "urandom"];
-    for f in dev_overlays {
-        nix::mount::mount(
-            Some(&Path::new("/dev/").join(f)),
-            &concat_absolute(&cfg.rootfs, "/dev/").join(f),
-            None::<&str>,
-            nix::mount::MsFlags::MS_BIND,
-            None::<&str>,
-        )
-        .expect("failed to mount device");
-    }
-
-    if !cfg.isolate_network {
-        nix::mount::mount(
-            Some(&std::fs::canonicalize("/etc/resolv.conf").unwrap()),
-            &concat_absolute(&cfg.rootfs, "/etc/resolv.conf"),
-            None::<&str>,
-            nix::mount::MsFlags::MS_BIND,
-            None::<&str>,
-        )
-        .expect("failed to mount /etc/resolv.conf");
+    if let Some(code) = matches.value_of("explain") {
+        exit(cli::explain::run(code));
     }
 
-    nix::mount::mount(
-        None::<&str>,
-        &concat_absolute(&cfg.rootfs, "/dev/pts"),
-        Some("devpts"),
-        nix::mount::MsFlags::empty(),
-        None::<&str>,
-    )
-    .expect("failed to mount /dev/pts");
-
-    nix::mount::mount(
-        None::<&str>,
-        &concat_absolute(&cfg.rootfs, "/dev/shm"),
-        Some("tmpfs"),
-        nix::mount::MsFlags::empty(),
-        None::<&str>,
-    )
-    .expect("failed to mount /dev/shm");
-
-    nix::mount::mount(
-        None::<&str>,
-        &concat_absolute(&cfg.rootfs, "/run"),
-        Some("tmpfs"),
-        nix::mount::MsFlags::empty(),
-        None::<&str>,
-    )
-    .expect("failed to mount /run");
-
-    nix::mount::mount(
-        None::<&str>,
-        &concat_absolute(&cfg.rootfs, "/tmp"),
-        Some("tmpfs"),
-        nix::mount::MsFlags::empty(),
-        None::<&str>,
-    )
-    .expect("failed to mount /tmp");
-
-    nix::mount::mount(
-        None::<&str>,
-        &concat_absolute(&cfg.rootfs, "/proc"),
-        Some("proc"),
-        nix::mount::MsFlags::empty(),
-        None::<&str>,
-    )
-    .expect("failed to mount /proc");
-
-    // Perform bind mounts requested by user.
-    for bm in &cfg.bind_mounts {
-        nix::mount::mount(
-            Some(&bm.source),
-            &concat_absolute(&cfg.rootfs, &bm.destination),
-            None::<&str>,
-            nix::mount::MsFlags::MS_BIND | nix::mount::MsFlags::MS_REC,
-            None::<&str>,
-        )
-        .expect("failed to perform bind mount");
-    }
-
-    // chroot() and change the current directory to /.
-    nix::unistd::chroot(&cfg.rootfs).expect("failed to chroot()");
-    nix::unistd::chdir("/").expect("failed to chdir() to root directory");
-
-    // TODO: We could drop privileges here.
-    //       (However, cbuildrt does not really protect against malicious sandbox escapes.)
-
-    // fork() and execve() in the child.
-    // The parent waits for the child to terminate.
-    // (We cannot use Rust's high-level API since we need to reap orphans.)
-    match unsafe { nix::unistd::fork() } {
-        Ok(nix::unistd::ForkResult::Child) => {
-            // Reset PATH to the default value
-            if cfg.user.uid == 0 {
-                std::env::set_var(
-                    "PATH",
-                    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
-                );
-            } else {
-                std::env::set_var("PATH", "/usr/local/bin:/usr/bin:/bin");
-            }
-
-            let exec_result = nix::unistd::execvp(
-                &CString::new(cfg.process.args[0].as_str()).unwrap(),
-                &cfg.process
-                    .args
-                    .iter()
-                    .map(|a| CString::new(a.as_str()).unwrap())
-                    .collect::<Vec<_>>(),
-            );
-            eprintln!("error when executing program: {}", exec_result.unwrap_err());
-            exit(1);
+    let code = match matches.subcommand() {
+        ("batch", Some(m)) => cli::batch::run(format, m),
+        ("check", Some(_)) => cli::check::run(format),
+        ("create", Some(m)) => cli::oci::create(
+            &state_dir(),
+            m.value_of("id").unwrap(),
+            Path::new(m.value_of("bundle").unwrap()),
+            m.value_of("pid-file").map(Path::new),
+        ),
+        ("start", Some(m)) => cli::oci::start(&state_dir(), m.value_of("id").unwrap()),
+        ("state", Some(m)) => cli::oci::state(&state_dir(), m.value_of("id").unwrap()),
+        ("kill", Some(m)) => cli::oci::kill(
+            &state_dir(),
+            m.value_of("id").unwrap(),
+            m.value_of("signal").unwrap(),
+        ),
+        ("delete", Some(m)) => cli::oci::delete(
+            &state_dir(),
+            m.value_of("id").unwrap(),
+            m.is_present("force"),
+        ),
+        #[cfg(target_os = "linux")]
+        ("commit", Some(m)) => cli::commit::run(
+            &state_dir(),
+            m.value_of("run").unwrap(),
+            m.value_of("lower").map(Path::new),
+            m.value_of("to").map(Path::new),
+            m.value_of("layer").map(Path::new),
+        ),
+        #[cfg(target_os = "linux")]
+        ("diff", Some(m)) => cli::diff::run(
+            format,
+            &state_dir(),
+            m.value_of("run").unwrap(),
+            m.value_of("lower").map(Path::new),
+            m.value_of("tar").map(Path::new),
+        ),
+        #[cfg(target_os = "linux")]
+        ("ps", Some(m)) => cli::ps::run(format, &state_dir(), m.value_of("id").unwrap()),
+        #[cfg(target_os = "linux")]
+        ("stats", Some(m)) => cli::stats::run(
+            format,
+            &state_dir(),
+            m.value_of("id").unwrap(),
+            m.value_of("interval").unwrap(),
+        ),
+        #[cfg(target_os = "linux")]
+        ("self-test", Some(_)) => cli::selftest::run(format),
+        ("attach", Some(m)) => cli::attach::run(&state_dir(), m.value_of("id").unwrap()),
+        ("logs", Some(m)) => cli::logs::run(
+            &state_dir(),
+            m.value_of("id").unwrap(),
+            m.is_present("follow"),
+        ),
+        ("man", Some(_)) => cli::man::run(make_app(), subcommands()),
+        ("completions", Some(m)) => cli::completions::run(make_app(), m.value_of("shell").unwrap()),
+        ("version", Some(_)) => cli::version::run(format),
+        #[cfg(target_os = "linux")]
+        ("watch", Some(m)) => cli::watch::run(format, m),
+        #[cfg(not(target_os = "linux"))]
+        (name @ "self-test", Some(_))
+        | (name @ "watch", Some(_))
+        | (name @ "diff", Some(_))
+        | (name @ "commit", Some(_))
+        | (name @ "ps", Some(_))
+        | (name @ "stats", Some(_)) => {
+            eprintln!("{} is only supported on Linux", name);
+            1
         }
-        Ok(nix::unistd::ForkResult::Parent { child: child_pid }) => {
-            loop {
-                // Now, let's wait for the child to terminate.
-                let child_status = nix::sys::wait::wait().expect("failed to wait for children");
-                if let nix::sys::wait::WaitStatus::Exited(pid, code) = child_status {
-                    if pid == child_pid {
-                        if code != 0 {
-                            eprintln!("child returned non-zero exit code");
-                        }
-                        exit(code);
-                    }
-                }
-            }
+        ("validate", Some(m)) => {
+            cli::validate::run(format, Path::new(m.value_of("cbuild-json").unwrap()))
         }
-        Err(_) => panic!("failed to fork from init"),
+        _ => cli::run::run(format, &matches, &state_dir()),
     };
+    exit(code);
 }
 
-fn main() {
-    let cfg = make_config_from_cli();
-
-    let lockfile_path = cfg
-        .rootfs
-        .parent()
-        .and_then(|p| Some(p.join(cfg.rootfs.file_name()?)))
-        .map(|p| p.with_extension("cbrt_lock"))
-        .expect("couldn't construct lockfile path");
-
-    let root_dir = open(
-        &lockfile_path,
-        OFlag::O_RDONLY | OFlag::O_CREAT | OFlag::O_CLOEXEC,
-        Mode::from_bits(0o444).unwrap(),
-    )
-    .expect("couldn't open rootfs for locking");
-
-    flock(root_dir, FlockArg::LockShared).expect("failed to lock rootdir");
-
-    let euid = nix::unistd::geteuid();
-    let egid = nix::unistd::getegid();
-
-    // Enter the user namespace and let children enter a new PID namespace.
-    // We cannot do mounts in this process yet, as this the process itself
-    // is not moved to the new PID namespace.
-    nix::sched::unshare(
-        nix::sched::CloneFlags::CLONE_NEWUSER | nix::sched::CloneFlags::CLONE_NEWPID,
-    )
-    .expect("failed to unshare()");
-
-    // Write the uid_map and gid_map files. Linux demands that we write setgroups first
-    // (otherwise, we need to be root in the outer namespace).
-
-    std::fs::write("/proc/self/setgroups", "deny").expect("unable to write setgroups file");
-
-    std::fs::write("/proc/self/uid_map", format!("{} {} 1", cfg.user.uid, euid))
-        .expect("unable to write uid_map file");
-    std::fs::write("/proc/self/gid_map", format!("{} {} 1", cfg.user.gid, egid))
-        .expect("unable to write gid_map file");
-
-    // Change user IDs.
-    nix::unistd::setuid(nix::unistd::Uid::from_raw(cfg.user.uid)).expect("failed to set UID");
-    nix::unistd::setgid(nix::unistd::Gid::from_raw(cfg.user.gid)).expect("failed to set GID");
-
-    // fork() and run init in the child.
-    // The parent waits for the child to terminate.
-    match unsafe { nix::unistd::fork() } {
-        Ok(nix::unistd::ForkResult::Child) => run_init(&cfg),
-        Ok(nix::unistd::ForkResult::Parent { child: init_pid }) => {
-            eprintln!("PID init is {} (outside the namespace)", init_pid);
-
-            // Wait for init to terminate.
-            let init_status =
-                nix::sys::wait::waitpid(init_pid, None).expect("failed to wait for init");
-            let init_code = match init_status {
-                nix::sys::wait::WaitStatus::Exited(_, code) => code,
-                _ => panic!("waiting for init returned {:?}", init_status),
-            };
-            exit(init_code);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_completions() {
+        for shell in [clap::Shell::Bash, clap::Shell::Zsh, clap::Shell::Fish] {
+            let mut script = Vec::new();
+            make_app().gen_completions_to("cbuildrt", shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("self-test"), "{}", shell);
+            assert!(!script.contains(cli::selftest::PROBE), "{}", shell);
         }
-        Err(_) => panic!("failed to fork from cbuildrt"),
-    };
+    }
 }
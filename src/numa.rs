@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+fn node_dir(node: u32) -> PathBuf {
+    PathBuf::from(format!("/sys/devices/system/node/node{}", node))
+}
+
+// Returns the CPUs of a NUMA node in the list format of sysfs and cpusets (e.g., "0-7,16-23").
+pub fn cpu_list(node: u32) -> Result<String, String> {
+    let path = node_dir(node).join("cpulist");
+    std::fs::read_to_string(&path)
+        .map(|list| list.trim().to_string())
+        .map_err(|e| format!("NUMA node {} is not available: {}", node, e))
+}
+
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+        let mut bounds = range.splitn(2, '-').map(|b| b.parse::<usize>().unwrap());
+        let first = bounds.next().unwrap();
+        let last = bounds.next().unwrap_or(first);
+        cpus.extend(first..=last);
+    }
+    cpus
+}
+
+// Restricts the CPUs and memory allocations of the current process (and its future children)
+// to a NUMA node. In contrast to a cpuset, the process could widen these again.
+pub fn bind(node: u32) -> Result<(), crate::Error> {
+    let list = cpu_list(node).map_err(crate::Error::Setup)?;
+    let mut cpus = nix::sched::CpuSet::new();
+    for cpu in parse_cpu_list(&list) {
+        cpus.set(cpu).expect("CPU number is out of range");
+    }
+    nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), &cpus)
+        .map_err(crate::setup_error("set CPU affinity"))?;
+
+    let bits = std::mem::size_of::<libc::c_ulong>() * 8;
+    let mut mask = vec![0 as libc::c_ulong; node as usize / bits + 1];
+    mask[node as usize / bits] |= 1 << (node as usize % bits);
+    // The kernel only considers maxnode - 1 bits.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            libc::MPOL_BIND,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+        )
+    };
+    if result < 0 {
+        return Err(crate::setup_error(format!(
+            "bind memory to NUMA node {}",
+            node
+        ))(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
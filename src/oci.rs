@@ -0,0 +1,277 @@
+// Translation of OCI runtime bundles into cbuildrt configurations.
+// Only the subset of the runtime spec that maps onto cbuildrt's features is supported.
+// Unsupported mounts and process settings are rejected, except for the mounts of the spec's
+// default configuration; other settings (e.g., cgroups or seccomp) are ignored. Of the capabilities, only the ambient set is carried over.
+
+use crate::{BindMount, Config, Error, Process, User};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Spec {
+    root: Root,
+    process: Option<SpecProcess>,
+    hostname: Option<String>,
+    #[serde(default)]
+    mounts: Vec<Mount>,
+    linux: Option<Linux>,
+}
+
+#[derive(Deserialize)]
+struct Root {
+    path: PathBuf,
+    #[serde(default)]
+    readonly: bool,
+}
+
+#[derive(Deserialize)]
+struct SpecProcess {
+    args: Vec<String>,
+    user: User,
+    cwd: Option<PathBuf>,
+    #[serde(default)]
+    env: Vec<String>,
+    #[serde(default)]
+    terminal: bool,
+    capabilities: Option<Capabilities>,
+}
+
+#[derive(Deserialize)]
+struct Capabilities {
+    #[serde(default)]
+    ambient: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Mount {
+    destination: PathBuf,
+    source: Option<PathBuf>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Linux {
+    #[serde(default)]
+    namespaces: Vec<Namespace>,
+}
+
+#[derive(Deserialize)]
+struct Namespace {
+    #[serde(rename = "type")]
+    kind: String,
+    path: Option<PathBuf>,
+}
+
+// Mounts (by type and destination) that cbuildrt performs itself. Their options are ignored.
+const BUILTIN_MOUNTS: &[(&str, &str)] = &[
+    ("proc", "/proc"),
+    ("tmpfs", "/dev"),
+    ("devpts", "/dev/pts"),
+    ("tmpfs", "/dev/shm"),
+    ("shm", "/dev/shm"),
+    ("tmpfs", "/run"),
+    ("tmpfs", "/tmp"),
+];
+
+// Mounts of the runtime spec's default configuration (which podman and containerd emit) that
+// cbuildrt does not provide. They are skipped; the process sees the rootfs' directories.
+const SKIPPED_MOUNTS: &[(&str, &str)] = &[
+    ("sysfs", "/sys"),
+    ("cgroup", "/sys/fs/cgroup"),
+    ("cgroup2", "/sys/fs/cgroup"),
+    ("mqueue", "/dev/mqueue"),
+];
+
+fn unsupported<T, S: AsRef<str>>(msg: S) -> Result<T, Error> {
+    Err(Error::Unsupported(format!("OCI bundle: {}", msg.as_ref())))
+}
+
+// Reads config.json of an OCI bundle.
+pub fn config_from_bundle(bundle: &Path) -> Result<Config, Error> {
+    let path = bundle.join("config.json");
+    let f = std::fs::File::open(&path)
+        .map_err(|e| Error::InvalidConfig(format!("unable to open {}: {}", path.display(), e)))?;
+    let spec: Spec = serde_json::from_reader(f)
+        .map_err(|e| Error::InvalidConfig(format!("failed to parse {}: {}", path.display(), e)))?;
+
+    let process = match spec.process {
+        Some(process) => process,
+        None => return unsupported("process is required"),
+    };
+    if process.terminal {
+        return unsupported("process.terminal is not supported");
+    }
+
+    let mut cfg = Config {
+        rootfs: bundle.join(&spec.root.path),
+        user: process.user,
+        // process.env is the complete environment of the process.
+        process: Process {
+            args: process.args,
+            script: None,
+            environ: BTreeMap::new(),
+            clear_environ: true,
+            cwd: process.cwd,
+        },
+        rootfs_writable: !spec.root.readonly,
+        hostname: spec.hostname,
+        ambient_capabilities: process.capabilities.map(|c| c.ambient).unwrap_or_default(),
+        ..Config::default()
+    };
+
+    for var in process.env {
+        match var.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                cfg.process
+                    .environ
+                    .insert(key.to_string(), value.to_string());
+            }
+            _ => {
+                return Err(Error::InvalidConfig(format!(
+                    "OCI bundle: {:?} is not a valid entry of process.env",
+                    var
+                )))
+            }
+        }
+    }
+
+    for ns in spec.linux.iter().flat_map(|l| l.namespaces.iter()) {
+        if ns.path.is_some() {
+            return unsupported("joining existing namespaces is not supported");
+        }
+        if ns.kind == "network" {
+            cfg.isolate_network = true;
+        }
+    }
+
+    // cbuildrt sets up /proc, /dev, /run and /tmp itself; other mounts must be bind mounts.
+    for mount in spec.mounts {
+        let bind = mount.kind.as_deref() == Some("bind")
+            || mount.options.iter().any(|o| o == "bind" || o == "rbind");
+        let destination = mount.destination;
+        let kind = mount.kind.unwrap_or_else(|| "none".to_string());
+        let builtin = BUILTIN_MOUNTS
+            .iter()
+            .chain(SKIPPED_MOUNTS)
+            .any(|(k, d)| *k == kind && destination == Path::new(d));
+        match (bind, mount.source) {
+            (true, Some(source)) => cfg.bind_mounts.push(BindMount {
+                destination,
+                source: bundle.join(source),
+                verify_writable: false,
+            }),
+            (true, None) => {
+                return unsupported(format!(
+                    "bind mount at {} lacks a source",
+                    destination.display()
+                ))
+            }
+            (false, _) if builtin => (),
+            (false, _) => {
+                return unsupported(format!(
+                    "{} mount at {} is not supported",
+                    kind,
+                    destination.display()
+                ))
+            }
+        }
+    }
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes config.json into a fresh bundle directory.
+    fn bundle(name: &str, spec: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cbuildrt-oci-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), spec).unwrap();
+        dir
+    }
+
+    #[test]
+    fn translates_supported_settings() {
+        let dir = bundle(
+            "supported",
+            r#"{
+                "root": {"path": "rootfs", "readonly": true},
+                "process": {
+                    "args": ["sh"],
+                    "user": {"uid": 1000, "gid": 100},
+                    "cwd": "/build",
+                    "env": ["PATH=/bin", "EMPTY="],
+                    "capabilities": {"ambient": ["CAP_NET_RAW"]}
+                },
+                "hostname": "build",
+                "mounts": [
+                    {"destination": "/proc", "type": "proc", "source": "proc"},
+                    {"destination": "/dev", "type": "tmpfs", "source": "tmpfs",
+                     "options": ["nosuid", "strictatime", "mode=755", "size=65536k"]},
+                    {"destination": "/dev/shm", "type": "shm", "source": "shm"},
+                    {"destination": "/dev/mqueue", "type": "mqueue", "source": "mqueue"},
+                    {"destination": "/sys", "type": "sysfs", "source": "sysfs",
+                     "options": ["nosuid", "noexec", "nodev", "ro"]},
+                    {"destination": "/sys/fs/cgroup", "type": "cgroup", "source": "cgroup"},
+                    {"destination": "/src", "type": "bind", "source": "src", "options": ["rbind"]}
+                ],
+                "linux": {"namespaces": [{"type": "pid"}, {"type": "network"}]}
+            }"#,
+        );
+        let cfg = config_from_bundle(&dir).unwrap();
+        assert_eq!(cfg.rootfs, dir.join("rootfs"));
+        assert!(!cfg.rootfs_writable);
+        assert_eq!((cfg.user.uid, cfg.user.gid), (1000, 100));
+        assert_eq!(cfg.process.args, ["sh"]);
+        assert_eq!(cfg.process.environ["PATH"], "/bin");
+        assert_eq!(cfg.process.environ["EMPTY"], "");
+        assert!(cfg.process.clear_environ);
+        assert_eq!(cfg.process.cwd.as_deref(), Some(Path::new("/build")));
+        assert_eq!(cfg.hostname.as_deref(), Some("build"));
+        assert_eq!(cfg.ambient_capabilities, ["CAP_NET_RAW"]);
+        assert!(cfg.isolate_network);
+        assert_eq!(cfg.bind_mounts.len(), 1);
+        assert_eq!(cfg.bind_mounts[0].destination, Path::new("/src"));
+        assert_eq!(cfg.bind_mounts[0].source, dir.join("src"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsupported_settings() {
+        let specs = [
+            r#"{"root": {"path": "rootfs"}}"#,
+            r#"{"root": {"path": "rootfs"},
+                "process": {"args": ["sh"], "user": {"uid": 0, "gid": 0}, "terminal": true}}"#,
+            r#"{"root": {"path": "rootfs"},
+                "process": {"args": ["sh"], "user": {"uid": 0, "gid": 0}},
+                "mounts": [{"destination": "/var/tmp", "type": "tmpfs"}]}"#,
+            r#"{"root": {"path": "rootfs"},
+                "process": {"args": ["sh"], "user": {"uid": 0, "gid": 0}},
+                "linux": {"namespaces": [{"type": "network", "path": "/run/netns/x"}]}}"#,
+        ];
+        for (i, spec) in specs.iter().enumerate() {
+            let dir = bundle(&format!("unsupported-{}", i), spec);
+            let result = config_from_bundle(&dir);
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert!(matches!(result, Err(Error::Unsupported(_))), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_env() {
+        let dir = bundle(
+            "env",
+            r#"{"root": {"path": "rootfs"},
+                "process": {"args": ["sh"], "user": {"uid": 0, "gid": 0}, "env": ["PATH"]}}"#,
+        );
+        let result = config_from_bundle(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+}
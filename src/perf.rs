@@ -0,0 +1,105 @@
+use std::path::Path;
+
+// Location of perf's output inside the sandbox. It is copied to the host after the run.
+const SANDBOX_OUTPUT: &str = "/run/cbuildrt/perf.data";
+
+// Location of the host's perf inside the sandbox (if the rootfs does not provide one).
+const SANDBOX_BINARY: &str = "/run/cbuildrt/perf";
+
+fn read_sysctl(name: &str) -> Option<i32> {
+    std::fs::read_to_string(format!("/proc/sys/kernel/{}", name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Warns about host policies that restrict what perf can record from inside the sandbox.
+pub fn check_host_policy() {
+    match read_sysctl("perf_event_paranoid") {
+        Some(level) if level > 2 => log!(
+            "warning: kernel.perf_event_paranoid is {}; perf_event_open() is likely \
+            disallowed for unprivileged users",
+            level
+        ),
+        Some(level) if level > 1 => log!(
+            "warning: kernel.perf_event_paranoid is {}; only user space can be profiled",
+            level
+        ),
+        _ => (),
+    }
+    // Kernel symbols are only visible if the kernel does not hide them from namespaced users.
+    if read_sysctl("kptr_restrict") != Some(0) {
+        log!("warning: kernel.kptr_restrict is set; kernel symbols will not be resolved");
+    }
+}
+
+// Makes perf and the host's sysfs (which perf uses to discover PMUs) available
+// inside the sandbox. Returns the path of perf inside the sandbox.
+// Must be called after /run has been mounted.
+pub fn setup(rootfs: &Path, cfg: &crate::Perf) -> Result<String, crate::Error> {
+    let sys = crate::concat_absolute(rootfs, "/sys");
+    if sys.is_dir() {
+        nix::mount::mount(
+            Some("/sys"),
+            &sys,
+            None::<&str>,
+            nix::mount::MsFlags::MS_BIND | nix::mount::MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .map_err(crate::setup_error("mount /sys"))?;
+        nix::mount::mount(
+            Some("/sys"),
+            &sys,
+            None::<&str>,
+            nix::mount::MsFlags::MS_REMOUNT
+                | nix::mount::MsFlags::MS_BIND
+                | nix::mount::MsFlags::MS_RDONLY
+                | crate::locked_mount_flags(Path::new("/sys"))?,
+            None::<&str>,
+        )
+        .map_err(crate::setup_error("make /sys read-only"))?;
+    } else {
+        log!("warning: rootfs does not contain /sys; perf will not find any PMUs");
+    }
+
+    crate::hosttool::provide(rootfs, "perf", cfg.perf.as_deref(), SANDBOX_BINARY)
+}
+
+// Returns the command line that runs args under perf record.
+pub fn wrap(binary: String, cfg: &crate::Perf, args: &[String]) -> Vec<String> {
+    let mut wrapped = vec![
+        binary,
+        "record".to_string(),
+        "-o".to_string(),
+        SANDBOX_OUTPUT.to_string(),
+    ];
+    if cfg.call_graph {
+        wrapped.push("-g".to_string());
+    }
+    if let Some(events) = &cfg.events {
+        wrapped.push("-e".to_string());
+        wrapped.push(events.clone());
+    }
+    wrapped.push("--".to_string());
+    wrapped.extend(args.iter().cloned());
+    wrapped
+}
+
+// Copies the recorded data to the host. Must be called after the process terminated.
+pub fn collect(rootfs: &Path, cfg: &crate::Perf) -> bool {
+    match std::fs::copy(crate::concat_absolute(rootfs, SANDBOX_OUTPUT), &cfg.output) {
+        Ok(_) => {
+            log!("perf data written to {}", cfg.output.display());
+            true
+        }
+        Err(e) => {
+            log!(
+                "failed to copy perf data to {}: {}",
+                cfg.output.display(),
+                e
+            );
+            false
+        }
+    }
+}
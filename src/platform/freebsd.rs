@@ -0,0 +1,461 @@
+// FreeBSD backend. The process runs in a jail whose root is the rootfs; bind mounts are
+// implemented by nullfs mounts. Unlike on Linux, mounts are not private to the sandbox:
+// the supervisor performs them on the host and unmounts them once the jail has terminated.
+// Only the core settings of the configuration are supported (see FreeBsd::check_config()).
+
+use crate::check::HostCheck;
+use crate::config::Config;
+use crate::handle::{send_event, Event, Handle};
+use crate::platform::Platform;
+use crate::sandbox::{self, copy_artifacts, Sandbox, StartGate};
+use crate::{concat_absolute, reproducible, runid, Error};
+use nix::fcntl::OFlag;
+use nix::unistd::Pid;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+// Not exported by the libc crate (see sys/procdesc.h and sys/jail.h).
+const PD_DAEMON: libc::c_int = 0x01;
+const PD_CLOEXEC: libc::c_int = 0x02;
+const JAIL_CREATE: libc::c_int = 0x01;
+const JAIL_ATTACH: libc::c_int = 0x04;
+const JAIL_SYS_DISABLE: libc::c_int = 0;
+const JAIL_SYS_INHERIT: libc::c_int = 2;
+
+// devfs ruleset that only exposes the devices that are safe to use in jails
+// (devfsrules_jail in /etc/defaults/devfs.rules).
+const DEVFS_RULESET: &str = "4";
+
+pub(crate) struct FreeBsd;
+
+impl Platform for FreeBsd {
+    fn check_config(cfg: &Config) -> Result<(), Error> {
+        let unsupported = [
+            ("sysctls", !cfg.sysctls.is_empty()),
+            ("process.script", cfg.process.script.is_some()),
+            ("ccache", cfg.ccache.is_some()),
+            ("sccache", cfg.sccache.is_some()),
+            ("distcc", cfg.distcc.is_some()),
+            ("proxy", cfg.proxy.is_some()),
+            ("downloadCache", cfg.download_cache.is_some()),
+            ("home", cfg.home.is_some()),
+            ("xdgDirs", cfg.xdg_dirs),
+            ("gui", cfg.gui),
+            ("dbus", cfg.dbus.is_some()),
+            ("locale", cfg.locale.is_some()),
+            ("secrets", !cfg.secrets.is_empty()),
+            (
+                "bindMounts[].verifyWritable",
+                cfg.bind_mounts.iter().any(|bm| bm.verify_writable),
+            ),
+            ("toolMounts", !cfg.tool_mounts.is_empty()),
+            ("sourceMounts", !cfg.source_mounts.is_empty()),
+            ("sysrootMounts", !cfg.sysroot_mounts.is_empty()),
+            ("accessManifest", cfg.access_manifest.is_some()),
+            ("traceSyscalls", cfg.trace_syscalls.is_some()),
+            ("perf", cfg.perf.is_some()),
+            ("qemuUser", cfg.qemu_user.is_some()),
+            ("debug", cfg.debug.is_some()),
+            ("staging", !cfg.staging.is_empty()),
+            ("disableAslr", cfg.disable_aslr),
+            ("console", cfg.console),
+            ("timeout", cfg.timeout.is_some()),
+            ("init", cfg.init.is_some()),
+            ("ambientCapabilities", !cfg.ambient_capabilities.is_empty()),
+            ("preload", !cfg.preload.is_empty()),
+            ("ldCache", cfg.ld_cache.is_some()),
+            ("hugepages", cfg.hugepages.is_some()),
+            ("resources", cfg.resources.is_some()),
+        ];
+        match unsupported.iter().find(|(_, set)| *set) {
+            Some((field, _)) => Err(Error::Unsupported(format!(
+                "{} is not supported on FreeBSD",
+                field
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn check_host() -> Vec<HostCheck> {
+        let root = nix::unistd::geteuid().is_root();
+        let jailed = sysctl_int("security.jail.jailed") == Some(1);
+        vec![
+            HostCheck {
+                name: "jails",
+                ok: root,
+                required: true,
+                detail: if root {
+                    "running as root".to_string()
+                } else {
+                    "creating jails requires root".to_string()
+                },
+            },
+            HostCheck {
+                name: "nestedJails",
+                ok: !jailed || sysctl_int("security.jail.children.max").unwrap_or(0) > 0,
+                required: false,
+                detail: if jailed {
+                    "cbuildrt runs inside a jail; its children.max must be positive".to_string()
+                } else {
+                    "cbuildrt does not run inside a jail".to_string()
+                },
+            },
+        ]
+    }
+
+    fn spawn(sandbox: &Sandbox) -> Result<Handle, Error> {
+        let cfg = &sandbox.cfg;
+        if !cfg.rootfs.is_dir() {
+            return Err(Error::Rootfs {
+                rootfs: cfg.rootfs.clone(),
+                reason: "is not a directory".to_string(),
+            });
+        }
+        let run_id = cfg.run_id.clone().unwrap_or_else(runid::generate);
+        let teardown = sandbox::prepare(sandbox, &run_id)?;
+
+        // The supervisor and the process report events through this pipe.
+        let (events_read, events_write) = nix::unistd::pipe2(OFlag::O_CLOEXEC)
+            .map_err(crate::setup_error("create events pipe"))?;
+        let events = unsafe { File::from_raw_fd(events_read) };
+
+        // Like pidfds on Linux, process descriptors can be polled for the termination of the
+        // supervisor. With PD_DAEMON, closing the descriptor does not kill the supervisor.
+        let mut fd: libc::c_int = -1;
+        let supervisor_pid = match unsafe { libc::pdfork(&mut fd, PD_DAEMON | PD_CLOEXEC) } {
+            -1 => {
+                return Err(Error::Setup(format!(
+                    "pdfork() failed: {}",
+                    std::io::Error::last_os_error()
+                )))
+            }
+            0 => {
+                drop(events);
+                runid::set_current(&run_id);
+                supervise(sandbox, events_write)
+            }
+            pid => Pid::from_raw(pid),
+        };
+        nix::unistd::close(events_write).map_err(crate::setup_error("close events pipe"))?;
+
+        let process = unsafe { File::from_raw_fd(fd) };
+        Handle::new(supervisor_pid, process, run_id, events, teardown)
+    }
+
+    fn process_fd(_pid: Pid) -> std::io::Result<File> {
+        // Process descriptors can only be obtained through pdfork().
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "process descriptors of existing processes are not supported",
+        ))
+    }
+
+    fn send_signal(pid: Pid, _process: Option<&File>, signal: libc::c_int) -> std::io::Result<()> {
+        if unsafe { libc::kill(pid.as_raw(), signal) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn sysctl_int(name: &str) -> Option<libc::c_int> {
+    let name = CString::new(name).unwrap();
+    let mut value: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let result = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    Some(value).filter(|_| result == 0)
+}
+
+// Builds the iovec array of name/value pairs that nmount() and jail_set() take.
+// Strings are passed with their terminating NUL byte.
+struct Params {
+    storage: Vec<Vec<u8>>,
+}
+
+impl Params {
+    fn new() -> Params {
+        Params {
+            storage: Vec::new(),
+        }
+    }
+
+    fn raw(mut self, name: &str, value: Vec<u8>) -> Params {
+        self.storage
+            .push(CString::new(name).unwrap().into_bytes_with_nul());
+        self.storage.push(value);
+        self
+    }
+
+    fn string<S: AsRef<OsStr>>(self, name: &str, value: S) -> Params {
+        let value = CString::new(value.as_ref().as_bytes()).unwrap();
+        self.raw(name, value.into_bytes_with_nul())
+    }
+
+    fn int(self, name: &str, value: libc::c_int) -> Params {
+        self.raw(name, value.to_ne_bytes().to_vec())
+    }
+
+    fn iovecs(&mut self) -> Vec<libc::iovec> {
+        self.storage
+            .iter_mut()
+            .map(|bytes| libc::iovec {
+                iov_base: bytes.as_mut_ptr() as *mut libc::c_void,
+                iov_len: bytes.len(),
+            })
+            .collect()
+    }
+}
+
+// Mounts that the supervisor performed on the host. They are unmounted (in reverse order)
+// when this is dropped, i.e., also if setting up the sandbox fails.
+struct Mounts {
+    targets: Vec<PathBuf>,
+}
+
+impl Mounts {
+    fn mount(&mut self, params: Params, target: &Path, read_only: bool) {
+        let mut params = params.string("fspath", target);
+        let mut iovecs = params.iovecs();
+        let flags = if read_only { libc::MNT_RDONLY } else { 0 };
+        if unsafe { libc::nmount(iovecs.as_mut_ptr(), iovecs.len() as libc::c_uint, flags) } < 0 {
+            panic!(
+                "failed to mount {}: {}",
+                target.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+        self.targets.push(target.to_path_buf());
+    }
+
+    fn nullfs(&mut self, source: &Path, target: &Path, read_only: bool) {
+        let params = Params::new()
+            .string("fstype", "nullfs")
+            .string("target", source);
+        self.mount(params, target, read_only);
+    }
+
+    fn devfs(&mut self, target: &Path) {
+        let params = Params::new()
+            .string("fstype", "devfs")
+            .string("ruleset", DEVFS_RULESET);
+        self.mount(params, target, false);
+    }
+}
+
+impl Drop for Mounts {
+    fn drop(&mut self) {
+        for target in self.targets.iter().rev() {
+            if let Err(e) = unmount(target) {
+                log!("failed to unmount {}: {}", target.display(), e);
+            }
+        }
+    }
+}
+
+// Unmounts a mount of the supervisor. A mount can still be busy after the jail has terminated
+// (e.g., if a host process has its working directory below it). FreeBSD cannot detach mounts
+// lazily like Linux's MNT_DETACH, hence busy mounts are unmounted forcibly.
+fn unmount(target: &Path) -> std::io::Result<()> {
+    let path = CString::new(target.as_os_str().as_bytes()).unwrap();
+    if unsafe { libc::unmount(path.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() != Some(libc::EBUSY) {
+        return Err(e);
+    }
+    log!("{} is busy, unmounting it forcibly", target.display());
+    if unsafe { libc::unmount(path.as_ptr(), libc::MNT_FORCE) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Entry point of the supervisor process. As on Linux, errors and panics are reported as events.
+fn supervise(sandbox: &Sandbox, events: RawFd) -> ! {
+    // Panic messages are reported as events instead.
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_supervisor(sandbox, events)
+    }))
+    .unwrap_or_else(|payload| {
+        let msg = match payload.downcast::<String>() {
+            Ok(msg) => *msg,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(msg) => msg.to_string(),
+                Err(_) => "unknown error".to_string(),
+            },
+        };
+        Err(Error::Setup(msg))
+    });
+    let code = match result {
+        Ok(code) => code,
+        Err(e) => {
+            send_event(events, &Event::Failed(e));
+            1
+        }
+    };
+    // Do not run the caller's atexit() handlers in forked processes.
+    unsafe { libc::_exit(code) }
+}
+
+// Sets up the mounts and runs the process in a jail. This function only returns in the
+// forked process if executing the process fails.
+fn run_supervisor(sandbox: &Sandbox, events: RawFd) -> Result<i32, Error> {
+    let cfg = &sandbox.cfg;
+    let rootfs = cfg.rootfs.canonicalize().map_err(|e| Error::Rootfs {
+        rootfs: cfg.rootfs.clone(),
+        reason: format!("cannot be resolved: {}", e),
+    })?;
+
+    // Mount points cannot be created once the rootfs is read-only.
+    for bm in &cfg.bind_mounts {
+        let target = concat_absolute(&rootfs, &bm.destination);
+        std::fs::create_dir_all(&target)
+            .unwrap_or_else(|e| panic!("failed to create mount point {}: {}", target.display(), e));
+    }
+    let mut mounts = Mounts {
+        targets: Vec::new(),
+    };
+    if !cfg.rootfs_writable {
+        mounts.nullfs(&rootfs, &rootfs, true);
+    }
+    mounts.devfs(&rootfs.join("dev"));
+    for bm in &cfg.bind_mounts {
+        mounts.nullfs(
+            &bm.source,
+            &concat_absolute(&rootfs, &bm.destination),
+            false,
+        );
+    }
+
+    match unsafe { nix::unistd::fork() }.expect("failed to fork process") {
+        nix::unistd::ForkResult::Child => {
+            // The supervisor unmounts the mounts.
+            std::mem::forget(mounts);
+            let start_gate = sandbox
+                .start_fifo
+                .as_deref()
+                .map(StartGate::new)
+                .transpose()?;
+            run_process(cfg, &rootfs, events, start_gate)
+        }
+        nix::unistd::ForkResult::Parent { child } => {
+            log!("PID of the process is {}", child);
+            send_event(
+                events,
+                &Event::Started {
+                    init_pid: child.as_raw(),
+                    run_id: runid::current().unwrap().to_string(),
+                },
+            );
+            let mut code = loop {
+                match nix::sys::wait::waitpid(child, None).expect("failed to wait for process") {
+                    nix::sys::wait::WaitStatus::Exited(_, code) => break code,
+                    nix::sys::wait::WaitStatus::Signaled(_, signal, _) => {
+                        log!("process was killed by {}", signal);
+                        break 128 + signal as i32;
+                    }
+                    _ => (),
+                }
+            };
+            if code != 0 {
+                log!("process returned non-zero exit code");
+            }
+            // The jail is removed together with its last process. The bind mounts are
+            // still in place at this point.
+            if !copy_artifacts(cfg) && code == 0 {
+                code = 1;
+            }
+            Ok(code)
+        }
+    }
+}
+
+// Creates the jail, enters it and executes the process.
+fn run_process(
+    cfg: &Config,
+    rootfs: &Path,
+    events: RawFd,
+    start_gate: Option<StartGate>,
+) -> Result<i32, Error> {
+    let network = if cfg.network_isolated() {
+        JAIL_SYS_DISABLE
+    } else {
+        JAIL_SYS_INHERIT
+    };
+    let mut params = Params::new()
+        .string("path", rootfs)
+        .int("ip4", network)
+        .int("ip6", network);
+    if let Some(hostname) = cfg.hostname() {
+        params = params.string("host.hostname", hostname);
+    }
+    let mut iovecs = params.iovecs();
+    let flags = JAIL_CREATE | JAIL_ATTACH;
+    if unsafe { libc::jail_set(iovecs.as_mut_ptr(), iovecs.len() as libc::c_uint, flags) } < 0 {
+        panic!("failed to create jail: {}", std::io::Error::last_os_error());
+    }
+    let cwd = cfg.process.cwd.as_deref().unwrap_or_else(|| Path::new("/"));
+    std::env::set_current_dir(cwd).map_err(crate::setup_error(format!(
+        "change directory to {}",
+        cwd.display()
+    )))?;
+
+    let gid = nix::unistd::Gid::from_raw(cfg.user.gid);
+    nix::unistd::setgroups(&[gid]).expect("failed to set supplementary groups");
+    nix::unistd::setgid(gid).expect("failed to set GID");
+    nix::unistd::setuid(nix::unistd::Uid::from_raw(cfg.user.uid)).expect("failed to set UID");
+
+    // (SOURCE_DATE_EPOCH is read from the caller's environment.)
+    let reproducible_env = cfg
+        .reproducible
+        .then(|| reproducible::environment(cfg.source_date_epoch));
+    if cfg.reproducible || cfg.process.clear_environ {
+        for (key, _) in std::env::vars_os() {
+            std::env::remove_var(key);
+        }
+    }
+    if let Some(reproducible_env) = reproducible_env {
+        for (key, value) in reproducible_env {
+            std::env::set_var(key, value);
+        }
+        nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(
+            reproducible::UMASK,
+        ));
+    }
+    std::env::set_var("CBUILDRT_RUN_ID", runid::current().unwrap());
+    std::env::set_var("PATH", sandbox::default_path(cfg));
+    for (key, value) in &cfg.process.environ {
+        std::env::set_var(key, value);
+    }
+
+    send_event(events, &Event::Ready);
+    if let Some(gate) = start_gate {
+        gate.wait()?;
+    }
+
+    let args = &cfg.process.args;
+    let exec_result = nix::unistd::execvp(
+        &CString::new(args[0].as_str()).unwrap(),
+        &args
+            .iter()
+            .map(|a| CString::new(a.as_str()).unwrap())
+            .collect::<Vec<_>>(),
+    );
+    Err(Error::Exec {
+        program: args[0].clone(),
+        reason: exec_result.unwrap_err().to_string(),
+    })
+}
@@ -0,0 +1,78 @@
+use crate::check::HostCheck;
+
+fn read_sysctl(path: &str) -> Option<i64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn check_user_namespaces() -> (bool, String) {
+    match read_sysctl("/proc/sys/user/max_user_namespaces") {
+        Some(0) => return (false, "user.max_user_namespaces is 0".to_string()),
+        None => return (false, "user namespaces are not supported".to_string()),
+        Some(_) => (),
+    }
+    // Debian-specific knob.
+    if read_sysctl("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        return (false, "kernel.unprivileged_userns_clone is 0".to_string());
+    }
+    if read_sysctl("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        return (
+            false,
+            "kernel.apparmor_restrict_unprivileged_userns is 1".to_string(),
+        );
+    }
+    (true, "unprivileged user namespaces are enabled".to_string())
+}
+
+fn check_pidfd() -> (bool, String) {
+    match super::pidfd_open(nix::unistd::getpid()) {
+        Ok(_) => (true, "pidfd_open() is supported".to_string()),
+        Err(e) => (false, format!("pidfd_open() failed: {}", e)),
+    }
+}
+
+fn check_binfmt_misc() -> (bool, String) {
+    if std::path::Path::new("/proc/sys/fs/binfmt_misc/status").exists() {
+        (true, "binfmt_misc is mounted".to_string())
+    } else {
+        (
+            false,
+            "binfmt_misc is not mounted; foreign-architecture rootfs trees cannot be used"
+                .to_string(),
+        )
+    }
+}
+
+fn check_perf() -> (bool, String) {
+    match read_sysctl("/proc/sys/kernel/perf_event_paranoid") {
+        Some(level) if level > 2 => (false, format!("kernel.perf_event_paranoid is {}", level)),
+        Some(level) => (true, format!("kernel.perf_event_paranoid is {}", level)),
+        None => (false, "perf events are not supported".to_string()),
+    }
+}
+
+// Checks the Linux-specific features of cbuildrt.
+pub(crate) fn check_host() -> Vec<HostCheck> {
+    let mut checks = Vec::new();
+    let mut push = |name, required, (ok, detail): (bool, String)| {
+        checks.push(HostCheck {
+            name,
+            ok,
+            required,
+            detail,
+        })
+    };
+    push("userNamespaces", true, check_user_namespaces());
+    push("pidfd", true, check_pidfd());
+    push(
+        "accessTracing",
+        false,
+        if crate::trace::SUPPORTED {
+            (true, "ptrace()-based tracing is supported".to_string())
+        } else {
+            (false, "not supported on this architecture".to_string())
+        },
+    );
+    push("binfmtMisc", false, check_binfmt_misc());
+    push("perf", false, check_perf());
+    checks
+}
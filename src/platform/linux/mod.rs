@@ -0,0 +1,1136 @@
+// Linux backend. The sandbox's processes run in user, mount, PID, IPC and UTS (and optionally
+// network) namespaces; the rootfs and the host directories are bind mounted (see spawn_sandbox()).
+
+use crate::check::HostCheck;
+use crate::config::{Config, Proxy};
+use crate::handle::{send_event, Event, Handle};
+use crate::platform::Platform;
+use crate::sandbox::{self, copy_artifacts, invalid, Sandbox, StartGate};
+use crate::{
+    bind_into_sandbox, binfmt, caps, ccache, cgroup, concat_absolute, console, copy, dbus, debug,
+    distcc, downloadcache, enter_rootfs, gui, home, hugetlb, ldcache, locale, locked_mount_flags,
+    numa, perf, preload, proxy, ptree, reproducible, runid, sccache, script, secrets, setup_error,
+    strace, trace, usage, xbstrap, xdg, Error,
+};
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::unistd::Pid;
+use std::ffi::CString;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+mod check;
+
+// Not exported by nix::sys::statfs.
+const CIFS_MAGIC: u64 = 0xff53_4d42;
+
+pub(crate) struct Linux;
+
+impl Platform for Linux {
+    fn check_config(cfg: &Config) -> Result<(), Error> {
+        if let Some(name) = cfg
+            .ambient_capabilities
+            .iter()
+            .find(|name| caps::number(name).is_none())
+        {
+            return invalid(format!("{} is not a known capability", name));
+        }
+
+        for key in cfg.sysctls.keys() {
+            match sysctl_namespace(key) {
+                Some(SysctlNamespace::Net) if !cfg.network_isolated() => {
+                    return invalid(format!("sysctl {} requires isolateNetwork", key));
+                }
+                // IPC sysctls are only writable by the root of the IPC namespace's
+                // user namespace; net sysctls merely need CAP_NET_ADMIN in it.
+                Some(SysctlNamespace::Ipc) if cfg.user.uid != 0 => {
+                    return invalid(format!("sysctl {} requires user.uid 0", key));
+                }
+                Some(_) => (),
+                None => {
+                    return invalid(format!(
+                        "sysctl {} is not namespaced and cannot be set",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_host() -> Vec<HostCheck> {
+        check::check_host()
+    }
+
+    fn spawn(sandbox: &Sandbox) -> Result<Handle, Error> {
+        spawn_sandbox(sandbox)
+    }
+
+    fn process_fd(pid: Pid) -> std::io::Result<File> {
+        pidfd_open(pid)
+    }
+
+    fn send_signal(_pid: Pid, process: Option<&File>, signal: libc::c_int) -> std::io::Result<()> {
+        let pidfd = process.expect("pidfds are always available on Linux");
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                pidfd.as_raw_fd(),
+                signal,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn pidfd_open(pid: Pid) -> std::io::Result<File> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+// Returns pairs of (path inside the sandbox, path on the host) for the bind mounts
+// that the build is expected to write to.
+fn writable_mounts(cfg: &Config) -> Vec<(PathBuf, PathBuf)> {
+    let mut mounts: Vec<_> = cfg
+        .bind_mounts
+        .iter()
+        .filter(|bm| bm.verify_writable)
+        .map(|bm| (bm.destination.clone(), bm.source.clone()))
+        .collect();
+    mounts.extend(xbstrap::writable_mounts(cfg));
+    if let Some(cc) = &cfg.ccache {
+        mounts.push((PathBuf::from(ccache::SANDBOX_DIR), cc.dir.clone()));
+    }
+    mounts
+}
+
+// Checks that the sandbox user can create files in the writable mounts. Otherwise, permission
+// problems would only surface as EACCES errors somewhere in the build's output.
+// init runs with the IDs of the sandbox user, but unlike the process, it still has capabilities.
+// access() ignores these (unless the user is root), hence it reflects the process' permissions.
+fn check_writable(cfg: &Config) -> Result<(), Error> {
+    for (destination, source) in writable_mounts(cfg) {
+        let path = concat_absolute(&cfg.rootfs, &destination);
+        let mode = if path.is_dir() {
+            nix::unistd::AccessFlags::W_OK | nix::unistd::AccessFlags::X_OK
+        } else {
+            nix::unistd::AccessFlags::W_OK
+        };
+        if let Err(e) = nix::unistd::access(&path, mode) {
+            return Err(Error::MountNotWritable {
+                destination,
+                source,
+                uid: cfg.user.uid,
+                reason: e.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Returns pairs of (path inside the sandbox, path on the host) for all bind mounts.
+fn host_backed_mounts(cfg: &Config) -> Vec<(PathBuf, PathBuf)> {
+    let mut mounts: Vec<_> = cfg
+        .bind_mounts
+        .iter()
+        .map(|bm| (bm.destination.clone(), bm.source.clone()))
+        .collect();
+    mounts.extend(xbstrap::mounts(cfg));
+    if !cfg.network_isolated() {
+        mounts.push((
+            PathBuf::from("/etc/resolv.conf"),
+            PathBuf::from("/etc/resolv.conf"),
+        ));
+    }
+    if let Some(cc) = &cfg.ccache {
+        mounts.push((PathBuf::from(ccache::SANDBOX_DIR), cc.dir.clone()));
+    }
+    if let Some(dir) = cfg.distcc.as_ref().and_then(|dc| dc.config_dir.as_ref()) {
+        mounts.push((PathBuf::from(distcc::SANDBOX_DIR), dir.clone()));
+    }
+    mounts
+}
+
+// Namespaces that a sysctl can be scoped to.
+#[derive(PartialEq)]
+enum SysctlNamespace {
+    Ipc,
+    Net,
+}
+
+// Determines which namespace a sysctl belongs to.
+// Returns None for sysctls that are not namespaced (and hence cannot be set in the sandbox).
+fn sysctl_namespace(key: &str) -> Option<SysctlNamespace> {
+    if key.starts_with("net.") {
+        return Some(SysctlNamespace::Net);
+    }
+    if key.starts_with("fs.mqueue.")
+        || key.starts_with("kernel.msg")
+        || key.starts_with("kernel.shm")
+        || key == "kernel.sem"
+    {
+        return Some(SysctlNamespace::Ipc);
+    }
+    None
+}
+
+// Number of attempts before retry_on_eagain() gives up.
+const EAGAIN_ATTEMPTS: u32 = 8;
+
+// unshare() and fork() fail with EAGAIN when the user namespace or PID limits are exhausted.
+// Under heavy parallel load, this is usually transient, hence retry with exponential backoff.
+// Returns Error::ResourceLimit if the limit persists and Error::Setup for other errors.
+fn retry_on_eagain<T, F: FnMut() -> nix::Result<T>>(what: &str, mut f: F) -> Result<T, Error> {
+    let mut delay = std::time::Duration::from_millis(10);
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(result) => return Ok(result),
+            Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) if attempt < EAGAIN_ATTEMPTS => {
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
+                return Err(Error::ResourceLimit {
+                    what: what.to_string(),
+                    attempts: attempt,
+                });
+            }
+            Err(e) => return Err(Error::Setup(format!("failed to {}: {}", what, e))),
+        }
+    }
+}
+
+// Inspects the filesystem that the rootfs resides on.
+// Returns the mount flags that need to be carried over when remounting the rootfs:
+// inside the user namespace, the kernel locks these flags and refuses to change them.
+fn check_rootfs_filesystem(rootfs: &Path) -> Result<nix::mount::MsFlags, Error> {
+    let rootfs_error = |reason: String| Error::Rootfs {
+        rootfs: rootfs.to_path_buf(),
+        reason,
+    };
+    let fs = nix::sys::statfs::statfs(rootfs)
+        .map_err(|e| rootfs_error(format!("cannot be inspected: {}", e)))?;
+    let fs_type = fs.filesystem_type();
+    if fs_type == nix::sys::statfs::NFS_SUPER_MAGIC
+        || fs_type == nix::sys::statfs::SMB_SUPER_MAGIC
+        || fs_type.0 as u64 == CIFS_MAGIC
+    {
+        log!(
+            "warning: rootfs {} resides on a network filesystem, \
+            which is known to cause permission errors with user namespaces",
+            rootfs.display()
+        );
+    }
+
+    let vfs = nix::sys::statvfs::statvfs(rootfs)
+        .map_err(|e| rootfs_error(format!("cannot be inspected: {}", e)))?;
+    if vfs.flags().contains(nix::sys::statvfs::FsFlags::ST_NOEXEC) {
+        return Err(rootfs_error(
+            "resides on a noexec mount; programs cannot be executed from it".to_string(),
+        ));
+    }
+
+    locked_mount_flags(rootfs)
+}
+
+// Signals that init forwards to the child.
+const FORWARDED_SIGNALS: &[nix::sys::signal::Signal] = &[
+    nix::sys::signal::Signal::SIGHUP,
+    nix::sys::signal::Signal::SIGINT,
+    nix::sys::signal::Signal::SIGTERM,
+    nix::sys::signal::Signal::SIGUSR1,
+    nix::sys::signal::Signal::SIGUSR2,
+];
+
+// PID of the child (inside the sandbox's PID namespace) that init forwards signals to.
+static FORWARD_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    // Signals that the kernel generates on behalf of a terminal also reach the child
+    // (since it is in the same process group); do not deliver them twice.
+    if unsafe { (*info).si_code } == libc::SI_KERNEL {
+        return;
+    }
+    unsafe {
+        libc::kill(FORWARD_PID.load(Ordering::Relaxed), signal);
+    }
+}
+
+// Since init is PID 1 of its namespace, signals from outside of the namespace
+// (e.g., from Handle::signal()) are discarded unless init handles them.
+fn forward_signals(child: nix::unistd::Pid, signals: &[nix::sys::signal::Signal]) {
+    FORWARD_PID.store(child.as_raw(), Ordering::Relaxed);
+    let action = nix::sys::signal::SigAction::new(
+        nix::sys::signal::SigHandler::SigAction(forward_signal),
+        nix::sys::signal::SaFlags::SA_RESTART | nix::sys::signal::SaFlags::SA_SIGINFO,
+        nix::sys::signal::SigSet::empty(),
+    );
+    for signal in signals {
+        unsafe { nix::sys::signal::sigaction(*signal, &action) }
+            .expect("failed to install signal handler");
+    }
+}
+
+// On SIGQUIT or once the timeout expires (SIGALRM), init logs the remaining processes
+// and kills them. This happens on a separate thread since init may be blocked in wait().
+fn watch_for_hangs(cfg: &Config) {
+    let mut signals = nix::sys::signal::SigSet::empty();
+    signals.add(nix::sys::signal::Signal::SIGQUIT);
+    signals.add(nix::sys::signal::Signal::SIGALRM);
+    signals
+        .thread_block()
+        .expect("failed to block SIGQUIT and SIGALRM");
+    let proc_dir = concat_absolute(&cfg.rootfs, "/proc");
+    let timeout = cfg.timeout;
+    std::thread::spawn(move || {
+        match signals.wait().expect("failed to wait for signals") {
+            nix::sys::signal::Signal::SIGALRM => {
+                log!("timeout of {} seconds expired", timeout.unwrap())
+            }
+            signal => log!("received {}", signal),
+        }
+        log!("remaining processes (PID, state, wait channel and command line):");
+        ptree::dump(&proc_dir);
+        // This kills all processes of the PID namespace except for init itself.
+        let _ = nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(-1),
+            nix::sys::signal::Signal::SIGKILL,
+        );
+    });
+    if let Some(seconds) = cfg.timeout {
+        nix::unistd::alarm::set(seconds);
+    }
+}
+
+// Sets up the mount namespace and runs the process. This function only returns in
+// processes that are forked from the supervisor; the result is their exit code.
+fn run_init(
+    sandbox: &Sandbox,
+    rootfs_flags: nix::mount::MsFlags,
+    cgroup: Option<&cgroup::Cgroup>,
+    events: RawFd,
+    cache_channel: Option<RawFd>,
+) -> Result<i32, Error> {
+    let cfg = &sandbox.cfg;
+    // We can now set up the remaining namespaces and perform mounts.
+    let mut clone_flags = nix::sched::CloneFlags::CLONE_NEWNS;
+    if cfg.network_isolated() {
+        clone_flags |= nix::sched::CloneFlags::CLONE_NEWNET;
+    }
+    // IPC sysctls would otherwise modify the host's IPC namespace.
+    if cfg
+        .sysctls
+        .keys()
+        .any(|k| sysctl_namespace(k) == Some(SysctlNamespace::Ipc))
+    {
+        clone_flags |= nix::sched::CloneFlags::CLONE_NEWIPC;
+    }
+    if cfg.hostname().is_some() {
+        clone_flags |= nix::sched::CloneFlags::CLONE_NEWUTS;
+    }
+    // The supervisor has already moved init into the sandbox's cgroup, which becomes the root.
+    if cfg.resources.as_ref().is_some_and(|r| r.mount_cgroup) {
+        clone_flags |= nix::sched::CloneFlags::CLONE_NEWCGROUP;
+    }
+    retry_on_eagain("unshare()", || nix::sched::unshare(clone_flags))?;
+
+    if let Some(hostname) = cfg.hostname() {
+        nix::unistd::sethostname(hostname).map_err(setup_error("set hostname"))?;
+    }
+    if let (Some(dc), Some(channel)) = (&cfg.download_cache, cache_channel) {
+        downloadcache::listen(dc, channel)?;
+    }
+
+    // First, we need to get a read-only rootfs (unless the config asks for a writable one).
+    // Mounting with MS_BIND ignored MS_RDONLY, but MS_REMOUNT respects it.
+
+    nix::mount::mount(
+        Some(&cfg.rootfs),
+        &cfg.rootfs,
+        None::<&str>,
+        nix::mount::MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .map_err(setup_error("bind mount rootfs to itself"))?;
+
+    if !cfg.rootfs_writable {
+        // The fs might be mounted as nosuid/nodev and we will not have permissions
+        // to strip these mount options.
+        // Instead of parsing the current mount table, just set these flags unconditionally for now.
+        // Other locked flags (e.g., atime flags) are determined by check_rootfs_filesystem().
+        nix::mount::mount(
+            Some(&cfg.rootfs),
+            &cfg.rootfs,
+            None::<&str>,
+            nix::mount::MsFlags::MS_REMOUNT
+                | nix::mount::MsFlags::MS_BIND
+                | nix::mount::MsFlags::MS_RDONLY
+                | nix::mount::MsFlags::MS_NOSUID
+                | nix::mount::MsFlags::MS_NODEV
+                | rootfs_flags,
+            None::<&str>,
+        )
+        .map_err(setup_error("make rootfs read-only"))?;
+    }
+
+    // Perform mounts of /dev, /dev/pts, /dev/shm, /run, /tmp and /proc.
+
+    let dev_overlays = vec!["tty", "null", "zero", "full", "random", "urandom"];
+    for f in dev_overlays {
+        nix::mount::mount(
+            Some(&Path::new("/dev/").join(f)),
+            &concat_absolute(&cfg.rootfs, "/dev/").join(f),
+            None::<&str>,
+            nix::mount::MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(setup_error(format!("mount /dev/{}", f)))?;
+    }
+
+    if !cfg.network_isolated() {
+        nix::mount::mount(
+            Some(
+                &std::fs::canonicalize("/etc/resolv.conf")
+                    .map_err(setup_error("resolve /etc/resolv.conf"))?,
+            ),
+            &concat_absolute(&cfg.rootfs, "/etc/resolv.conf"),
+            None::<&str>,
+            nix::mount::MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(setup_error("mount /etc/resolv.conf"))?;
+    }
+
+    nix::mount::mount(
+        None::<&str>,
+        &concat_absolute(&cfg.rootfs, "/dev/pts"),
+        Some("devpts"),
+        nix::mount::MsFlags::empty(),
+        // The console is allocated through this instance's ptmx.
+        cfg.console.then_some("ptmxmode=0666"),
+    )
+    .map_err(setup_error("mount /dev/pts"))?;
+    let console = if cfg.console {
+        Some(console::create(&cfg.rootfs)?)
+    } else {
+        None
+    };
+
+    nix::mount::mount(
+        None::<&str>,
+        &concat_absolute(&cfg.rootfs, "/dev/shm"),
+        Some("tmpfs"),
+        nix::mount::MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(setup_error("mount /dev/shm"))?;
+
+    nix::mount::mount(
+        None::<&str>,
+        &concat_absolute(&cfg.rootfs, "/run"),
+        Some("tmpfs"),
+        nix::mount::MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(setup_error("mount /run"))?;
+
+    nix::mount::mount(
+        None::<&str>,
+        &concat_absolute(&cfg.rootfs, "/tmp"),
+        Some("tmpfs"),
+        nix::mount::MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(setup_error("mount /tmp"))?;
+
+    nix::mount::mount(
+        None::<&str>,
+        &concat_absolute(&cfg.rootfs, "/proc"),
+        Some("proc"),
+        nix::mount::MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(setup_error("mount /proc"))?;
+
+    // Apply sysctls. This needs to happen after mounting /proc since the sysctls
+    // of the new namespaces are only visible through the new procfs instance.
+    for (key, value) in &cfg.sysctls {
+        let path = concat_absolute(&cfg.rootfs, "/proc/sys").join(key.replace('.', "/"));
+        std::fs::write(&path, value).map_err(setup_error(format!("set sysctl {}", key)))?;
+    }
+
+    if cfg.reproducible {
+        reproducible::mask_machine_id(&cfg.rootfs)?;
+    }
+
+    if let Some(cc) = &cfg.ccache {
+        ccache::mount(&cfg.rootfs, &cc.dir)?;
+    }
+    if let Some(socket) = cfg
+        .sccache
+        .as_ref()
+        .and_then(|sc| sc.server_socket.as_ref())
+    {
+        sccache::mount(&cfg.rootfs, socket)?;
+    }
+    if let Some(dir) = cfg.distcc.as_ref().and_then(|dc| dc.config_dir.as_ref()) {
+        distcc::mount(&cfg.rootfs, dir)?;
+    }
+
+    xbstrap::mount(cfg)?;
+
+    if let Some(home) = cfg.home.as_ref().filter(|h| h.create) {
+        home::create(&cfg.rootfs, &home.path)?;
+    }
+    if cfg.xdg_dirs {
+        xdg::create_dirs(&cfg.rootfs, cfg.user.uid)?;
+    }
+    if let Some(bus) = &cfg.dbus {
+        dbus::mount(&cfg.rootfs, bus)?;
+    }
+    if let Some(l) = &cfg.locale {
+        locale::setup(&cfg.rootfs, l)?;
+    }
+    if !cfg.secrets.is_empty() {
+        secrets::mount(&cfg.rootfs, &cfg.secrets)?;
+    }
+    if let Some(script) = &cfg.process.script {
+        script::write(&cfg.rootfs, script)?;
+    }
+    preload::mount(&cfg.rootfs, &cfg.preload)?;
+    let displays = if cfg.gui {
+        let displays = gui::Displays::from_environment();
+        displays.mount(&cfg.rootfs, cfg.user.uid)?;
+        Some(displays)
+    } else {
+        None
+    };
+
+    if let Some(qemu) = &cfg.qemu_user {
+        binfmt::mount_interpreter(&cfg.rootfs, qemu)?;
+    }
+
+    let strace_binary = match &cfg.trace_syscalls {
+        Some(t) => Some(strace::setup(&cfg.rootfs, t)?),
+        None => None,
+    };
+    let perf_binary = match &cfg.perf {
+        Some(p) => Some(perf::setup(&cfg.rootfs, p)?),
+        None => None,
+    };
+    // This hides the host's cgroups that perf::setup() may expose.
+    if let Some(cg) = cgroup.filter(|_| cfg.resources.as_ref().is_some_and(|r| r.mount_cgroup)) {
+        cg.mount(&cfg.rootfs)?;
+    }
+    if let Some(gdbserver) = cfg.debug.as_ref().and_then(|d| d.gdbserver.as_ref()) {
+        debug::setup_gdbserver(&cfg.rootfs, gdbserver)?;
+    }
+
+    // Perform bind mounts requested by user.
+    for bm in &cfg.bind_mounts {
+        nix::mount::mount(
+            Some(&bm.source),
+            &concat_absolute(&cfg.rootfs, &bm.destination),
+            None::<&str>,
+            nix::mount::MsFlags::MS_BIND | nix::mount::MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .map_err(setup_error(format!(
+            "bind mount {} to {}",
+            bm.source.display(),
+            bm.destination.display()
+        )))?;
+    }
+
+    if let Some(hp) = &cfg.hugepages {
+        let dir = hugetlb::run_dir(&hp.source, runid::current().unwrap());
+        bind_into_sandbox(&cfg.rootfs, &dir, &hp.destination, false)?;
+    }
+
+    // Copy staged trees into the sandbox. In contrast to bind mounts,
+    // modifications by the build do not propagate back to the host.
+    // If there is a work directory, the copies are stored there (where they can share
+    // data blocks with the source) and bind mounted into the sandbox.
+    let run_dir = cfg.run_dir(runid::current().unwrap());
+    for (i, staging) in cfg.staging.iter().enumerate() {
+        let copy = match &run_dir {
+            Some(dir) => dir.join("staging").join(i.to_string()),
+            None => concat_absolute(&cfg.rootfs, &staging.destination),
+        };
+        if let Some(parent) = copy.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(setup_error(format!("create {}", parent.display())))?;
+        }
+        copy::copy_tree(&staging.source, &copy, staging.reflink).map_err(setup_error(format!(
+            "stage {} to {}",
+            staging.source.display(),
+            staging.destination.display()
+        )))?;
+        if run_dir.is_some() {
+            bind_into_sandbox(&cfg.rootfs, &copy, &staging.destination, false)?;
+        }
+    }
+
+    if let Some(ld) = &cfg.ld_cache {
+        ldcache::refresh(&cfg.rootfs, ld)?;
+    }
+
+    check_writable(cfg)?;
+
+    // TODO: We could drop privileges here.
+    //       (However, cbuildrt does not really protect against malicious sandbox escapes.)
+
+    let ccache_stats = match &cfg.ccache {
+        Some(cc) if cc.stats => {
+            let stats = ccache::Stats::collect(&cfg.rootfs);
+            if stats.is_none() {
+                log!("warning: unable to obtain ccache statistics");
+            }
+            stats
+        }
+        _ => None,
+    };
+
+    // With a custom init, the child becomes PID 1 of a nested PID namespace.
+    // Our own PID namespace is kept open to restore it for later fork()s of init.
+    let own_pid_ns = match &cfg.init {
+        Some(_) => {
+            let ns = std::fs::File::open("/proc/self/ns/pid")
+                .map_err(setup_error("open PID namespace"))?;
+            nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWPID)
+                .map_err(setup_error("unshare PID namespace"))?;
+            Some(ns)
+        }
+        None => None,
+    };
+
+    // fork() and execve() in the child.
+    // The parent waits for the child to terminate.
+    // (We cannot use Rust's high-level API since we need to reap orphans.)
+    let fork_result = retry_on_eagain("fork() from init", || unsafe { nix::unistd::fork() })?;
+    if let (Some(ns), nix::unistd::ForkResult::Parent { .. }) = (&own_pid_ns, &fork_result) {
+        nix::sched::setns(ns.as_raw_fd(), nix::sched::CloneFlags::CLONE_NEWPID)
+            .map_err(setup_error("restore PID namespace"))?;
+    }
+    match fork_result {
+        nix::unistd::ForkResult::Child => {
+            let start_gate = sandbox
+                .start_fifo
+                .as_deref()
+                .map(StartGate::new)
+                .transpose()?;
+
+            if own_pid_ns.is_some() {
+                // /proc needs to show the nested PID namespace. Use a private mount namespace
+                // such that init keeps its view.
+                nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNS)
+                    .map_err(setup_error("unshare mount namespace"))?;
+                nix::mount::mount(
+                    None::<&str>,
+                    &concat_absolute(&cfg.rootfs, "/proc"),
+                    Some("proc"),
+                    nix::mount::MsFlags::empty(),
+                    None::<&str>,
+                )
+                .map_err(setup_error("mount /proc"))?;
+            }
+
+            // chroot() and change the current directory to /.
+            enter_rootfs(&cfg.rootfs).map_err(setup_error("enter rootfs"))?;
+            if let Some(cwd) = &cfg.process.cwd {
+                std::env::set_current_dir(cwd).map_err(setup_error(format!(
+                    "change directory to {}",
+                    cwd.display()
+                )))?;
+            }
+            if cfg.console {
+                console::attach();
+            }
+
+            // The host's environment needs to be read before it is cleared.
+            let proxy_env = match cfg.proxy {
+                Some(Proxy::Host) => proxy::host_environment(),
+                _ => Vec::new(),
+            };
+
+            // (SOURCE_DATE_EPOCH is read from the caller's environment.)
+            let reproducible_env = cfg
+                .reproducible
+                .then(|| reproducible::environment(cfg.source_date_epoch));
+            if cfg.reproducible || cfg.process.clear_environ {
+                for (key, _) in std::env::vars_os() {
+                    std::env::remove_var(key);
+                }
+            }
+            if let Some(reproducible_env) = reproducible_env {
+                for (key, value) in reproducible_env {
+                    std::env::set_var(key, value);
+                }
+                nix::sys::stat::umask(Mode::from_bits_truncate(reproducible::UMASK));
+            }
+
+            std::env::set_var("CBUILDRT_RUN_ID", runid::current().unwrap());
+
+            std::env::set_var("PATH", sandbox::default_path(cfg));
+
+            if let Some(home) = &cfg.home {
+                std::env::set_var("HOME", &home.path);
+            }
+            if cfg.xdg_dirs {
+                for (key, value) in xdg::environment(cfg.user.uid) {
+                    std::env::set_var(key, value);
+                }
+            }
+            if let Some(l) = &cfg.locale {
+                locale::clear_environment();
+                for (key, value) in locale::environment(l) {
+                    std::env::set_var(key, value);
+                }
+            }
+            for (key, value) in secrets::environment(&cfg.secrets) {
+                std::env::set_var(key, value);
+            }
+            if cfg.dbus.is_some() {
+                for (key, value) in dbus::environment() {
+                    std::env::set_var(key, value);
+                }
+            }
+            if let Some(displays) = &displays {
+                for (key, value) in displays.environment(cfg.user.uid) {
+                    std::env::set_var(key, value);
+                }
+            }
+
+            if let Some(Proxy::None) = cfg.proxy {
+                proxy::clear_environment();
+            }
+            for (key, value) in proxy_env {
+                std::env::set_var(key, value);
+            }
+            if let Some(dc) = &cfg.download_cache {
+                proxy::clear_environment();
+                for (key, value) in downloadcache::environment(dc) {
+                    std::env::set_var(key, value);
+                }
+            }
+
+            if cfg.ccache.is_some() {
+                std::env::set_var("CCACHE_DIR", ccache::SANDBOX_DIR);
+            }
+            if let Some(sc) = &cfg.sccache {
+                for (key, value) in sccache::environment(sc) {
+                    std::env::set_var(key, value);
+                }
+            }
+            if let Some(dc) = &cfg.distcc {
+                for (key, value) in distcc::environment(dc) {
+                    std::env::set_var(key, value);
+                }
+            }
+            for (key, value) in xbstrap::environment(cfg) {
+                std::env::set_var(key, value);
+            }
+            if !cfg.preload.is_empty() {
+                std::env::set_var("LD_PRELOAD", preload::environment(&cfg.preload));
+            }
+            for (key, value) in &cfg.process.environ {
+                std::env::set_var(key, value);
+            }
+
+            if cfg.disable_aslr {
+                // The personality is inherited across execve() and fork().
+                let persona = unsafe { libc::personality(0xffff_ffff) };
+                let result = unsafe {
+                    libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong)
+                };
+                if persona < 0 || result < 0 {
+                    return Err(setup_error("disable ASLR")(std::io::Error::last_os_error()));
+                }
+            }
+
+            if !cfg.ambient_capabilities.is_empty() {
+                caps::raise_ambient(&cfg.ambient_capabilities);
+            }
+
+            if cfg.access_manifest.is_some() {
+                // Let init attach before we execute anything.
+                nix::sys::ptrace::traceme().map_err(setup_error("enable tracing"))?;
+                nix::sys::signal::raise(nix::sys::signal::Signal::SIGSTOP)
+                    .map_err(setup_error("stop for tracing"))?;
+            }
+
+            let mut args = match &cfg.process.script {
+                Some(_) => script::args(),
+                None => cfg.process.args.clone(),
+            };
+            if let Some(binary) = perf_binary {
+                args = perf::wrap(binary, cfg.perf.as_ref().unwrap(), &args);
+            }
+            if let Some(binary) = strace_binary {
+                args = strace::wrap(binary, cfg.trace_syscalls.as_ref().unwrap(), &args);
+            }
+            if let Some(d) = cfg.debug.as_ref().filter(|d| d.gdbserver.is_some()) {
+                args = debug::wrap(d, &args);
+            }
+            if let Some(init) = &cfg.init {
+                args = init.iter().cloned().chain(args).collect();
+            }
+
+            send_event(events, &Event::Ready);
+            if let Some(gate) = start_gate {
+                gate.wait()?;
+            }
+
+            let exec_result = nix::unistd::execvp(
+                &CString::new(args[0].as_str()).unwrap(),
+                &args
+                    .iter()
+                    .map(|a| CString::new(a.as_str()).unwrap())
+                    .collect::<Vec<_>>(),
+            );
+            Err(Error::Exec {
+                program: args[0].clone(),
+                reason: exec_result.unwrap_err().to_string(),
+            })
+        }
+        nix::unistd::ForkResult::Parent { child: child_pid } => {
+            let relay = console.map(console::Console::relay);
+            forward_signals(child_pid, FORWARDED_SIGNALS);
+            watch_for_hangs(cfg);
+            let mut code = if let Some(manifest) = &cfg.access_manifest {
+                let mut tracer = trace::Tracer::new(&cfg.rootfs, host_backed_mounts(cfg));
+                let code = tracer.run(child_pid);
+                if let Err(e) = tracer.write_manifest(manifest) {
+                    log!("failed to write {}: {}", manifest.display(), e);
+                }
+                code
+            } else {
+                loop {
+                    // Now, let's wait for the child to terminate.
+                    let child_status = nix::sys::wait::wait().expect("failed to wait for children");
+                    match child_status {
+                        nix::sys::wait::WaitStatus::Exited(pid, code) if pid == child_pid => {
+                            break code
+                        }
+                        // E.g., if the child was killed by the OOM killer.
+                        nix::sys::wait::WaitStatus::Signaled(pid, signal, _)
+                            if pid == child_pid =>
+                        {
+                            log!("child was killed by {}", signal);
+                            break 128 + signal as i32;
+                        }
+                        _ => (),
+                    }
+                }
+            };
+            nix::unistd::alarm::cancel();
+            if let Some(relay) = relay {
+                relay.finish();
+            }
+            if code != 0 {
+                log!("child returned non-zero exit code");
+            }
+
+            if let Some(kills) = cgroup
+                .and_then(cgroup::Cgroup::oom_kills)
+                .filter(|n| *n > 0)
+            {
+                log!(
+                    "the sandbox exceeded its memory limit ({} processes were killed)",
+                    kills
+                );
+            }
+
+            if let Some(t) = cfg
+                .trace_syscalls
+                .as_ref()
+                .filter(|_| !cfg.secrets.is_empty())
+            {
+                if let Err(e) = secrets::Scrubber::new(&cfg.secrets).scrub_file(&t.output) {
+                    log!("failed to scrub {}: {}", t.output.display(), e);
+                }
+            }
+
+            if let Some(before) = ccache_stats {
+                if let Some(after) = ccache::Stats::collect(&cfg.rootfs) {
+                    after.report_since(&before);
+                }
+            }
+
+            // The writable parts of the sandbox are still mounted at this point.
+            send_event(
+                events,
+                &Event::DiskUsage(usage::measure(cfg, run_dir.as_deref())),
+            );
+            if !copy_artifacts(cfg) && code == 0 {
+                code = 1;
+            }
+            if let Some(p) = &cfg.perf {
+                if !perf::collect(&cfg.rootfs, p) && code == 0 {
+                    code = 1;
+                }
+            }
+            if let Some(dir) = run_dir.as_ref().filter(|_| cfg.keep_work_dir) {
+                log!("keeping work directory {}", dir.display());
+            }
+            Ok(code)
+        }
+    }
+}
+
+fn spawn_sandbox(sandbox: &Sandbox) -> Result<Handle, Error> {
+    let cfg = &sandbox.cfg;
+    // Resources registered here are released when the handle is dropped
+    // (or when this function fails).
+    let run_id = cfg.run_id.clone().unwrap_or_else(runid::generate);
+
+    let rootfs_flags = check_rootfs_filesystem(&cfg.rootfs)?;
+    if cfg.resources.as_ref().is_some_and(|r| r.mount_cgroup)
+        && !concat_absolute(&cfg.rootfs, "/sys").is_dir()
+    {
+        return Err(Error::Rootfs {
+            rootfs: cfg.rootfs.clone(),
+            reason: "lacks /sys, which is needed to mount the cgroup".to_string(),
+        });
+    }
+    if cfg.console && !concat_absolute(&cfg.rootfs, console::SANDBOX_PATH).exists() {
+        return Err(Error::Rootfs {
+            rootfs: cfg.rootfs.clone(),
+            reason: "lacks /dev/console, which is needed as the mount point of the console"
+                .to_string(),
+        });
+    }
+
+    if cfg.access_manifest.is_some() && !trace::SUPPORTED {
+        return Err(Error::Unsupported(
+            "file-access tracing is not supported on this architecture".to_string(),
+        ));
+    }
+
+    if cfg.perf.is_some() {
+        perf::check_host_policy();
+    }
+
+    binfmt::prepare(&cfg.rootfs, cfg.qemu_user.as_deref(), cfg.rootfs_writable)
+        .map_err(Error::Unsupported)?;
+
+    let mut teardown = sandbox::prepare(sandbox, &run_id)?;
+
+    let cgroup = match &cfg.resources {
+        Some(resources) => cgroup::Cgroup::create(resources, &run_id)?,
+        None => None,
+    };
+    if let Some(cg) = cgroup.clone() {
+        let paths: Vec<String> = cg.paths().iter().map(|p| p.display().to_string()).collect();
+        teardown.defer(format!("cgroup {}", paths.join(", ")), move || cg.remove());
+    }
+    if let Some(hp) = &cfg.hugepages {
+        let dir = hugetlb::create(&hp.source, &run_id)?;
+        teardown.defer(
+            format!("huge page directory {}", dir.display()),
+            move || std::fs::remove_dir_all(&dir),
+        );
+    }
+    // The proxy runs in this process; init sends it a socket from the sandbox's namespace.
+    let cache_channel = match &cfg.download_cache {
+        Some(dc) => {
+            let (server, channel) = downloadcache::Server::start(dc)?;
+            teardown.defer("download cache proxy", move || {
+                drop(server);
+                Ok(())
+            });
+            Some(channel)
+        }
+        None => None,
+    };
+
+    // The supervisor, init and the child report events through this pipe.
+    // The child's end is closed by execve() (or when all of these processes exit).
+    let (events_read, events_write) =
+        nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(setup_error("create events pipe"))?;
+    let events = unsafe { std::fs::File::from_raw_fd(events_read) };
+
+    let supervisor_pid =
+        match retry_on_eagain("fork() from cbuildrt", || unsafe { nix::unistd::fork() })? {
+            nix::unistd::ForkResult::Child => {
+                drop(events);
+                runid::set_current(&run_id);
+                supervise(
+                    sandbox,
+                    rootfs_flags,
+                    cgroup.as_ref(),
+                    events_write,
+                    cache_channel,
+                )
+            }
+            nix::unistd::ForkResult::Parent { child } => child,
+        };
+    nix::unistd::close(events_write).map_err(setup_error("close events pipe"))?;
+    if let Some(channel) = cache_channel {
+        nix::unistd::close(channel).map_err(setup_error("close download cache channel"))?;
+    }
+
+    let pidfd = pidfd_open(supervisor_pid)
+        .map_err(|e| Error::Unsupported(format!("pidfd_open() failed: {}", e)))?;
+    Handle::new(supervisor_pid, pidfd, run_id, events, teardown)
+}
+
+// Entry point of the supervisor process, which enters the namespaces and forks init.
+// Never returns into the caller of Sandbox::spawn(); instead, errors and panics of the
+// supervisor (and of the processes that it forks) are reported as events.
+fn supervise(
+    sandbox: &Sandbox,
+    rootfs_flags: nix::mount::MsFlags,
+    cgroup: Option<&cgroup::Cgroup>,
+    events: RawFd,
+    cache_channel: Option<RawFd>,
+) -> ! {
+    // Panic messages are reported as events instead.
+    std::panic::set_hook(Box::new(|_| {}));
+    // Read the secrets before the process leaves the host's file system.
+    let scrubber = secrets::Scrubber::new(&sandbox.cfg.secrets);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_supervisor(sandbox, rootfs_flags, cgroup, events, cache_channel)
+    }))
+    .unwrap_or_else(|payload| {
+        let msg = match payload.downcast::<String>() {
+            Ok(msg) => *msg,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(msg) => msg.to_string(),
+                Err(_) => "unknown error".to_string(),
+            },
+        };
+        Err(Error::Setup(msg))
+    });
+    let code = match result {
+        Ok(code) => code,
+        Err(e) => {
+            send_event(events, &Event::Failed(scrubber.scrub_error(e)));
+            1
+        }
+    };
+    // Do not run the caller's atexit() handlers in forked processes.
+    unsafe { libc::_exit(code) }
+}
+
+fn run_supervisor(
+    sandbox: &Sandbox,
+    rootfs_flags: nix::mount::MsFlags,
+    cgroup: Option<&cgroup::Cgroup>,
+    events: RawFd,
+    cache_channel: Option<RawFd>,
+) -> Result<i32, Error> {
+    let cfg = &sandbox.cfg;
+    // All processes of the sandbox inherit the cgroup and the NUMA binding.
+    if let Some(cgroup) = cgroup {
+        cgroup.join()?;
+    }
+    if let Some(node) = cfg.resources.as_ref().and_then(|r| r.numa_node) {
+        numa::bind(node)?;
+    }
+    if let Some(netns) = cfg.distcc.as_ref().and_then(|dc| dc.netns.as_ref()) {
+        distcc::join_netns(netns)?;
+    }
+
+    let euid = nix::unistd::geteuid();
+    let egid = nix::unistd::getegid();
+
+    // Enter the user namespace and let children enter a new PID namespace.
+    // We cannot do mounts in this process yet, as this the process itself
+    // is not moved to the new PID namespace.
+    retry_on_eagain("unshare()", || {
+        nix::sched::unshare(
+            nix::sched::CloneFlags::CLONE_NEWUSER | nix::sched::CloneFlags::CLONE_NEWPID,
+        )
+    })?;
+
+    // Write the uid_map and gid_map files. Linux demands that we write setgroups first
+    // (otherwise, we need to be root in the outer namespace).
+
+    std::fs::write("/proc/self/setgroups", "deny").map_err(setup_error("write setgroups file"))?;
+
+    std::fs::write("/proc/self/uid_map", format!("{} {} 1", cfg.user.uid, euid))
+        .map_err(setup_error("write uid_map file"))?;
+    std::fs::write("/proc/self/gid_map", format!("{} {} 1", cfg.user.gid, egid))
+        .map_err(setup_error("write gid_map file"))?;
+
+    // Change user IDs.
+    nix::unistd::setuid(nix::unistd::Uid::from_raw(cfg.user.uid))
+        .map_err(setup_error("set UID"))?;
+    nix::unistd::setgid(nix::unistd::Gid::from_raw(cfg.user.gid))
+        .map_err(setup_error("set GID"))?;
+
+    // fork() and run init in the child.
+    // The parent waits for the child to terminate.
+    match retry_on_eagain("fork() from supervisor", || unsafe { nix::unistd::fork() })? {
+        nix::unistd::ForkResult::Child => {
+            run_init(sandbox, rootfs_flags, cgroup, events, cache_channel)
+        }
+        nix::unistd::ForkResult::Parent { child: init_pid } => {
+            // Only init uses the channel; the proxy notices if init fails before sending.
+            if let Some(channel) = cache_channel {
+                nix::unistd::close(channel).expect("failed to close download cache channel");
+            }
+            log!("PID init is {} (outside the namespace)", init_pid);
+            send_event(
+                events,
+                &Event::Started {
+                    init_pid: init_pid.as_raw(),
+                    run_id: runid::current().unwrap().to_string(),
+                },
+            );
+            if let Some(d) = cfg.debug.as_ref().filter(|d| d.gdbserver.is_some()) {
+                debug::print_attach_command(d, cfg.network_isolated(), init_pid);
+            }
+            // Lets init dump the processes (see watch_for_hangs()).
+            forward_signals(init_pid, &[nix::sys::signal::Signal::SIGQUIT]);
+
+            // Wait for init to terminate.
+            // If init is killed (e.g., by Handle::kill()), the sandbox terminates with 128 plus
+            // the signal number, like a shell reports it.
+            loop {
+                match nix::sys::wait::waitpid(init_pid, None).expect("failed to wait for init") {
+                    nix::sys::wait::WaitStatus::Exited(_, code) => return Ok(code),
+                    nix::sys::wait::WaitStatus::Signaled(_, signal, _) => {
+                        log!("init was killed by {}", signal);
+                        return Ok(128 + signal as i32);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sysctl_namespaces() {
+        assert!(sysctl_namespace("net.ipv4.ip_forward") == Some(SysctlNamespace::Net));
+        assert!(sysctl_namespace("kernel.shmmax") == Some(SysctlNamespace::Ipc));
+        assert!(sysctl_namespace("kernel.msgmnb") == Some(SysctlNamespace::Ipc));
+        assert!(sysctl_namespace("kernel.sem") == Some(SysctlNamespace::Ipc));
+        assert!(sysctl_namespace("fs.mqueue.msg_max") == Some(SysctlNamespace::Ipc));
+        assert!(sysctl_namespace("kernel.semx").is_none());
+        assert!(sysctl_namespace("kernel.randomize_va_space").is_none());
+        assert!(sysctl_namespace("vm.swappiness").is_none());
+    }
+}
@@ -0,0 +1,41 @@
+// Backends that implement sandboxes with the isolation primitives of an operating system.
+// All backends run the same Config format; settings that a backend cannot implement are
+// rejected by its check_config() (and hence by Sandbox::from_config()).
+
+use crate::check::HostCheck;
+use crate::handle::Handle;
+use crate::sandbox::Sandbox;
+use crate::{Config, Error};
+use nix::unistd::Pid;
+use std::fs::File;
+
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "linux")]
+mod linux;
+
+// The backend of the operating system that cbuildrt is built for.
+#[cfg(target_os = "freebsd")]
+pub(crate) use freebsd::FreeBsd as Native;
+#[cfg(target_os = "linux")]
+pub(crate) use linux::Linux as Native;
+
+pub(crate) trait Platform {
+    // Checks the platform-specific constraints of the configuration.
+    // This does not inspect the host.
+    fn check_config(cfg: &Config) -> Result<(), Error>;
+
+    // Checks which features of the backend the host supports.
+    fn check_host() -> Vec<HostCheck>;
+
+    // Sets up the sandbox and starts the process (see Sandbox::spawn()).
+    fn spawn(sandbox: &Sandbox) -> Result<Handle, Error>;
+
+    // Returns a file descriptor that refers to the process, such that it can be signaled
+    // without racing against PID reuse. Fails with ErrorKind::Unsupported if the platform
+    // cannot obtain such descriptors for existing processes.
+    fn process_fd(pid: Pid) -> std::io::Result<File>;
+
+    // Sends a signal to a process, through its descriptor (if any).
+    fn send_signal(pid: Pid, process: Option<&File>, signal: libc::c_int) -> std::io::Result<()>;
+}
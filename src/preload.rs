@@ -0,0 +1,35 @@
+use crate::Preload;
+use std::path::{Path, PathBuf};
+
+// Location of shared objects from the host inside the sandbox.
+const SANDBOX_DIR: &str = "/run/cbuildrt/preload";
+
+// Host objects are mounted into separate directories such that their names cannot clash.
+fn sandbox_path(index: usize, p: &Preload) -> PathBuf {
+    if p.host {
+        Path::new(SANDBOX_DIR)
+            .join(index.to_string())
+            .join(p.path.file_name().unwrap())
+    } else {
+        p.path.clone()
+    }
+}
+
+// Mounts the shared objects that are taken from the host.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, preload: &[Preload]) -> Result<(), crate::Error> {
+    for (index, p) in preload.iter().enumerate().filter(|(_, p)| p.host) {
+        crate::bind_into_sandbox(rootfs, &p.path, sandbox_path(index, p), true)?;
+    }
+    Ok(())
+}
+
+// Returns the value of LD_PRELOAD. It replaces the caller's value, which refers to host paths.
+pub fn environment(preload: &[Preload]) -> String {
+    preload
+        .iter()
+        .enumerate()
+        .map(|(index, p)| sandbox_path(index, p).to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,32 @@
+// Proxy variables that are understood by common tools (curl, wget, git, pip, ...).
+const PROXY_VARIABLES: &[&str] = &[
+    "http_proxy",
+    "https_proxy",
+    "ftp_proxy",
+    "all_proxy",
+    "no_proxy",
+];
+
+// Returns the host's proxy configuration.
+// Tools disagree on whether they read the lower or upper case variants,
+// hence both variants are returned (the lower case one takes precedence on the host).
+pub fn host_environment() -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for name in PROXY_VARIABLES {
+        let upper = name.to_uppercase();
+        let value = std::env::var(name).or_else(|_| std::env::var(&upper));
+        if let Ok(value) = value {
+            env.push((name.to_string(), value.clone()));
+            env.push((upper, value));
+        }
+    }
+    env
+}
+
+// Removes all proxy variables from the current environment.
+pub fn clear_environment() {
+    for name in PROXY_VARIABLES {
+        std::env::remove_var(name);
+        std::env::remove_var(name.to_uppercase());
+    }
+}
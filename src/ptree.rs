@@ -0,0 +1,89 @@
+// Dumps the processes of the sandbox (e.g., to diagnose hung builds).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+struct Process {
+    ppid: i32,
+    state: String,
+    wchan: String,
+    cmdline: String,
+}
+
+fn read_process(dir: &Path) -> Option<Process> {
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    // The command name may contain spaces and parentheses, hence split after the last ')'.
+    let (comm, rest) = stat.rsplit_once(')')?;
+    let comm = comm.split_once('(')?.1;
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.to_string();
+    let ppid = fields.next()?.parse().ok()?;
+
+    let wchan = std::fs::read_to_string(dir.join("wchan")).unwrap_or_default();
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    let cmdline = if cmdline.is_empty() {
+        // Kernel threads and zombies do not have a command line.
+        format!("[{}]", comm)
+    } else {
+        String::from_utf8_lossy(&cmdline)
+            .trim_end_matches('\0')
+            .replace('\0', " ")
+    };
+    Some(Process {
+        ppid,
+        state,
+        wchan: if wchan.is_empty() || wchan == "0" {
+            "-".to_string()
+        } else {
+            wchan
+        },
+        cmdline,
+    })
+}
+
+fn log_subtree(processes: &BTreeMap<i32, Process>, pid: i32, depth: usize) {
+    let p = &processes[&pid];
+    log!(
+        "{:indent$}{} {} {} {}",
+        "",
+        pid,
+        p.state,
+        p.wchan,
+        p.cmdline,
+        indent = 2 * depth
+    );
+    for (child, _) in processes.iter().filter(|(_, c)| c.ppid == pid) {
+        log_subtree(processes, *child, depth + 1);
+    }
+}
+
+// Logs the tree of processes (PID, state, wait channel and command line) of the PID namespace
+// that proc_dir belongs to.
+pub fn dump(proc_dir: &Path) {
+    let entries = match std::fs::read_dir(proc_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log!("failed to read {}: {}", proc_dir.display(), e);
+            return;
+        }
+    };
+    let mut processes = BTreeMap::new();
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Processes may terminate while we iterate.
+        if let Some(p) = read_process(&entry.path()) {
+            processes.insert(pid, p);
+        }
+    }
+    let roots: Vec<i32> = processes
+        .iter()
+        .filter(|(_, p)| !processes.contains_key(&p.ppid))
+        .map(|(pid, _)| *pid)
+        .collect();
+    for pid in roots {
+        log_subtree(&processes, pid, 0);
+    }
+}
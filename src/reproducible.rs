@@ -0,0 +1,40 @@
+// Hostname of reproducible sandboxes.
+pub const HOSTNAME: &str = "cbuildrt";
+
+// umask of processes in reproducible sandboxes.
+pub const UMASK: u32 = 0o022;
+
+// Returns the variables that make up the (otherwise empty) environment of reproducible sandboxes.
+pub fn environment(source_date_epoch: Option<u64>) -> Vec<(String, String)> {
+    // If no epoch is configured, honor the caller's SOURCE_DATE_EPOCH (if any).
+    let epoch = source_date_epoch
+        .map(|epoch| epoch.to_string())
+        .or_else(|| std::env::var("SOURCE_DATE_EPOCH").ok())
+        .unwrap_or_else(|| "0".to_string());
+    vec![
+        ("SOURCE_DATE_EPOCH".to_string(), epoch),
+        ("LC_ALL".to_string(), "C.UTF-8".to_string()),
+        ("TZ".to_string(), "UTC".to_string()),
+    ]
+}
+
+// Hides the rootfs' machine-id (if any) behind an empty file.
+// Must be called after /run has been mounted.
+#[cfg(target_os = "linux")]
+pub fn mask_machine_id(rootfs: &std::path::Path) -> Result<(), crate::Error> {
+    if !crate::concat_absolute(rootfs, "/etc/machine-id").exists() {
+        return Ok(());
+    }
+    let blank = crate::concat_absolute(rootfs, "/run/cbuildrt/machine-id");
+    std::fs::create_dir_all(blank.parent().unwrap())
+        .map_err(crate::setup_error("create /run/cbuildrt"))?;
+    std::fs::write(&blank, "").map_err(crate::setup_error("create blank machine-id"))?;
+    nix::mount::mount(
+        Some(&blank),
+        &crate::concat_absolute(rootfs, "/etc/machine-id"),
+        None::<&str>,
+        nix::mount::MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .map_err(crate::setup_error("mount blank machine-id"))
+}
@@ -0,0 +1,36 @@
+use std::sync::OnceLock;
+
+// ID of the run that the current process belongs to.
+// This is only set in the sandbox's processes (i.e., not in the caller).
+static CURRENT: OnceLock<String> = OnceLock::new();
+
+// Generates a random ID (formatted as a version 4 UUID).
+pub fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    let n = unsafe { libc::getrandom(bytes.as_mut_ptr() as *mut libc::c_void, bytes.len(), 0) };
+    if n != bytes.len() as isize {
+        panic!(
+            "failed to generate run ID: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+pub fn set_current(id: &str) {
+    let _ = CURRENT.set(id.to_string());
+}
+
+pub fn current() -> Option<&'static str> {
+    CURRENT.get().map(String::as_str)
+}
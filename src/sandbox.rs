@@ -0,0 +1,477 @@
+use crate::config::{Config, Home};
+use crate::handle::Handle;
+use crate::platform::{Native, Platform};
+use crate::teardown::Teardown;
+use crate::{concat_absolute, copy, Error};
+use nix::fcntl::{flock, open, FlockArg, OFlag};
+use nix::sys::stat::Mode;
+use std::ffi::OsString;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+// A validated configuration that is ready to run.
+pub struct Sandbox {
+    pub(crate) cfg: Config,
+    pub(crate) start_fifo: Option<PathBuf>,
+    pub(crate) wait_if_locked: bool,
+}
+
+// Builds a Sandbox for the common cases. Less common features can be configured
+// by constructing a Config and passing it to Sandbox::from_config().
+#[derive(Default)]
+pub struct SandboxBuilder {
+    cfg: Config,
+}
+
+impl SandboxBuilder {
+    pub fn rootfs<P: Into<PathBuf>>(mut self, rootfs: P) -> Self {
+        self.cfg.rootfs = rootfs.into();
+        self
+    }
+
+    // uid and gid of the process inside the sandbox.
+    pub fn user(mut self, uid: libc::uid_t, gid: libc::gid_t) -> Self {
+        self.cfg.user = crate::User { uid, gid };
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Self {
+        self.cfg.process.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn bind_mount<S: Into<PathBuf>, D: Into<PathBuf>>(
+        mut self,
+        source: S,
+        destination: D,
+    ) -> Self {
+        self.cfg.bind_mounts.push(crate::BindMount {
+            source: source.into(),
+            destination: destination.into(),
+            verify_writable: false,
+        });
+        self
+    }
+
+    pub fn artifact<S: Into<PathBuf>, D: Into<PathBuf>>(
+        mut self,
+        source: S,
+        destination: D,
+    ) -> Self {
+        self.cfg.artifacts.push(crate::Artifact {
+            source: source.into(),
+            destination: destination.into(),
+        });
+        self
+    }
+
+    pub fn isolate_network(mut self, isolate: bool) -> Self {
+        self.cfg.isolate_network = isolate;
+        self
+    }
+
+    pub fn rootfs_writable(mut self, writable: bool) -> Self {
+        self.cfg.rootfs_writable = writable;
+        self
+    }
+
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.cfg.reproducible = reproducible;
+        self
+    }
+
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.cfg.hostname = Some(hostname.into());
+        self
+    }
+
+    // Sets HOME; if create is set, an empty home directory is mounted at path.
+    pub fn home<P: Into<PathBuf>>(mut self, path: P, create: bool) -> Self {
+        self.cfg.home = Some(Home {
+            path: path.into(),
+            create,
+        });
+        self
+    }
+
+    pub fn sysctl<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.cfg.sysctls.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Sandbox, Error> {
+        Sandbox::from_config(self.cfg)
+    }
+}
+
+impl Sandbox {
+    pub fn builder() -> SandboxBuilder {
+        SandboxBuilder::default()
+    }
+
+    // Validates the configuration. This does not inspect the host; host-dependent checks
+    // are performed by run().
+    pub fn from_config(cfg: Config) -> Result<Sandbox, Error> {
+        validate(&cfg)?;
+        Ok(Sandbox {
+            cfg,
+            start_fifo: None,
+            wait_if_locked: false,
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    // Delays the execution of the process until the given FIFO is opened for reading
+    // (as done by the start command of OCI runtimes). Event::Ready is reported right
+    // before blocking on the FIFO.
+    pub fn start_gate<P: Into<PathBuf>>(mut self, fifo: P) -> Self {
+        self.start_fifo = Some(fifo.into());
+        self
+    }
+
+    // Read-only runs fail with Error::RootfsLocked while a writable run holds the rootfs.
+    // With this option, they wait until the writable run releases it instead.
+    // Writable runs never wait.
+    pub fn wait_if_locked(mut self) -> Self {
+        self.wait_if_locked = true;
+        self
+    }
+
+    // Runs the process inside the sandbox and returns its exit code.
+    // The namespaces are entered by a forked supervisor process; the calling process
+    // itself is not affected (and may be multi-threaded).
+    pub fn run(&self) -> Result<i32, Error> {
+        Native::spawn(self)?.wait()
+    }
+
+    // Starts the sandbox without waiting for it to terminate.
+    pub fn spawn(&self) -> Result<Handle, Error> {
+        Native::spawn(self)
+    }
+}
+
+pub(crate) fn invalid<S: Into<String>>(msg: S) -> Result<(), Error> {
+    Err(Error::InvalidConfig(msg.into()))
+}
+
+// Whether name can be used as a single path component.
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+fn validate(cfg: &Config) -> Result<(), Error> {
+    if cfg.rootfs.as_os_str().is_empty() {
+        return invalid("rootfs is not set");
+    }
+    match (&cfg.process.script, cfg.process.args.is_empty()) {
+        (Some(_), false) => {
+            return invalid("process.args and process.script are mutually exclusive")
+        }
+        (None, true) => return invalid("process.args must not be empty"),
+        _ => (),
+    }
+    if let Some(dir) = cfg
+        .path_prepend
+        .iter()
+        .chain(&cfg.path_append)
+        .find(|dir| !dir.is_absolute() || dir.to_string_lossy().contains(':'))
+    {
+        return invalid(format!(
+            "{} is not an absolute path that can be added to PATH",
+            dir.display()
+        ));
+    }
+    if cfg.timeout == Some(0) {
+        return invalid("timeout must be positive");
+    }
+    if cfg.init.as_ref().is_some_and(|init| init.is_empty()) {
+        return invalid("init must not be empty");
+    }
+    if let Some(resources) = &cfg.resources {
+        let weights = [
+            (
+                "resources.cpu.weight",
+                resources.cpu.as_ref().and_then(|c| c.weight),
+            ),
+            (
+                "resources.io.weight",
+                resources.io.as_ref().and_then(|io| io.weight),
+            ),
+        ];
+        for (field, weight) in weights.iter() {
+            if weight.is_some_and(|w| !(1..=10000).contains(&w)) {
+                return invalid(format!("{} must be between 1 and 10000", field));
+            }
+        }
+        if resources.cpu.as_ref().and_then(|c| c.period) == Some(0) {
+            return invalid("resources.cpu.period must be positive");
+        }
+        let limited = resources.numa_node.is_some()
+            || resources.memory.is_some()
+            || resources.pids_max.is_some()
+            || resources.cpu.is_some()
+            || resources.io.is_some()
+            || !resources.hugetlb.is_empty();
+        if resources.mount_cgroup && !limited {
+            return invalid("resources.mountCgroup requires a setting that uses a cgroup");
+        }
+    }
+    if let Some(id) = &cfg.run_id {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.';
+        if id.is_empty() || !id.chars().all(valid) {
+            return invalid(format!("{:?} is not a valid run ID", id));
+        }
+    }
+
+    // A process can only be traced by a single tracer.
+    let gdbserver = cfg.debug.as_ref().is_some_and(|d| d.gdbserver.is_some());
+    let tracers = [
+        cfg.access_manifest.is_some(),
+        cfg.trace_syscalls.is_some(),
+        gdbserver,
+    ];
+    if tracers.iter().filter(|t| **t).count() > 1 {
+        return invalid(
+            "file-access tracing, strace and gdbserver cannot be used at the same time",
+        );
+    }
+
+    if cfg.home.as_ref().is_some_and(|h| !h.path.is_absolute()) {
+        return invalid("home.path must be absolute");
+    }
+    if cfg
+        .process
+        .cwd
+        .as_ref()
+        .is_some_and(|cwd| !cwd.is_absolute())
+    {
+        return invalid("process.cwd must be absolute");
+    }
+
+    if let Some(locale) = &cfg.locale {
+        if let Some(key) = locale.categories.keys().find(|k| !k.starts_with("LC_")) {
+            return invalid(format!("{} is not a locale category", key));
+        }
+    }
+
+    if let Some(p) = cfg
+        .preload
+        .iter()
+        .find(|p| !p.path.is_absolute() || p.path.file_name().is_none())
+    {
+        return invalid(format!(
+            "{} is not an absolute path to a shared object",
+            p.path.display()
+        ));
+    }
+
+    for name in cfg.secrets.keys() {
+        if !is_file_name(name) {
+            return invalid(format!("{:?} is not a valid name for a secret", name));
+        }
+    }
+    // The names become directories below /run/xbstrap.
+    let named_mounts = [
+        ("toolMounts", &cfg.tool_mounts),
+        ("sourceMounts", &cfg.source_mounts),
+        ("sysrootMounts", &cfg.sysroot_mounts),
+    ];
+    for (field, mounts) in named_mounts.iter() {
+        if let Some(name) = mounts.keys().find(|name| !is_file_name(name)) {
+            return invalid(format!("{:?} is not a valid name for {}", name, field));
+        }
+    }
+
+    if cfg.isolate_network && cfg.distcc.is_some() {
+        return invalid("distcc cannot be used together with isolateNetwork");
+    }
+    if cfg.reproducible && cfg.distcc.is_some() {
+        return invalid(
+            "distcc cannot be used for reproducible builds since it requires network access",
+        );
+    }
+
+    if cfg.download_cache.is_some() {
+        if cfg.distcc.is_some() {
+            return invalid("downloadCache cannot be used together with distcc");
+        }
+        if cfg.proxy.is_some() {
+            return invalid("proxy and downloadCache are mutually exclusive");
+        }
+    }
+
+    if let Some(sc) = &cfg.sccache {
+        if sc.server_port.is_some() && cfg.network_isolated() {
+            return invalid("sccache.serverPort cannot be used together with isolateNetwork");
+        }
+    }
+
+    // Settings that depend on the platform are checked by its backend.
+    Native::check_config(cfg)
+}
+
+// Copies the artifacts out of the sandbox. Returns false if any artifact could not be copied.
+pub(crate) fn copy_artifacts(cfg: &Config) -> bool {
+    let mut success = true;
+    for artifact in &cfg.artifacts {
+        let source = concat_absolute(&cfg.rootfs, &artifact.source);
+        if let Some(parent) = artifact.destination.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                log!("failed to create {}: {}", parent.display(), e);
+                success = false;
+                continue;
+            }
+        }
+        match copy::copy_tree(&source, &artifact.destination, false) {
+            Ok(n) => log!(
+                "copied artifact {} to {} ({} files)",
+                artifact.source.display(),
+                artifact.destination.display(),
+                n
+            ),
+            Err(e) => {
+                log!(
+                    "failed to copy artifact {} to {}: {}",
+                    artifact.source.display(),
+                    artifact.destination.display(),
+                    e
+                );
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+// The FIFO of Sandbox::start_gate. It is a host path, hence its directory is opened
+// before entering the rootfs and the FIFO itself is opened relative to it right before
+// the process is executed.
+pub(crate) struct StartGate {
+    fifo: PathBuf,
+    dir: std::fs::File,
+}
+
+impl StartGate {
+    pub(crate) fn new(fifo: &Path) -> Result<StartGate, Error> {
+        let dir = match fifo.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        Ok(StartGate {
+            fifo: fifo.to_path_buf(),
+            dir: std::fs::File::open(dir)
+                .map_err(crate::setup_error(format!("open {}", dir.display())))?,
+        })
+    }
+
+    // Blocks until another process opens the FIFO for reading.
+    pub(crate) fn wait(self) -> Result<(), Error> {
+        let name = self.fifo.file_name().unwrap_or_default();
+        let fd = nix::fcntl::openat(
+            self.dir.as_raw_fd(),
+            name,
+            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map_err(crate::setup_error(format!("open {}", self.fifo.display())))?;
+        let mut f = unsafe { std::fs::File::from_raw_fd(fd) };
+        f.write_all(b"0").map_err(crate::setup_error(format!(
+            "write to {}",
+            self.fifo.display()
+        )))
+    }
+}
+
+// Returns the default PATH of the process (including pathPrepend and pathAppend).
+pub(crate) fn default_path(cfg: &Config) -> OsString {
+    let default = if cfg.user.uid == 0 {
+        "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+    } else {
+        "/usr/local/bin:/usr/bin:/bin"
+    };
+    let mut dirs = cfg.path_prepend.clone();
+    dirs.extend(std::env::split_paths(default));
+    dirs.extend(cfg.path_append.iter().cloned());
+    std::env::join_paths(dirs).unwrap()
+}
+
+// Sets up the resources that all backends need for a run: it creates the work directory of
+// the run and locks the rootfs. Both are released by the returned Teardown, which backends
+// extend with their own resources.
+pub(crate) fn prepare(sandbox: &Sandbox, run_id: &str) -> Result<Teardown, Error> {
+    let cfg = &sandbox.cfg;
+    let mut teardown = Teardown::new(run_id);
+
+    let lockfile_path = cfg
+        .rootfs
+        .parent()
+        .and_then(|p| Some(p.join(cfg.rootfs.file_name()?)))
+        .map(|p| p.with_extension("cbrt_lock"))
+        .ok_or_else(|| Error::Rootfs {
+            rootfs: cfg.rootfs.clone(),
+            reason: "does not have a parent directory for the lock file".to_string(),
+        })?;
+
+    let root_dir = open(
+        &lockfile_path,
+        OFlag::O_RDONLY | OFlag::O_CREAT | OFlag::O_CLOEXEC,
+        Mode::from_bits(0o444).unwrap(),
+    )
+    .map_err(|e| Error::Rootfs {
+        rootfs: cfg.rootfs.clone(),
+        reason: format!("cannot be locked: {}: {}", lockfile_path.display(), e),
+    })?;
+    teardown.defer("rootfs lock", move || {
+        nix::unistd::close(root_dir).map_err(std::io::Error::other)
+    });
+
+    if let Some(run_dir) = cfg.run_dir(run_id) {
+        std::fs::create_dir_all(&run_dir).map_err(|e| {
+            Error::Setup(format!(
+                "failed to create work directory {}: {}",
+                run_dir.display(),
+                e
+            ))
+        })?;
+        if !cfg.keep_work_dir {
+            teardown.defer(format!("work directory {}", run_dir.display()), move || {
+                std::fs::remove_dir_all(&run_dir)
+            });
+        }
+    }
+
+    // Read-only runs can share the rootfs, but writable runs need exclusive access.
+    // Runs fail immediately on a conflicting run, except for read-only runs with
+    // wait_if_locked, which wait for the writable run to finish.
+    let lock_arg = if cfg.rootfs_writable {
+        FlockArg::LockExclusiveNonblock
+    } else {
+        FlockArg::LockSharedNonblock
+    };
+    let mut result = flock(root_dir, lock_arg);
+    if result == Err(nix::Error::Sys(nix::errno::Errno::EAGAIN))
+        && !cfg.rootfs_writable
+        && sandbox.wait_if_locked
+    {
+        log!(
+            "rootfs {} is locked for writing by another cbuildrt instance, waiting",
+            cfg.rootfs.display()
+        );
+        result = flock(root_dir, FlockArg::LockShared);
+    }
+    match result {
+        Ok(()) => Ok(teardown),
+        Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => Err(Error::RootfsLocked {
+            rootfs: cfg.rootfs.clone(),
+            writable: cfg.rootfs_writable,
+        }),
+        Err(e) => Err(Error::Rootfs {
+            rootfs: cfg.rootfs.clone(),
+            reason: format!("cannot be locked: {}: {}", lockfile_path.display(), e),
+        }),
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::Path;
+
+// Location of the sccache server socket inside the sandbox.
+pub const SANDBOX_SOCKET: &str = "/run/sccache/server.sock";
+
+// Bind mounts the host's sccache server socket to SANDBOX_SOCKET.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, host_socket: &Path) -> Result<(), crate::Error> {
+    crate::bind_into_sandbox(rootfs, host_socket, SANDBOX_SOCKET, false)
+}
+
+// Returns the environment variables that point sccache inside the sandbox to the server.
+pub fn environment(cfg: &crate::Sccache) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    if cfg.server_socket.is_some() {
+        env.push(("SCCACHE_SERVER_UDS".to_string(), SANDBOX_SOCKET.to_string()));
+    }
+    if let Some(port) = cfg.server_port {
+        env.push(("SCCACHE_SERVER_PORT".to_string(), port.to_string()));
+    }
+    if cfg.rustc_wrapper {
+        env.push(("RUSTC_WRAPPER".to_string(), "sccache".to_string()));
+    }
+    for (key, value) in &cfg.environ {
+        env.push((key.clone(), value.clone()));
+    }
+    env
+}
@@ -0,0 +1,21 @@
+use std::path::Path;
+
+// Location of process.script inside the sandbox.
+const SANDBOX_PATH: &str = "/run/cbuildrt/script";
+
+// Writes the script into the sandbox. Must be called after /run has been mounted.
+pub fn write(rootfs: &Path, script: &str) -> Result<(), crate::Error> {
+    std::fs::create_dir_all(crate::concat_absolute(rootfs, "/run/cbuildrt"))
+        .map_err(crate::setup_error("create /run/cbuildrt"))?;
+    std::fs::write(crate::concat_absolute(rootfs, SANDBOX_PATH), script)
+        .map_err(crate::setup_error("write process.script"))
+}
+
+// Command line that executes the script. With -e, the script fails as soon as a command fails.
+pub fn args() -> Vec<String> {
+    vec![
+        "/bin/sh".to_string(),
+        "-e".to_string(),
+        SANDBOX_PATH.to_string(),
+    ]
+}
@@ -0,0 +1,183 @@
+use crate::{Error, Secret};
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+// Location of the secrets inside the sandbox.
+const SANDBOX_DIR: &str = "/run/cbuildrt/secrets";
+
+// Lines of secrets that are shorter than this are not scrubbed individually
+// (they would match too many unrelated strings, e.g., PEM delimiters).
+const MIN_SCRUB_LEN: usize = 8;
+
+// Copies the secrets to a private tmpfs, which is remounted read-only afterwards.
+// In contrast to bind mounts, this does not expose the permissions of the host files.
+// Must be called after /run has been mounted.
+pub fn mount(rootfs: &Path, secrets: &BTreeMap<String, Secret>) -> Result<(), Error> {
+    let dir = crate::concat_absolute(rootfs, SANDBOX_DIR);
+    std::fs::create_dir_all(&dir).map_err(crate::setup_error("create /run/cbuildrt/secrets"))?;
+    let flags = nix::mount::MsFlags::MS_NOSUID
+        | nix::mount::MsFlags::MS_NODEV
+        | nix::mount::MsFlags::MS_NOEXEC;
+    nix::mount::mount(None::<&str>, &dir, Some("tmpfs"), flags, Some("mode=0500"))
+        .map_err(crate::setup_error("mount /run/cbuildrt/secrets"))?;
+    // The tmpfs is still writable at this point since init owns it.
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))
+        .map_err(crate::setup_error("make /run/cbuildrt/secrets writable"))?;
+    for (name, secret) in secrets {
+        let contents = std::fs::read(&secret.source)
+            .map_err(crate::setup_error(format!("read secret {}", name)))?;
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .and_then(|_| std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o400)))
+            .map_err(crate::setup_error(format!("store secret {}", name)))?;
+    }
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o500))
+        .map_err(crate::setup_error("restrict /run/cbuildrt/secrets"))?;
+    nix::mount::mount(
+        None::<&str>,
+        &dir,
+        None::<&str>,
+        nix::mount::MsFlags::MS_REMOUNT | nix::mount::MsFlags::MS_RDONLY | flags,
+        None::<&str>,
+    )
+    .map_err(crate::setup_error("make /run/cbuildrt/secrets read-only"))
+}
+
+// Returns the variables that point to the secrets inside the sandbox.
+pub fn environment(secrets: &BTreeMap<String, Secret>) -> Vec<(String, String)> {
+    secrets
+        .iter()
+        .filter_map(|(name, secret)| {
+            let env = secret.env.as_ref()?;
+            Some((env.clone(), format!("{}/{}", SANDBOX_DIR, name)))
+        })
+        .collect()
+}
+
+// Removes the values of secrets from logs and error messages.
+// Since the values are matched literally, transformed (e.g., escaped or truncated)
+// occurrences are not removed.
+pub struct Scrubber {
+    values: Vec<Vec<u8>>,
+}
+
+impl Scrubber {
+    // Secrets that cannot be read are skipped (mount() reports them).
+    pub fn new(secrets: &BTreeMap<String, Secret>) -> Scrubber {
+        Scrubber::from_contents(
+            secrets
+                .values()
+                .filter_map(|secret| std::fs::read(&secret.source).ok()),
+        )
+    }
+
+    fn from_contents<I: IntoIterator<Item = Vec<u8>>>(secrets: I) -> Scrubber {
+        let mut values = Vec::new();
+        for contents in secrets {
+            for line in contents.split(|b| *b == b'\n') {
+                if line.len() >= MIN_SCRUB_LEN {
+                    values.push(line.to_vec());
+                }
+            }
+            values.push(contents);
+        }
+        // Replace longer values first, such that they are not split up by shorter ones.
+        // Blank values (e.g., an empty file that ends with a newline) would mangle the text.
+        values.retain(|v| v.iter().any(|b| !b.is_ascii_whitespace()));
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+        values.dedup();
+        Scrubber { values }
+    }
+
+    pub fn scrub(&self, text: &[u8]) -> Vec<u8> {
+        let mut text = text.to_vec();
+        for value in &self.values {
+            let mut scrubbed = Vec::with_capacity(text.len());
+            let mut rest = &text[..];
+            while let Some(pos) = rest.windows(value.len()).position(|w| w == &value[..]) {
+                scrubbed.extend_from_slice(&rest[..pos]);
+                scrubbed.extend_from_slice(b"***");
+                rest = &rest[pos + value.len()..];
+            }
+            scrubbed.extend_from_slice(rest);
+            text = scrubbed;
+        }
+        text
+    }
+
+    fn scrub_str(&self, text: &str) -> String {
+        String::from_utf8_lossy(&self.scrub(text.as_bytes())).into_owned()
+    }
+
+    pub fn scrub_file(&self, path: &Path) -> std::io::Result<()> {
+        let contents = std::fs::read(path)?;
+        std::fs::write(path, self.scrub(&contents))
+    }
+
+    pub fn scrub_error(&self, e: Error) -> Error {
+        match e {
+            Error::InvalidConfig(msg) => Error::InvalidConfig(self.scrub_str(&msg)),
+            Error::Unsupported(msg) => Error::Unsupported(self.scrub_str(&msg)),
+            Error::Setup(msg) => Error::Setup(self.scrub_str(&msg)),
+            Error::Rootfs { rootfs, reason } => Error::Rootfs {
+                rootfs,
+                reason: self.scrub_str(&reason),
+            },
+            Error::Exec { program, reason } => Error::Exec {
+                program: self.scrub_str(&program),
+                reason: self.scrub_str(&reason),
+            },
+            e @ Error::RootfsLocked { .. }
+            | e @ Error::ResourceLimit { .. }
+            | e @ Error::MountNotWritable { .. } => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrubber(secrets: &[&str]) -> Scrubber {
+        Scrubber::from_contents(secrets.iter().map(|s| s.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn scrub_repeated() {
+        let s = scrubber(&["hunter2hunter2"]);
+        assert_eq!(
+            s.scrub(b"a hunter2hunter2 b hunter2hunter2"),
+            b"a *** b ***".to_vec()
+        );
+    }
+
+    #[test]
+    fn scrub_overlapping() {
+        // The longer secret contains the shorter one.
+        let s = scrubber(&["password", "password123"]);
+        assert_eq!(s.scrub(b"password123 password"), b"*** ***".to_vec());
+        // Occurrences of the same secret that overlap are replaced from left to right.
+        let s = scrubber(&["abababab"]);
+        assert_eq!(s.scrub(b"ababababab"), b"***ab".to_vec());
+    }
+
+    #[test]
+    fn scrub_lines() {
+        let s = scrubber(&["-----BEGIN-----\nverysecretkey\nshort\n"]);
+        assert_eq!(
+            s.scrub(b"got verysecretkey and short"),
+            b"got *** and short".to_vec()
+        );
+    }
+
+    #[test]
+    fn scrub_empty() {
+        let s = scrubber(&["", "\n\n"]);
+        assert_eq!(
+            s.scrub(b"nothing to hide\n\n"),
+            b"nothing to hide\n\n".to_vec()
+        );
+        assert_eq!(scrubber(&["secretvalue"]).scrub(b""), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,36 @@
+use std::path::Path;
+
+// Location of the trace inside the sandbox; the host's output file is bind mounted here.
+const SANDBOX_OUTPUT: &str = "/run/cbuildrt/strace.log";
+
+// Location of the host's strace inside the sandbox (if the rootfs does not provide one).
+const SANDBOX_BINARY: &str = "/run/cbuildrt/strace";
+
+// Makes strace and the output file available inside the sandbox.
+// Returns the path of strace inside the sandbox. Must be called after /run has been mounted.
+pub fn setup(rootfs: &Path, cfg: &crate::SyscallTrace) -> Result<String, crate::Error> {
+    std::fs::File::create(&cfg.output).map_err(crate::setup_error(format!(
+        "create {}",
+        cfg.output.display()
+    )))?;
+    crate::bind_into_sandbox(rootfs, &cfg.output, SANDBOX_OUTPUT, false)?;
+
+    crate::hosttool::provide(rootfs, "strace", cfg.strace.as_deref(), SANDBOX_BINARY)
+}
+
+// Returns the command line that runs args under strace.
+pub fn wrap(binary: String, cfg: &crate::SyscallTrace, args: &[String]) -> Vec<String> {
+    let mut wrapped = vec![
+        binary,
+        "-f".to_string(),
+        "-o".to_string(),
+        SANDBOX_OUTPUT.to_string(),
+    ];
+    if let Some(filter) = &cfg.filter {
+        wrapped.push("-e".to_string());
+        wrapped.push(filter.clone());
+    }
+    wrapped.push("--".to_string());
+    wrapped.extend(args.iter().cloned());
+    wrapped
+}
@@ -0,0 +1,45 @@
+use nix::unistd::{getpid, Pid};
+
+// Actions are Send such that Handle (which owns the guard) can be moved between threads.
+type Action = Box<dyn FnOnce() -> std::io::Result<()> + Send>;
+
+// Stack of cleanup actions that are run (in reverse order) when the guard is dropped.
+// This ensures that resources created during setup are released even if a later step fails.
+pub struct Teardown {
+    // Forked children inherit a copy of the guard; only the process that created it may clean up.
+    owner: Pid,
+    run_id: String,
+    actions: Vec<(String, Action)>,
+}
+
+impl Teardown {
+    pub fn new(run_id: &str) -> Teardown {
+        Teardown {
+            owner: getpid(),
+            run_id: run_id.to_string(),
+            actions: Vec::new(),
+        }
+    }
+
+    // Registers an action that releases the resource described by what.
+    pub fn defer<S, F>(&mut self, what: S, action: F)
+    where
+        S: Into<String>,
+        F: FnOnce() -> std::io::Result<()> + Send + 'static,
+    {
+        self.actions.push((what.into(), Box::new(action)));
+    }
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        if getpid() != self.owner {
+            return;
+        }
+        while let Some((what, action)) = self.actions.pop() {
+            if let Err(e) = action() {
+                eprintln!("[{}] failed to clean up {}: {}", self.run_id, what, e);
+            }
+        }
+    }
+}
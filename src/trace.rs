@@ -0,0 +1,222 @@
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+// Whether file-access tracing is available on this architecture.
+pub const SUPPORTED: bool = cfg!(target_arch = "x86_64");
+
+// Directories of the sandbox that are not backed by the host (i.e., tmpfs and special mounts).
+const EPHEMERAL_DIRS: &[&str] = &["/dev", "/proc", "/run", "/tmp"];
+
+// List of host files that were accessed by the build.
+#[derive(Serialize, Default)]
+struct Manifest {
+    reads: BTreeSet<PathBuf>,
+    writes: BTreeSet<PathBuf>,
+    executed: BTreeSet<PathBuf>,
+}
+
+#[derive(Default)]
+struct ProcessState {
+    // ptrace() does not tell us whether a syscall-stop is an entry or an exit.
+    in_syscall: bool,
+    // Set on entry to open()-like syscalls; true if the file is opened for writing.
+    pending_open: Option<bool>,
+}
+
+pub struct Tracer {
+    rootfs: PathBuf,
+    // init is PID 1 of the sandbox's PID namespace, but it has not chrooted and its /proc
+    // belongs to the host's PID namespace. PIDs of tracees refer to the sandbox's namespace,
+    // hence we need to use the sandbox's procfs.
+    proc_dir: PathBuf,
+    // Pairs of (path inside the sandbox, path on the host), longest sandbox path first.
+    mounts: Vec<(PathBuf, PathBuf)>,
+    processes: HashMap<Pid, ProcessState>,
+    manifest: Manifest,
+}
+
+// Determines whether a syscall opens a file. Returns Some(true) for opens that may write.
+#[cfg(target_arch = "x86_64")]
+fn open_access(pid: Pid) -> Option<bool> {
+    let regs = ptrace::getregs(pid).ok()?;
+    let flags = match regs.orig_rax {
+        2 => regs.rsi as libc::c_int,   // open()
+        257 => regs.rdx as libc::c_int, // openat()
+        85 => return Some(true),        // creat()
+        // openat2() passes the flags as the first member of struct open_how.
+        437 => ptrace::read(pid, regs.rdx as ptrace::AddressType).ok()? as libc::c_int,
+        _ => return None,
+    };
+    Some(flags & libc::O_ACCMODE != libc::O_RDONLY || flags & (libc::O_CREAT | libc::O_TRUNC) != 0)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn syscall_result(pid: Pid) -> Option<i64> {
+    ptrace::getregs(pid).ok().map(|regs| regs.rax as i64)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn open_access(_pid: Pid) -> Option<bool> {
+    None
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn syscall_result(_pid: Pid) -> Option<i64> {
+    None
+}
+
+impl Tracer {
+    pub fn new(rootfs: &Path, mut mounts: Vec<(PathBuf, PathBuf)>) -> Tracer {
+        // readlink() on /proc returns canonical paths.
+        let canonicalize = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        for (_, host) in mounts.iter_mut() {
+            *host = canonicalize(host);
+        }
+        mounts.sort_by_key(|(sandbox, _)| std::cmp::Reverse(sandbox.as_os_str().len()));
+        Tracer {
+            rootfs: canonicalize(rootfs),
+            proc_dir: crate::concat_absolute(rootfs, "/proc"),
+            mounts,
+            processes: HashMap::new(),
+            manifest: Manifest::default(),
+        }
+    }
+
+    // Maps a path (as seen by init) to the host file that backs it.
+    // Returns None for files that live on ephemeral mounts or outside of the sandbox.
+    fn host_path(&self, path: &Path) -> Option<PathBuf> {
+        let sandbox_path = Path::new("/").join(path.strip_prefix(&self.rootfs).ok()?);
+        for (sandbox, host) in &self.mounts {
+            if let Ok(rest) = sandbox_path.strip_prefix(sandbox) {
+                // Avoid a trailing slash if a file is bind mounted.
+                if rest.as_os_str().is_empty() {
+                    return Some(host.clone());
+                }
+                return Some(host.join(rest));
+            }
+        }
+        if EPHEMERAL_DIRS.iter().any(|d| sandbox_path.starts_with(d)) {
+            return None;
+        }
+        Some(path.to_path_buf())
+    }
+
+    fn record_fd(&mut self, pid: Pid, fd: i64, write: bool) {
+        let link = match std::fs::read_link(self.proc_dir.join(format!("{}/fd/{}", pid, fd))) {
+            Ok(link) => link,
+            Err(_) => return,
+        };
+        // Ignore directories, devices, pipes, etc.
+        if !std::fs::metadata(&link)
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+        {
+            return;
+        }
+        if let Some(host) = self.host_path(&link) {
+            if write {
+                self.manifest.writes.insert(host);
+            } else {
+                self.manifest.reads.insert(host);
+            }
+        }
+    }
+
+    fn record_exec(&mut self, pid: Pid) {
+        let exe = match std::fs::read_link(self.proc_dir.join(format!("{}/exe", pid))) {
+            Ok(exe) => exe,
+            Err(_) => return,
+        };
+        if let Some(host) = self.host_path(&exe) {
+            self.manifest.reads.insert(host.clone());
+            self.manifest.executed.insert(host);
+        }
+    }
+
+    fn handle_syscall(&mut self, pid: Pid) {
+        let state = self.processes.entry(pid).or_default();
+        if !state.in_syscall {
+            state.in_syscall = true;
+            state.pending_open = open_access(pid);
+            return;
+        }
+        state.in_syscall = false;
+        if let Some(write) = state.pending_open.take() {
+            match syscall_result(pid) {
+                Some(fd) if fd >= 0 => self.record_fd(pid, fd, write),
+                _ => (),
+            }
+        }
+    }
+
+    // Traces the child (and all of its descendants) until it terminates.
+    // The child must have called PTRACE_TRACEME and stopped itself via SIGSTOP.
+    // Returns the exit code of the child.
+    pub fn run(&mut self, child: Pid) -> i32 {
+        waitpid(child, None).expect("failed to wait for traced child");
+        ptrace::setoptions(
+            child,
+            ptrace::Options::PTRACE_O_TRACESYSGOOD
+                | ptrace::Options::PTRACE_O_TRACEFORK
+                | ptrace::Options::PTRACE_O_TRACEVFORK
+                | ptrace::Options::PTRACE_O_TRACECLONE
+                | ptrace::Options::PTRACE_O_TRACEEXEC
+                | ptrace::Options::PTRACE_O_EXITKILL,
+        )
+        .expect("failed to set ptrace options");
+        self.processes.insert(child, ProcessState::default());
+        ptrace::syscall(child, None).expect("failed to resume traced child");
+
+        loop {
+            let status =
+                waitpid(None, Some(WaitPidFlag::__WALL)).expect("failed to wait for children");
+            // Resuming may fail if the process was killed in the meantime; ignore that.
+            match status {
+                WaitStatus::Exited(pid, code) => {
+                    self.processes.remove(&pid);
+                    if pid == child {
+                        return code;
+                    }
+                }
+                WaitStatus::Signaled(pid, signal, _) => {
+                    self.processes.remove(&pid);
+                    if pid == child {
+                        return 128 + signal as i32;
+                    }
+                }
+                WaitStatus::PtraceSyscall(pid) => {
+                    self.handle_syscall(pid);
+                    let _ = ptrace::syscall(pid, None);
+                }
+                WaitStatus::PtraceEvent(pid, _, event) => {
+                    if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 {
+                        self.record_exec(pid);
+                    }
+                    let _ = ptrace::syscall(pid, None);
+                }
+                WaitStatus::Stopped(pid, signal) => {
+                    // New processes start with a SIGSTOP that must not be delivered.
+                    if signal == Signal::SIGSTOP && !self.processes.contains_key(&pid) {
+                        self.processes.insert(pid, ProcessState::default());
+                        let _ = ptrace::syscall(pid, None);
+                    } else {
+                        let _ = ptrace::syscall(pid, signal);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Writes the manifest as JSON to the given host path.
+    pub fn write_manifest(&self, path: &Path) -> std::io::Result<()> {
+        let f = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(f, &self.manifest)?;
+        Ok(())
+    }
+}
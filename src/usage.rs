@@ -0,0 +1,65 @@
+// Measures the disk space that a run consumed in the writable layers of the sandbox.
+// Must be called by init after the process has exited, while the tmpfs mounts still exist.
+
+use crate::handle::{DiskUsage, LayerKind};
+use crate::Config;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Space that is allocated on a mounted file system.
+fn used_space(path: &Path) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(path).ok()?;
+    Some((stat.blocks() - stat.blocks_free()) as u64 * stat.fragment_size() as u64)
+}
+
+// Space that is allocated by the files below a directory. Does not follow symlinks.
+fn tree_space(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut bytes = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            bytes += tree_space(&entry?.path())?;
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn measure(cfg: &Config, run_dir: Option<&Path>) -> Vec<DiskUsage> {
+    let mut tmpfs: Vec<PathBuf> = ["/dev/shm", "/run", "/tmp"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(home) = cfg.home.as_ref().filter(|h| h.create) {
+        tmpfs.push(home.path.clone());
+    }
+
+    let mut usage = Vec::new();
+    for path in tmpfs {
+        match used_space(&crate::concat_absolute(&cfg.rootfs, &path)) {
+            Some(bytes) => usage.push(DiskUsage {
+                path,
+                kind: LayerKind::Tmpfs,
+                bytes,
+            }),
+            None => log!(
+                "warning: unable to measure disk usage of {}",
+                path.display()
+            ),
+        }
+    }
+    if let Some(dir) = run_dir {
+        match tree_space(dir) {
+            Ok(bytes) => usage.push(DiskUsage {
+                path: dir.to_path_buf(),
+                kind: LayerKind::WorkDir,
+                bytes,
+            }),
+            Err(e) => log!(
+                "warning: unable to measure disk usage of {}: {}",
+                dir.display(),
+                e
+            ),
+        }
+    }
+    usage
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Standard locations of xbstrap's directories inside the sandbox.
+const TOOLS_DIR: &str = "/run/xbstrap/tools";
+const SOURCES_DIR: &str = "/run/xbstrap/sources";
+const SYSROOTS_DIR: &str = "/run/xbstrap/sysroots";
+
+// Kinds of directories that xbstrap mounts into the sandbox.
+// Each kind determines the location inside the sandbox and the name of the environment variables.
+struct Kind {
+    dir: &'static str,
+    env_prefix: &'static str,
+}
+
+const TOOL: Kind = Kind {
+    dir: TOOLS_DIR,
+    env_prefix: "XBSTRAP_TOOL_",
+};
+const SOURCE: Kind = Kind {
+    dir: SOURCES_DIR,
+    env_prefix: "XBSTRAP_SOURCE_",
+};
+const SYSROOT: Kind = Kind {
+    dir: SYSROOTS_DIR,
+    env_prefix: "XBSTRAP_SYSROOT_",
+};
+
+fn kinds(cfg: &crate::Config) -> Vec<(&Kind, &BTreeMap<String, crate::NamedMount>)> {
+    vec![
+        (&TOOL, &cfg.tool_mounts),
+        (&SOURCE, &cfg.source_mounts),
+        (&SYSROOT, &cfg.sysroot_mounts),
+    ]
+}
+
+fn sandbox_path(kind: &Kind, name: &str) -> PathBuf {
+    Path::new(kind.dir).join(name)
+}
+
+// Converts a name like "host-gcc" into an environment variable like "XBSTRAP_TOOL_HOST_GCC".
+fn env_name(kind: &Kind, name: &str) -> String {
+    let suffix: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}{}", kind.env_prefix, suffix)
+}
+
+// Returns pairs of (path inside the sandbox, path on the host) for all mounts.
+pub fn mounts(cfg: &crate::Config) -> Vec<(PathBuf, PathBuf)> {
+    let mut result = Vec::new();
+    for (kind, mounts) in kinds(cfg) {
+        for (name, nm) in mounts {
+            result.push((sandbox_path(kind, name), nm.source.clone()));
+        }
+    }
+    result
+}
+
+// Like mounts(), but only returns the writable mounts.
+pub fn writable_mounts(cfg: &crate::Config) -> Vec<(PathBuf, PathBuf)> {
+    let mut result = Vec::new();
+    for (kind, mounts) in kinds(cfg) {
+        for (name, nm) in mounts.iter().filter(|(_, nm)| nm.writable) {
+            result.push((sandbox_path(kind, name), nm.source.clone()));
+        }
+    }
+    result
+}
+
+// Performs the tool, source and sysroot mounts.
+// Must be called after /run has been mounted.
+pub fn mount(cfg: &crate::Config) -> Result<(), crate::Error> {
+    for (kind, mounts) in kinds(cfg) {
+        for (name, nm) in mounts {
+            crate::bind_into_sandbox(
+                &cfg.rootfs,
+                &nm.source,
+                sandbox_path(kind, name),
+                !nm.writable,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Returns variables that point to the mounted directories.
+pub fn environment(cfg: &crate::Config) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for (kind, mounts) in kinds(cfg) {
+        for name in mounts.keys() {
+            env.push((
+                env_name(kind, name),
+                sandbox_path(kind, name).to_string_lossy().into_owned(),
+            ));
+        }
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_names() {
+        assert_eq!(env_name(&TOOL, "host-gcc"), "XBSTRAP_TOOL_HOST_GCC");
+        assert_eq!(env_name(&SOURCE, "glib2.0"), "XBSTRAP_SOURCE_GLIB2_0");
+        assert_eq!(env_name(&SYSROOT, "system"), "XBSTRAP_SYSROOT_SYSTEM");
+    }
+}
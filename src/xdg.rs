@@ -0,0 +1,41 @@
+use crate::Error;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+// Locations of the XDG base directories inside the sandbox.
+// These are on the /run tmpfs, hence they are private to the sandbox.
+pub const CACHE_HOME: &str = "/run/cbuildrt/xdg/cache";
+pub const CONFIG_HOME: &str = "/run/cbuildrt/xdg/config";
+
+pub fn runtime_dir(uid: libc::uid_t) -> String {
+    format!("/run/user/{}", uid)
+}
+
+// init runs with the IDs of the sandbox user, hence the directory is owned by that user.
+fn create_private_dir(rootfs: &Path, dir: &str) -> Result<(), Error> {
+    let path = crate::concat_absolute(rootfs, dir);
+    std::fs::create_dir_all(&path)
+        .and_then(|_| std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)))
+        .map_err(crate::setup_error(format!("create {}", dir)))
+}
+
+// Creates XDG_RUNTIME_DIR. Must be called after /run has been mounted.
+pub fn create_runtime_dir(rootfs: &Path, uid: libc::uid_t) -> Result<(), Error> {
+    create_private_dir(rootfs, &runtime_dir(uid))
+}
+
+// Creates the XDG base directories. Must be called after /run has been mounted.
+pub fn create_dirs(rootfs: &Path, uid: libc::uid_t) -> Result<(), Error> {
+    create_private_dir(rootfs, CACHE_HOME)?;
+    create_private_dir(rootfs, CONFIG_HOME)?;
+    create_runtime_dir(rootfs, uid)
+}
+
+// Returns the environment variables that point to the XDG base directories.
+pub fn environment(uid: libc::uid_t) -> Vec<(String, String)> {
+    vec![
+        ("XDG_CACHE_HOME".to_string(), CACHE_HOME.to_string()),
+        ("XDG_CONFIG_HOME".to_string(), CONFIG_HOME.to_string()),
+        ("XDG_RUNTIME_DIR".to_string(), runtime_dir(uid)),
+    ]
+}